@@ -126,6 +126,7 @@ copy /Y /B "!DLL!" "dist\\" >nul
 if exist "target\\release\\moeqi_ffi.lib" copy /Y /B "target\\release\\moeqi_ffi.lib" "dist\\" >nul
 if exist "target\\release\\moeqi_ffi.pdb" copy /Y /B "target\\release\\moeqi_ffi.pdb" "dist\\" >nul
 if exist "target\\release\\moeqi_ffi.dll.a" copy /Y /B "target\\release\\moeqi_ffi.dll.a" "dist\\" >nul
+copy /Y "moeqi-ffi\\include\\moeqi.h" "dist\\" >nul
 exit /b 0
 
 :cmd_wasm
@@ -1,18 +1,120 @@
 /* tslint:disable */
 /* eslint-disable */
 
+/**
+ * A decoded image, pixels kept in wasm memory.
+ */
 export class Decoded {
     private constructor();
     free(): void;
     [Symbol.dispose](): void;
+    /**
+     * A copy as `Rgba8`, ready for canvas: gray is replicated, alpha is
+     * opaque where missing and 16-bit samples keep their top 8 significant
+     * bits ([`Decoded::bit_depth`]).
+     */
+    toRgba(): Decoded;
+    /**
+     * Significant bits per sample: 8, or 9..16 for the 16-bit formats.
+     */
+    readonly bitDepth: number;
+    /**
+     * [`Decoded::pixels`] as a `Uint8ClampedArray`, for `new ImageData(..)`.
+     * Only `Rgba8` images; see [`Decoded::to_rgba`].
+     */
+    readonly clampedPixels: Uint8ClampedArray;
+    /**
+     * `"Gray8"`, `"Rgb8"`, `"Rgba8"`, `"Gray16"`, `"Rgb16"` or `"Rgba16"`.
+     */
+    readonly format: string;
+    readonly height: number;
+    /**
+     * View of the pixels (row-major, 16-bit samples little-endian) without
+     * a copy. Only valid until this `Decoded` is freed or wasm memory grows;
+     * copy it with `slice()` to keep it.
+     */
+    readonly pixels: Uint8Array;
+    readonly width: number;
 }
 
+/**
+ * Header fields of a `MOEQIBIN` file, for debugging.
+ */
+export class MqbHeader {
+    private constructor();
+    free(): void;
+    [Symbol.dispose](): void;
+    /**
+     * Residual coder: `"Varint"` or `"Huff"`.
+     */
+    readonly codec: string;
+    /**
+     * Number of experts `E` in the model.
+     */
+    readonly experts: number;
+    readonly height: number;
+    /**
+     * Size of the coded residuals.
+     */
+    readonly payloadBytes: number;
+    /**
+     * Residual quantization step; 1 is lossless.
+     */
+    readonly qstep: number;
+    readonly width: number;
+}
+
+/**
+ * Decode a `MOEQI2` or legacy `MOEQI1` file.
+ */
+export function decode(bytes: Uint8Array): Decoded;
+
+/**
+ * Decode a `MOEQIBIN` file: `Gray8`, or `Rgba8` for canvas when `rgba` is
+ * true.
+ */
+export function decodeMqb(bytes: Uint8Array, rgba?: boolean | null): Decoded;
+
+/**
+ * Encode raw pixels into a `MOEQI2` file. `format` is one of the
+ * [`Decoded::format`] names; `cfg_json` a JSON `CodecConfig`, or
+ * `undefined` for the default.
+ */
+export function encode(pixels: Uint8Array, width: number, height: number, format: string, cfg_json?: string | null): Uint8Array;
+
+/**
+ * Parse the header of a `MOEQIBIN` file without decoding it.
+ */
+export function parseMqb(bytes: Uint8Array): MqbHeader;
+
 export type InitInput = RequestInfo | URL | Response | BufferSource | WebAssembly.Module;
 
 export interface InitOutput {
     readonly memory: WebAssembly.Memory;
     readonly __wbg_decoded_free: (a: number, b: number) => void;
+    readonly __wbg_mqbheader_free: (a: number, b: number) => void;
+    readonly decode: (a: number, b: number) => [number, number, number];
+    readonly decodeMqb: (a: number, b: number, c: number) => [number, number, number];
+    readonly decoded_bitDepth: (a: number) => number;
+    readonly decoded_clampedPixels: (a: number) => [number, number, number];
+    readonly decoded_format: (a: number) => [number, number];
+    readonly decoded_height: (a: number) => number;
+    readonly decoded_pixels: (a: number) => any;
+    readonly decoded_toRgba: (a: number) => number;
+    readonly decoded_width: (a: number) => number;
+    readonly encode: (a: number, b: number, c: number, d: number, e: number, f: number, g: number, h: number) => [number, number, number, number];
+    readonly mqbheader_codec: (a: number) => [number, number];
+    readonly mqbheader_experts: (a: number) => number;
+    readonly mqbheader_height: (a: number) => number;
+    readonly mqbheader_payloadBytes: (a: number) => number;
+    readonly mqbheader_qstep: (a: number) => number;
+    readonly mqbheader_width: (a: number) => number;
+    readonly parseMqb: (a: number, b: number) => [number, number, number];
     readonly __wbindgen_externrefs: WebAssembly.Table;
+    readonly __wbindgen_malloc: (a: number, b: number) => number;
+    readonly __externref_table_dealloc: (a: number) => void;
+    readonly __wbindgen_free: (a: number, b: number, c: number) => void;
+    readonly __wbindgen_realloc: (a: number, b: number, c: number, d: number) => number;
     readonly __wbindgen_start: () => void;
 }
 
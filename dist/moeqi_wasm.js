@@ -1,6 +1,15 @@
 /* @ts-self-types="./moeqi_wasm.d.ts" */
 
+/**
+ * A decoded image, pixels kept in wasm memory.
+ */
 export class Decoded {
+    static __wrap(ptr) {
+        const obj = Object.create(Decoded.prototype);
+        obj.__wbg_ptr = ptr;
+        DecodedFinalization.register(obj, obj.__wbg_ptr, obj);
+        return obj;
+    }
     __destroy_into_raw() {
         const ptr = this.__wbg_ptr;
         this.__wbg_ptr = 0;
@@ -11,15 +20,249 @@ export class Decoded {
         const ptr = this.__destroy_into_raw();
         wasm.__wbg_decoded_free(ptr, 0);
     }
+    /**
+     * Significant bits per sample: 8, or 9..16 for the 16-bit formats.
+     * @returns {number}
+     */
+    get bitDepth() {
+        const ret = wasm.decoded_bitDepth(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * [`Decoded::pixels`] as a `Uint8ClampedArray`, for `new ImageData(..)`.
+     * Only `Rgba8` images; see [`Decoded::to_rgba`].
+     * @returns {Uint8ClampedArray}
+     */
+    get clampedPixels() {
+        const ret = wasm.decoded_clampedPixels(this.__wbg_ptr);
+        if (ret[2]) {
+            throw takeFromExternrefTable0(ret[1]);
+        }
+        return takeFromExternrefTable0(ret[0]);
+    }
+    /**
+     * `"Gray8"`, `"Rgb8"`, `"Rgba8"`, `"Gray16"`, `"Rgb16"` or `"Rgba16"`.
+     * @returns {string}
+     */
+    get format() {
+        let deferred1_0;
+        let deferred1_1;
+        try {
+            const ret = wasm.decoded_format(this.__wbg_ptr);
+            deferred1_0 = ret[0];
+            deferred1_1 = ret[1];
+            return getStringFromWasm0(ret[0], ret[1]);
+        } finally {
+            wasm.__wbindgen_free(deferred1_0, deferred1_1, 1);
+        }
+    }
+    /**
+     * @returns {number}
+     */
+    get height() {
+        const ret = wasm.decoded_height(this.__wbg_ptr);
+        return ret >>> 0;
+    }
+    /**
+     * View of the pixels (row-major, 16-bit samples little-endian) without
+     * a copy. Only valid until this `Decoded` is freed or wasm memory grows;
+     * copy it with `slice()` to keep it.
+     * @returns {Uint8Array}
+     */
+    get pixels() {
+        const ret = wasm.decoded_pixels(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * A copy as `Rgba8`, ready for canvas: gray is replicated, alpha is
+     * opaque where missing and 16-bit samples keep their top 8 significant
+     * bits ([`Decoded::bit_depth`]).
+     * @returns {Decoded}
+     */
+    toRgba() {
+        const ret = wasm.decoded_toRgba(this.__wbg_ptr);
+        return Decoded.__wrap(ret);
+    }
+    /**
+     * @returns {number}
+     */
+    get width() {
+        const ret = wasm.decoded_width(this.__wbg_ptr);
+        return ret >>> 0;
+    }
 }
 if (Symbol.dispose) Decoded.prototype[Symbol.dispose] = Decoded.prototype.free;
 
+/**
+ * Header fields of a `MOEQIBIN` file, for debugging.
+ */
+export class MqbHeader {
+    static __wrap(ptr) {
+        const obj = Object.create(MqbHeader.prototype);
+        obj.__wbg_ptr = ptr;
+        MqbHeaderFinalization.register(obj, obj.__wbg_ptr, obj);
+        return obj;
+    }
+    __destroy_into_raw() {
+        const ptr = this.__wbg_ptr;
+        this.__wbg_ptr = 0;
+        MqbHeaderFinalization.unregister(this);
+        return ptr;
+    }
+    free() {
+        const ptr = this.__destroy_into_raw();
+        wasm.__wbg_mqbheader_free(ptr, 0);
+    }
+    /**
+     * Residual coder: `"Varint"` or `"Huff"`.
+     * @returns {string}
+     */
+    get codec() {
+        let deferred1_0;
+        let deferred1_1;
+        try {
+            const ret = wasm.mqbheader_codec(this.__wbg_ptr);
+            deferred1_0 = ret[0];
+            deferred1_1 = ret[1];
+            return getStringFromWasm0(ret[0], ret[1]);
+        } finally {
+            wasm.__wbindgen_free(deferred1_0, deferred1_1, 1);
+        }
+    }
+    /**
+     * Number of experts `E` in the model.
+     * @returns {number}
+     */
+    get experts() {
+        const ret = wasm.mqbheader_experts(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get height() {
+        const ret = wasm.mqbheader_height(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * Size of the coded residuals.
+     * @returns {number}
+     */
+    get payloadBytes() {
+        const ret = wasm.mqbheader_payloadBytes(this.__wbg_ptr);
+        return ret >>> 0;
+    }
+    /**
+     * Residual quantization step; 1 is lossless.
+     * @returns {number}
+     */
+    get qstep() {
+        const ret = wasm.mqbheader_qstep(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * @returns {number}
+     */
+    get width() {
+        const ret = wasm.mqbheader_width(this.__wbg_ptr);
+        return ret;
+    }
+}
+if (Symbol.dispose) MqbHeader.prototype[Symbol.dispose] = MqbHeader.prototype.free;
+
+/**
+ * Decode a `MOEQI2` or legacy `MOEQI1` file.
+ * @param {Uint8Array} bytes
+ * @returns {Decoded}
+ */
+export function decode(bytes) {
+    const ptr0 = passArray8ToWasm0(bytes, wasm.__wbindgen_malloc);
+    const len0 = WASM_VECTOR_LEN;
+    const ret = wasm.decode(ptr0, len0);
+    if (ret[2]) {
+        throw takeFromExternrefTable0(ret[1]);
+    }
+    return Decoded.__wrap(ret[0]);
+}
+
+/**
+ * Decode a `MOEQIBIN` file: `Gray8`, or `Rgba8` for canvas when `rgba` is
+ * true.
+ * @param {Uint8Array} bytes
+ * @param {boolean | null} [rgba]
+ * @returns {Decoded}
+ */
+export function decodeMqb(bytes, rgba) {
+    const ptr0 = passArray8ToWasm0(bytes, wasm.__wbindgen_malloc);
+    const len0 = WASM_VECTOR_LEN;
+    const ret = wasm.decodeMqb(ptr0, len0, isLikeNone(rgba) ? 0xFFFFFF : rgba ? 1 : 0);
+    if (ret[2]) {
+        throw takeFromExternrefTable0(ret[1]);
+    }
+    return Decoded.__wrap(ret[0]);
+}
+
+/**
+ * Encode raw pixels into a `MOEQI2` file. `format` is one of the
+ * [`Decoded::format`] names; `cfg_json` a JSON `CodecConfig`, or
+ * `undefined` for the default.
+ * @param {Uint8Array} pixels
+ * @param {number} width
+ * @param {number} height
+ * @param {string} format
+ * @param {string | null} [cfg_json]
+ * @returns {Uint8Array}
+ */
+export function encode(pixels, width, height, format, cfg_json) {
+    const ptr0 = passArray8ToWasm0(pixels, wasm.__wbindgen_malloc);
+    const len0 = WASM_VECTOR_LEN;
+    const ptr1 = passStringToWasm0(format, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    const len1 = WASM_VECTOR_LEN;
+    var ptr2 = isLikeNone(cfg_json) ? 0 : passStringToWasm0(cfg_json, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    var len2 = WASM_VECTOR_LEN;
+    const ret = wasm.encode(ptr0, len0, width, height, ptr1, len1, ptr2, len2);
+    if (ret[3]) {
+        throw takeFromExternrefTable0(ret[2]);
+    }
+    var v4 = getArrayU8FromWasm0(ret[0], ret[1]).slice();
+    wasm.__wbindgen_free(ret[0], ret[1] * 1, 1);
+    return v4;
+}
+
+/**
+ * Parse the header of a `MOEQIBIN` file without decoding it.
+ * @param {Uint8Array} bytes
+ * @returns {MqbHeader}
+ */
+export function parseMqb(bytes) {
+    const ptr0 = passArray8ToWasm0(bytes, wasm.__wbindgen_malloc);
+    const len0 = WASM_VECTOR_LEN;
+    const ret = wasm.parseMqb(ptr0, len0);
+    if (ret[2]) {
+        throw takeFromExternrefTable0(ret[1]);
+    }
+    return MqbHeader.__wrap(ret[0]);
+}
 function __wbg_get_imports() {
     const import0 = {
         __proto__: null,
-        __wbg___wbindgen_throw_be289d5034ed271b: function(arg0, arg1) {
+        __wbg_Error_30c8987f7c2ed4e2: function(arg0, arg1) {
+            const ret = Error(getStringFromWasm0(arg0, arg1));
+            return ret;
+        },
+        __wbg___wbindgen_throw_41e9ee4f547fc59a: function(arg0, arg1) {
             throw new Error(getStringFromWasm0(arg0, arg1));
         },
+        __wbindgen_generic_0000000000000001: function(arg0, arg1) {
+            // Cast intrinsic for `Ref(Slice(U8)) -> NamedExternref("Uint8Array")`.
+            const ret = getArrayU8FromWasm0(arg0, arg1);
+            return ret;
+        },
+        __wbindgen_generic_0000000000000002: function(arg0, arg1) {
+            // Cast intrinsic for `Ref(Slice(U8)) -> NamedExternref("Uint8ClampedArray")`.
+            const ret = getArrayU8FromWasm0(arg0, arg1);
+            return ret;
+        },
         __wbindgen_init_externref_table: function() {
             const table = wasm.__wbindgen_externrefs;
             const offset = table.grow(4);
@@ -38,11 +281,18 @@ function __wbg_get_imports() {
 
 const DecodedFinalization = (typeof FinalizationRegistry === 'undefined')
     ? { register: () => {}, unregister: () => {} }
-    : new FinalizationRegistry(ptr => wasm.__wbg_decoded_free(ptr >>> 0, 1));
+    : new FinalizationRegistry(ptr => wasm.__wbg_decoded_free(ptr, 1));
+const MqbHeaderFinalization = (typeof FinalizationRegistry === 'undefined')
+    ? { register: () => {}, unregister: () => {} }
+    : new FinalizationRegistry(ptr => wasm.__wbg_mqbheader_free(ptr, 1));
 
-function getStringFromWasm0(ptr, len) {
+function getArrayU8FromWasm0(ptr, len) {
     ptr = ptr >>> 0;
-    return decodeText(ptr, len);
+    return getUint8ArrayMemory0().subarray(ptr / 1, ptr / 1 + len);
+}
+
+function getStringFromWasm0(ptr, len) {
+    return decodeText(ptr >>> 0, len);
 }
 
 let cachedUint8ArrayMemory0 = null;
@@ -53,6 +303,60 @@ function getUint8ArrayMemory0() {
     return cachedUint8ArrayMemory0;
 }
 
+function isLikeNone(x) {
+    return x === undefined || x === null;
+}
+
+function passArray8ToWasm0(arg, malloc) {
+    const ptr = malloc(arg.length * 1, 1) >>> 0;
+    getUint8ArrayMemory0().set(arg, ptr / 1);
+    WASM_VECTOR_LEN = arg.length;
+    return ptr;
+}
+
+function passStringToWasm0(arg, malloc, realloc) {
+    if (realloc === undefined) {
+        const buf = cachedTextEncoder.encode(arg);
+        const ptr = malloc(buf.length, 1) >>> 0;
+        getUint8ArrayMemory0().subarray(ptr, ptr + buf.length).set(buf);
+        WASM_VECTOR_LEN = buf.length;
+        return ptr;
+    }
+
+    let len = arg.length;
+    let ptr = malloc(len, 1) >>> 0;
+
+    const mem = getUint8ArrayMemory0();
+
+    let offset = 0;
+
+    for (; offset < len; offset++) {
+        const code = arg.charCodeAt(offset);
+        if (code > 0x7F) break;
+        mem[ptr + offset] = code;
+    }
+    if (offset !== len) {
+        if (offset !== 0) {
+            arg = arg.slice(offset);
+        }
+        ptr = realloc(ptr, len, len = offset + arg.length * 3, 1) >>> 0;
+        const view = getUint8ArrayMemory0().subarray(ptr + offset, ptr + len);
+        const ret = cachedTextEncoder.encodeInto(arg, view);
+
+        offset += ret.written;
+        ptr = realloc(ptr, len, offset, 1) >>> 0;
+    }
+
+    WASM_VECTOR_LEN = offset;
+    return ptr;
+}
+
+function takeFromExternrefTable0(idx) {
+    const value = wasm.__wbindgen_externrefs.get(idx);
+    wasm.__externref_table_dealloc(idx);
+    return value;
+}
+
 let cachedTextDecoder = new TextDecoder('utf-8', { ignoreBOM: true, fatal: true });
 cachedTextDecoder.decode();
 const MAX_SAFARI_DECODE_BYTES = 2146435072;
@@ -67,8 +371,24 @@ function decodeText(ptr, len) {
     return cachedTextDecoder.decode(getUint8ArrayMemory0().subarray(ptr, ptr + len));
 }
 
-let wasmModule, wasm;
+const cachedTextEncoder = new TextEncoder();
+
+if (!('encodeInto' in cachedTextEncoder)) {
+    cachedTextEncoder.encodeInto = function (arg, view) {
+        const buf = cachedTextEncoder.encode(arg);
+        view.set(buf);
+        return {
+            read: arg.length,
+            written: buf.length
+        };
+    };
+}
+
+let WASM_VECTOR_LEN = 0;
+
+let wasmModule, wasmInstance, wasm;
 function __wbg_finalize_init(instance, module) {
+    wasmInstance = instance;
     wasm = instance.exports;
     wasmModule = module;
     cachedUint8ArrayMemory0 = null;
@@ -78,11 +398,15 @@ function __wbg_finalize_init(instance, module) {
 
 async function __wbg_load(module, imports) {
     if (typeof Response === 'function' && module instanceof Response) {
+        if (!module.ok) {
+            throw new Error(`failed to fetch Wasm: ${module.status} ${module.statusText} fetching '${module.url}'`);
+        }
+
         if (typeof WebAssembly.instantiateStreaming === 'function') {
             try {
                 return await WebAssembly.instantiateStreaming(module, imports);
             } catch (e) {
-                const validResponse = module.ok && expectedResponseType(module.type);
+                const validResponse = expectedResponseType(module.type);
 
                 if (validResponse && module.headers.get('Content-Type') !== 'application/wasm') {
                     console.warn("`WebAssembly.instantiateStreaming` failed because your server does not serve Wasm with `application/wasm` MIME type. Falling back to `WebAssembly.instantiate` which is slower. Original error:\n", e);
@@ -2,5 +2,27 @@
 /* eslint-disable */
 export const memory: WebAssembly.Memory;
 export const __wbg_decoded_free: (a: number, b: number) => void;
+export const __wbg_mqbheader_free: (a: number, b: number) => void;
+export const decode: (a: number, b: number) => [number, number, number];
+export const decodeMqb: (a: number, b: number, c: number) => [number, number, number];
+export const decoded_bitDepth: (a: number) => number;
+export const decoded_clampedPixels: (a: number) => [number, number, number];
+export const decoded_format: (a: number) => [number, number];
+export const decoded_height: (a: number) => number;
+export const decoded_pixels: (a: number) => any;
+export const decoded_toRgba: (a: number) => number;
+export const decoded_width: (a: number) => number;
+export const encode: (a: number, b: number, c: number, d: number, e: number, f: number, g: number, h: number) => [number, number, number, number];
+export const mqbheader_codec: (a: number) => [number, number];
+export const mqbheader_experts: (a: number) => number;
+export const mqbheader_height: (a: number) => number;
+export const mqbheader_payloadBytes: (a: number) => number;
+export const mqbheader_qstep: (a: number) => number;
+export const mqbheader_width: (a: number) => number;
+export const parseMqb: (a: number, b: number) => [number, number, number];
 export const __wbindgen_externrefs: WebAssembly.Table;
+export const __wbindgen_malloc: (a: number, b: number) => number;
+export const __externref_table_dealloc: (a: number) => void;
+export const __wbindgen_free: (a: number, b: number, c: number) => void;
+export const __wbindgen_realloc: (a: number, b: number, c: number, d: number) => number;
 export const __wbindgen_start: () => void;
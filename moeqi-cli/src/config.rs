@@ -0,0 +1,327 @@
+//! Command-line flags for every [`CodecConfig`] field.
+
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+use moeqi::{
+    AlphaConfig, ChannelLayout, ChromaSiting, CodecConfig, CodecKind, ColorTransform, PlaneConfig,
+    Predictor, Subsampling, Upsampling,
+};
+
+/// Each flag overrides the matching field of `--config`, or of the default
+/// config when there is none.
+#[derive(Debug, Default, Args)]
+pub struct CodecArgs {
+    /// JSON `CodecConfig` to start from.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    #[arg(long, value_enum)]
+    pub codec: Option<Codec>,
+    /// Residual quantization; 0 is lossless.
+    #[arg(long)]
+    pub quant_bits: Option<u8>,
+    /// Predict from reconstructed samples.
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub strict_recon: Option<bool>,
+    #[arg(long, value_enum)]
+    pub color: Option<Color>,
+    /// Chroma subsampling of `--color ycbcr`.
+    #[arg(long, value_enum)]
+    pub subsampling: Option<Chroma>,
+    #[arg(long, value_enum)]
+    pub siting: Option<Siting>,
+    #[arg(long, value_enum)]
+    pub upsampling: Option<Upsample>,
+    #[arg(long, value_enum)]
+    pub predictor: Option<Pred>,
+    /// Side of independently coded tiles; 0 = untiled.
+    #[arg(long)]
+    pub tile_size: Option<u32>,
+    /// Significant bits of 16-bit images (9..=16); 0 = all.
+    #[arg(long)]
+    pub bit_depth: Option<u8>,
+    /// Near-lossless bound per sample.
+    #[arg(long)]
+    pub max_abs_error: Option<u8>,
+    #[arg(long, value_enum)]
+    pub layout: Option<Layout>,
+    /// Per-plane override, e.g. `1:codec=arith,predictor=med,quant-bits=2`.
+    /// Repeatable.
+    #[arg(long, value_name = "I:KEY=VALUE,..", value_parser = parse_plane)]
+    pub plane: Vec<(usize, PlaneConfig)>,
+    /// Code alpha apart from colour.
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub separate_alpha: Option<bool>,
+    /// Zero the colour of fully transparent pixels.
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub clear_transparent: Option<bool>,
+    /// Colour is premultiplied by alpha.
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub premultiplied: Option<bool>,
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub run_mode: Option<bool>,
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub palette: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Codec {
+    Varint,
+    Arith,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Color {
+    None,
+    Ycocg,
+    Ycbcr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Chroma {
+    #[value(name = "444")]
+    S444,
+    #[value(name = "422")]
+    S422,
+    #[value(name = "420")]
+    S420,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Siting {
+    Centered,
+    Cosited,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Upsample {
+    Nearest,
+    Bilinear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Pred {
+    Left,
+    Up,
+    Average,
+    Paeth,
+    Med,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    Interleaved,
+    Planar,
+}
+
+impl From<Codec> for CodecKind {
+    fn from(c: Codec) -> Self {
+        match c {
+            Codec::Varint => CodecKind::PredictVarint,
+            Codec::Arith => CodecKind::PredictArith,
+        }
+    }
+}
+
+impl From<Pred> for Predictor {
+    fn from(p: Pred) -> Self {
+        match p {
+            Pred::Left => Predictor::Left,
+            Pred::Up => Predictor::Up,
+            Pred::Average => Predictor::Average,
+            Pred::Paeth => Predictor::Paeth,
+            Pred::Med => Predictor::Med,
+        }
+    }
+}
+
+impl CodecArgs {
+    pub fn to_config(&self) -> Result<CodecConfig, String> {
+        let mut cfg = match &self.config {
+            Some(path) => {
+                let json = std::fs::read_to_string(path)
+                    .map_err(|e| format!("{}: {e}", path.display()))?;
+                serde_json::from_str(&json).map_err(|e| format!("{}: {e}", path.display()))?
+            }
+            None => CodecConfig::default(),
+        };
+
+        if let Some(c) = self.codec {
+            cfg.codec = c.into();
+        }
+        set(&mut cfg.quant_bits, self.quant_bits);
+        set(&mut cfg.strict_recon, self.strict_recon);
+        set(&mut cfg.tile_size, self.tile_size);
+        set(&mut cfg.bit_depth, self.bit_depth);
+        set(&mut cfg.max_abs_error, self.max_abs_error);
+        if let Some(p) = self.predictor {
+            cfg.predictor = p.into();
+        }
+        if let Some(l) = self.layout {
+            cfg.layout = match l {
+                Layout::Interleaved => ChannelLayout::Interleaved,
+                Layout::Planar => ChannelLayout::Planar,
+            };
+        }
+        for &(i, plane) in &self.plane {
+            cfg.planes[i] = plane;
+        }
+        let AlphaConfig {
+            separate,
+            clear_transparent,
+            premultiplied,
+        } = &mut cfg.alpha;
+        set(separate, self.separate_alpha);
+        set(clear_transparent, self.clear_transparent);
+        set(premultiplied, self.premultiplied);
+        set(&mut cfg.run_mode, self.run_mode);
+        set(&mut cfg.palette, self.palette);
+
+        cfg.color_transform = self.color_transform(cfg.color_transform)?;
+        Ok(cfg)
+    }
+
+    /// `--color` and the YCbCr options on top of `base`.
+    fn color_transform(&self, base: ColorTransform) -> Result<ColorTransform, String> {
+        let ycbcr = match (self.color, base) {
+            (Some(Color::None), _) => ColorTransform::None,
+            (Some(Color::Ycocg), _) => ColorTransform::YCoCgR,
+            (Some(Color::Ycbcr), ColorTransform::YCbCr { .. }) | (None, _) => base,
+            (Some(Color::Ycbcr), _) => ColorTransform::YCbCr {
+                subsampling: Subsampling::S420,
+                siting: ChromaSiting::default(),
+                upsampling: Upsampling::default(),
+            },
+        };
+        let ColorTransform::YCbCr {
+            mut subsampling,
+            mut siting,
+            mut upsampling,
+        } = ycbcr
+        else {
+            if self.subsampling.is_some() || self.siting.is_some() || self.upsampling.is_some() {
+                return Err("--subsampling, --siting and --upsampling need --color ycbcr".into());
+            }
+            return Ok(ycbcr);
+        };
+        if let Some(s) = self.subsampling {
+            subsampling = match s {
+                Chroma::S444 => Subsampling::S444,
+                Chroma::S422 => Subsampling::S422,
+                Chroma::S420 => Subsampling::S420,
+            };
+        }
+        if let Some(s) = self.siting {
+            siting = match s {
+                Siting::Centered => ChromaSiting::Centered,
+                Siting::Cosited => ChromaSiting::Cosited,
+            };
+        }
+        if let Some(u) = self.upsampling {
+            upsampling = match u {
+                Upsample::Nearest => Upsampling::Nearest,
+                Upsample::Bilinear => Upsampling::Bilinear,
+            };
+        }
+        Ok(ColorTransform::YCbCr {
+            subsampling,
+            siting,
+            upsampling,
+        })
+    }
+}
+
+fn set<T>(field: &mut T, value: Option<T>) {
+    if let Some(v) = value {
+        *field = v;
+    }
+}
+
+/// `I:KEY=VALUE,..` with keys `codec`, `predictor` and `quant-bits`.
+fn parse_plane(s: &str) -> Result<(usize, PlaneConfig), String> {
+    let (index, fields) = s.split_once(':').ok_or("expected I:KEY=VALUE,..")?;
+    let index: usize = index
+        .parse()
+        .map_err(|_| format!("bad plane index {index:?}"))?;
+    if index >= 4 {
+        return Err(format!("plane index {index} is not in 0..4"));
+    }
+    let mut plane = PlaneConfig::default();
+    for field in fields.split(',').filter(|f| !f.is_empty()) {
+        let (key, value) = field
+            .split_once('=')
+            .ok_or_else(|| format!("expected KEY=VALUE, got {field:?}"))?;
+        match key {
+            "codec" => plane.codec = Some(Codec::from_str(value, true)?.into()),
+            "predictor" => plane.predictor = Some(Pred::from_str(value, true)?.into()),
+            "quant-bits" => {
+                plane.quant_bits = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("bad quant-bits {value:?}"))?,
+                )
+            }
+            _ => return Err(format!("unknown plane key {key:?}")),
+        }
+    }
+    Ok((index, plane))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        codec: CodecArgs,
+    }
+
+    fn config(args: &[&str]) -> Result<CodecConfig, String> {
+        let cli = Cli::try_parse_from(std::iter::once("moeqi").chain(args.iter().copied()))
+            .map_err(|e| e.to_string())?;
+        cli.codec.to_config()
+    }
+
+    #[test]
+    fn flags_override_defaults() {
+        assert_eq!(config(&[]).unwrap(), CodecConfig::default());
+        let cfg = config(&[
+            "--codec=arith",
+            "--predictor=med",
+            "--color=ycbcr",
+            "--subsampling=422",
+            "--layout=planar",
+            "--plane=1:codec=varint,quant-bits=3",
+            "--separate-alpha",
+            "--run-mode",
+            "--strict-recon=false",
+        ])
+        .unwrap();
+        assert_eq!(cfg.codec, CodecKind::PredictArith);
+        assert_eq!(cfg.predictor, Predictor::Med);
+        assert_eq!(
+            cfg.color_transform,
+            ColorTransform::YCbCr {
+                subsampling: Subsampling::S422,
+                siting: ChromaSiting::Centered,
+                upsampling: Upsampling::Bilinear,
+            }
+        );
+        assert_eq!(cfg.layout, ChannelLayout::Planar);
+        assert_eq!(
+            cfg.planes[1],
+            PlaneConfig {
+                codec: Some(CodecKind::PredictVarint),
+                predictor: None,
+                quant_bits: Some(3),
+            }
+        );
+        assert!(cfg.alpha.separate && cfg.run_mode && !cfg.strict_recon);
+
+        assert!(config(&["--siting=cosited"]).is_err());
+        assert!(config(&["--plane=4:codec=arith"]).is_err());
+        assert!(config(&["--plane=0:speed=9"]).is_err());
+    }
+}
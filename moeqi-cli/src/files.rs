@@ -0,0 +1,164 @@
+//! Reading and writing source images (PNG, PGM/PPM/PAM) and telling the
+//! coded containers apart.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use moeqi::{Image, PixelFormat};
+use moeqi_core::format::{moeqi2, pnm};
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Which container a coded file is in, from its magic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Moeqi1,
+    Moeqi2,
+    MoeqiBin,
+}
+
+impl Container {
+    pub fn sniff(bytes: &[u8]) -> Option<Container> {
+        if bytes.starts_with(b"MOEQIBIN") {
+            Some(Container::MoeqiBin)
+        } else if bytes.starts_with(moeqi2::MAGIC) {
+            Some(Container::Moeqi2)
+        } else if bytes.starts_with(b"MOEQI1") {
+            Some(Container::Moeqi1)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Container::Moeqi1 => "MOEQI1",
+            Container::Moeqi2 => "MOEQI2",
+            Container::MoeqiBin => "MOEQIBIN",
+        }
+    }
+}
+
+/// Decode a file in any of the [`Container`]s.
+pub fn decode(bytes: &[u8]) -> Result<Image> {
+    match Container::sniff(bytes) {
+        Some(Container::MoeqiBin) => Ok(moeqi_core::moe::decode(bytes)?),
+        Some(_) => Ok(moeqi::decode(bytes)?.0),
+        None => Err("not a MOEQI1, MOEQI2 or MOEQIBIN file".into()),
+    }
+}
+
+/// A source image, by extension: `.png`, `.pgm`, `.ppm` or `.pam`. A coded file
+/// (any [`Container`]) is decoded instead, whatever its name.
+pub fn read_image(path: &Path) -> Result<Image> {
+    let bytes = std::fs::read(path)?;
+    if Container::sniff(&bytes).is_some() {
+        return decode(&bytes);
+    }
+    match extension(path).as_str() {
+        "png" => read_png(&bytes),
+        "pgm" | "ppm" | "pnm" | "pam" => Ok(pnm::decode(&bytes)?),
+        _ => Err(format!(
+            "{}: expected a .png, .pgm, .ppm or .pam file",
+            path.display()
+        )
+        .into()),
+    }
+}
+
+/// Write `img` as PNG, as binary PGM/PPM when `path` ends in `.pgm`, `.ppm`
+/// or `.pnm`, or as PAM for `.pam`.
+pub fn write_image(path: &Path, img: &Image) -> Result<()> {
+    match extension(path).as_str() {
+        "pgm" | "ppm" | "pnm" => Ok(std::fs::write(path, pnm::encode(img, pnm::Kind::Raw)?)?),
+        "pam" => Ok(std::fs::write(path, pnm::encode(img, pnm::Kind::Pam)?)?),
+        _ => write_png(path, img),
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+fn read_png(bytes: &[u8]) -> Result<Image> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    buf.truncate(info.buffer_size());
+
+    let wide = info.bit_depth == png::BitDepth::Sixteen;
+    let bytes = if wide { 2 } else { 1 };
+    if wide {
+        // PNG is big-endian, `Image` little-endian
+        for s in buf.chunks_exact_mut(2) {
+            s.swap(0, 1);
+        }
+    }
+    let (format, data) = match info.color_type {
+        png::ColorType::Grayscale => (PixelFormat::Gray8, buf),
+        png::ColorType::Rgb => (PixelFormat::Rgb8, buf),
+        png::ColorType::Rgba => (PixelFormat::Rgba8, buf),
+        png::ColorType::GrayscaleAlpha => {
+            let data = buf
+                .chunks_exact(2 * bytes)
+                .flat_map(|px| {
+                    let (g, a) = px.split_at(bytes);
+                    [g, g, g, a].concat()
+                })
+                .collect();
+            (PixelFormat::Rgba8, data)
+        }
+        png::ColorType::Indexed => return Err("indexed PNG was not expanded".into()),
+    };
+    let format = match (format, wide) {
+        (f, false) => f,
+        (PixelFormat::Gray8, true) => PixelFormat::Gray16,
+        (PixelFormat::Rgb8, true) => PixelFormat::Rgb16,
+        (_, true) => PixelFormat::Rgba16,
+    };
+    Ok(Image {
+        width: info.width,
+        height: info.height,
+        format,
+        data,
+    })
+}
+
+fn write_png(path: &Path, img: &Image) -> Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), img.width, img.height);
+    encoder.set_color(match img.format.channels() {
+        1 => png::ColorType::Grayscale,
+        3 => png::ColorType::Rgb,
+        _ => png::ColorType::Rgba,
+    });
+    let mut data = img.data.clone();
+    if img.format.bytes_per_sample() == 2 {
+        encoder.set_depth(png::BitDepth::Sixteen);
+        for s in data.chunks_exact_mut(2) {
+            s.swap(0, 1);
+        }
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffing() {
+        assert_eq!(Container::sniff(b"MOEQIBIN\x02"), Some(Container::MoeqiBin));
+        assert_eq!(Container::sniff(b"MOEQI1...."), Some(Container::Moeqi1));
+        assert_eq!(Container::sniff(b"MOEQI2...."), Some(Container::Moeqi2));
+        assert_eq!(Container::sniff(b"\x89PNG"), None);
+    }
+}
@@ -0,0 +1,385 @@
+//! `moeqi`: encode, decode, inspect, verify and benchmark images from the
+//! command line. Coded inputs may be in any container: `MOEQI2`, the legacy
+//! `MOEQI1` or the Gray8 `MOEQIBIN`.
+
+mod config;
+mod files;
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use moeqi::{CodecConfig, Image, PixelFormat};
+use moeqi_core::format::{binary, moeqi2};
+use moeqi_core::moe::{self, Model};
+use moeqi_core::train::eval;
+use moeqi_core::train::moe::{fit_model, MoeFitConfig};
+
+use config::CodecArgs;
+use files::{Container, Result};
+
+#[derive(Parser)]
+#[command(name = "moeqi", version, about = "MoE-Qi image codec")]
+struct Cli {
+    /// Worker threads for tiled images (0 = one per core); needs the
+    /// `parallel` feature and `--tile-size`, as untiled images use one
+    /// thread.
+    #[arg(long, global = true)]
+    threads: Option<usize>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Encode a PNG, PGM, PPM or PAM image.
+    Encode {
+        input: PathBuf,
+        /// Defaults to the input with a `.moeqi` extension.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        target: TargetArgs,
+    },
+    /// Decode to PNG, or to PGM/PPM/PAM by the output's extension.
+    Decode {
+        input: PathBuf,
+        /// Defaults to the input with a `.png` extension.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Print the header fields, payload size and bits per pixel.
+    Info { input: PathBuf },
+    /// Round-trip an image and report the PSNR. A source image is coded with
+    /// the given flags, a coded file is re-coded with its own parameters.
+    Verify {
+        input: PathBuf,
+        /// Compare this coded file against `input` instead of coding it.
+        #[arg(long, value_name = "FILE")]
+        encoded: Option<PathBuf>,
+        #[command(flatten)]
+        target: TargetArgs,
+    },
+    /// Encode and decode repeatedly and report the throughput in MP/s.
+    Bench {
+        input: PathBuf,
+        #[arg(short = 'n', long, default_value_t = 10)]
+        iterations: u32,
+        #[command(flatten)]
+        target: TargetArgs,
+    },
+}
+
+/// The container to code into, and its parameters.
+#[derive(Args)]
+struct TargetArgs {
+    #[arg(long, value_enum, default_value_t = ContainerArg::Moeqi2)]
+    container: ContainerArg,
+    #[command(flatten)]
+    codec: CodecArgs,
+    /// `MOEQIBIN`: experts fitted to the image.
+    #[arg(long, default_value_t = MoeFitConfig::default().experts)]
+    experts: u16,
+    /// `MOEQIBIN`: residual quantization step; 1 is lossless.
+    #[arg(long, default_value_t = 1)]
+    qstep: u16,
+    /// `MOEQIBIN`: residual entropy coder.
+    #[arg(long, value_enum, default_value_t = MoeCodec::Varint)]
+    moe_codec: MoeCodec,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ContainerArg {
+    Moeqi2,
+    Moeqi1,
+    Moeqibin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum MoeCodec {
+    Varint,
+    Huff,
+}
+
+/// Everything needed to code an image into one of the containers.
+enum Target {
+    Moeqi2(CodecConfig),
+    Moeqi1(CodecConfig),
+    MoeqiBin {
+        model: Model,
+        qstep: u16,
+        codec: moe::Codec,
+    },
+}
+
+impl Target {
+    /// From the command line; `MOEQIBIN` fits its model to `img`.
+    fn from_args(args: &TargetArgs, img: &Image) -> Result<Target> {
+        let cfg = args.codec.to_config()?;
+        Ok(match args.container {
+            ContainerArg::Moeqi2 => Target::Moeqi2(cfg),
+            ContainerArg::Moeqi1 => Target::Moeqi1(cfg),
+            ContainerArg::Moeqibin => {
+                if img.format != PixelFormat::Gray8 {
+                    return Err("MOEQIBIN is Gray8 only".into());
+                }
+                let fit = MoeFitConfig {
+                    experts: args.experts,
+                    ..MoeFitConfig::default()
+                };
+                Target::MoeqiBin {
+                    model: fit_model(std::slice::from_ref(img), fit)?,
+                    qstep: args.qstep,
+                    codec: match args.moe_codec {
+                        MoeCodec::Varint => moe::Codec::Varint,
+                        MoeCodec::Huff => moe::Codec::Huff,
+                    },
+                }
+            }
+        })
+    }
+
+    /// The parameters `bytes` was coded with, and its decoded image.
+    fn from_file(bytes: &[u8]) -> Result<(Target, Image)> {
+        Ok(match Container::sniff(bytes) {
+            Some(Container::MoeqiBin) => {
+                let bs = moe::parse_mqb(bytes)?;
+                let img = moe::decode(bytes)?;
+                let target = Target::MoeqiBin {
+                    model: bs.model,
+                    qstep: bs.qstep,
+                    codec: bs.codec,
+                };
+                (target, img)
+            }
+            Some(Container::Moeqi2) => {
+                let (img, cfg) = moeqi2::decode(bytes)?;
+                (Target::Moeqi2(cfg), img)
+            }
+            Some(Container::Moeqi1) => {
+                let (img, cfg) = binary::decode_v1(bytes)?;
+                (Target::Moeqi1(cfg), img)
+            }
+            None => return Err("not a MOEQI1, MOEQI2 or MOEQIBIN file".into()),
+        })
+    }
+
+    fn encode(&self, img: &Image) -> Result<Vec<u8>> {
+        Ok(match self {
+            Target::Moeqi2(cfg) => moeqi::encode(img, *cfg)?,
+            Target::Moeqi1(cfg) => binary::encode_v1(img, *cfg)?,
+            Target::MoeqiBin {
+                model,
+                qstep,
+                codec,
+            } => moe::encode(img, model, *qstep, *codec)?,
+        })
+    }
+}
+
+/// `input` as an image and the target to code it with: the flags for a
+/// source image, the file's own parameters for a coded one.
+fn load(input: &Path, args: &TargetArgs) -> Result<(Image, Target)> {
+    let bytes = std::fs::read(input)?;
+    if Container::sniff(&bytes).is_some() {
+        let (target, img) = Target::from_file(&bytes)?;
+        return Ok((img, target));
+    }
+    let img = files::read_image(input)?;
+    let target = Target::from_args(args, &img)?;
+    Ok((img, target))
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if let Some(n) = cli.threads {
+        moeqi::set_threads(n);
+    }
+    match run(cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("moeqi: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Encode {
+            input,
+            output,
+            target,
+        } => {
+            let img = files::read_image(&input)?;
+            let bytes = Target::from_args(&target, &img)?.encode(&img)?;
+            let output = output.unwrap_or_else(|| input.with_extension("moeqi"));
+            std::fs::write(&output, &bytes)?;
+            println!(
+                "{}: {}x{} {:?}, {} bytes, {:.3} bpp",
+                output.display(),
+                img.width,
+                img.height,
+                img.format,
+                bytes.len(),
+                eval::bpp(bytes.len(), &img)
+            );
+        }
+        Command::Decode { input, output } => {
+            let img = files::decode(&std::fs::read(&input)?)?;
+            let output = output.unwrap_or_else(|| input.with_extension("png"));
+            files::write_image(&output, &img)?;
+            println!(
+                "{}: {}x{} {:?}",
+                output.display(),
+                img.width,
+                img.height,
+                img.format
+            );
+        }
+        Command::Info { input } => info(&std::fs::read(&input)?)?,
+        Command::Verify {
+            input,
+            encoded,
+            target,
+        } => verify(&input, encoded.as_deref(), &target)?,
+        Command::Bench {
+            input,
+            iterations,
+            target,
+        } => bench(&input, iterations.max(1), &target)?,
+    }
+    Ok(())
+}
+
+fn info(bytes: &[u8]) -> Result<()> {
+    let container = Container::sniff(bytes).ok_or("not a MOEQI1, MOEQI2 or MOEQIBIN file")?;
+    println!("container: {}", container.name());
+    let (width, height, payload) = match container {
+        Container::Moeqi2 => {
+            let c = moeqi2::read_container(bytes)?;
+            let h = &c.header;
+            println!("version: {}", h.version);
+            println!("features: {:#x}", h.features);
+            println!("format: {:?}", h.format);
+            println!("bit depth: {}", h.bit_depth);
+            println!("tiles: {}", c.tiles().len());
+            if !c.bands.is_empty() {
+                println!("bands: {}", c.bands.len());
+            }
+            if let Some(p) = &c.palette {
+                println!("palette: {} colours", p.len());
+            }
+            for (key, value) in &c.metadata {
+                println!("meta {key}: {value}");
+            }
+            println!("config: {}", serde_json::to_string(&c.config)?);
+            let payload = c.data.len() + c.bands.iter().map(|b| b.len()).sum::<usize>();
+            (h.width, h.height, payload)
+        }
+        Container::Moeqi1 => {
+            let (img, cfg) = binary::decode_v1(bytes)?;
+            println!("format: {:?}", img.format);
+            println!("config: {}", serde_json::to_string(&cfg)?);
+            // the payload length is the last header field
+            let payload = u32::from_le_bytes(bytes[18..22].try_into()?) as usize;
+            (img.width, img.height, payload)
+        }
+        Container::MoeqiBin => {
+            let bs = moe::parse_mqb(bytes)?;
+            println!("format: {:?}", PixelFormat::Gray8);
+            println!("qstep: {}", bs.qstep);
+            println!("codec: {:?}", bs.codec);
+            println!("experts: {}", bs.model.e);
+            println!("residuals: {}", bs.residuals_count);
+            (bs.w as u32, bs.h as u32, bs.payload.len())
+        }
+    };
+    let pixels = (width as f64 * height as f64).max(1.0);
+    println!("size: {width}x{height}");
+    println!("file bytes: {}", bytes.len());
+    println!("payload bytes: {payload}");
+    println!("bpp: {:.3}", bytes.len() as f64 * 8.0 / pixels);
+    Ok(())
+}
+
+fn verify(input: &Path, encoded: Option<&Path>, args: &TargetArgs) -> Result<()> {
+    let (source, bytes) = match encoded {
+        Some(encoded) => (files::read_image(input)?, std::fs::read(encoded)?),
+        None => {
+            let (img, target) = load(input, args)?;
+            let bytes = target.encode(&img)?;
+            (img, bytes)
+        }
+    };
+    let decoded = files::decode(&bytes)?;
+    if (decoded.width, decoded.height, decoded.format)
+        != (source.width, source.height, source.format)
+    {
+        return Err(format!(
+            "decoded {}x{} {:?}, expected {}x{} {:?}",
+            decoded.width,
+            decoded.height,
+            decoded.format,
+            source.width,
+            source.height,
+            source.format
+        )
+        .into());
+    }
+    let psnr = eval::psnr(&source, &decoded)?;
+    let max_error = source
+        .samples()
+        .iter()
+        .zip(decoded.samples())
+        .map(|(&a, b)| a.abs_diff(b))
+        .max()
+        .unwrap_or(0);
+    println!("bytes: {}", bytes.len());
+    println!("bpp: {:.3}", eval::bpp(bytes.len(), &source));
+    match max_error {
+        0 => println!("psnr: lossless"),
+        _ => println!("psnr: {psnr:.2} dB"),
+    }
+    println!("max abs error: {max_error}");
+    Ok(())
+}
+
+fn bench(input: &Path, iterations: u32, args: &TargetArgs) -> Result<()> {
+    let (img, target) = load(input, args)?;
+    let megapixels = img.width as f64 * img.height as f64 / 1e6;
+
+    let start = Instant::now();
+    let mut bytes = Vec::new();
+    for _ in 0..iterations {
+        bytes = target.encode(&img)?;
+    }
+    let encode = start.elapsed().as_secs_f64() / iterations as f64;
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        files::decode(&bytes)?;
+    }
+    let decode = start.elapsed().as_secs_f64() / iterations as f64;
+
+    println!(
+        "{}x{} {:?}, {} bytes, {:.3} bpp",
+        img.width,
+        img.height,
+        img.format,
+        bytes.len(),
+        eval::bpp(bytes.len(), &img)
+    );
+    println!(
+        "encode: {:.2} MP/s ({:.2} ms)",
+        megapixels / encode,
+        encode * 1e3
+    );
+    println!(
+        "decode: {:.2} MP/s ({:.2} ms)",
+        megapixels / decode,
+        decode * 1e3
+    );
+    Ok(())
+}
@@ -0,0 +1,38 @@
+//! Table-driven vs. tree-walking Huffman decode of MOEQIBIN residuals.
+//!
+//! `cargo bench -p moeqi-core --bench huff_decode`
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use moeqi_core::moe::codec_huff::{decode_huff_i16, decode_huff_i16_tree, encode_huff_i16};
+
+/// Laplacian-ish residuals, like a well-predicted 1024x1024 image.
+fn residuals(n: usize) -> Vec<i16> {
+    let mut seed = 0x2545_f491u32;
+    (0..n)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let mag = (seed & 0xffff).leading_zeros() as i16 - 16 + (seed >> 28) as i16;
+            if seed & 0x8000_0000 != 0 { -mag } else { mag }
+        })
+        .collect()
+}
+
+fn bench(c: &mut Criterion) {
+    let vals = residuals(1 << 20);
+    let (payload, syms, lens) = encode_huff_i16(&vals).unwrap();
+
+    let mut g = c.benchmark_group("huff_decode");
+    g.throughput(Throughput::Elements(vals.len() as u64));
+    g.bench_function("lut", |b| {
+        b.iter(|| decode_huff_i16(black_box(&payload), vals.len(), &syms, &lens).unwrap())
+    });
+    g.bench_function("tree", |b| {
+        b.iter(|| decode_huff_i16_tree(black_box(&payload), vals.len(), &syms, &lens).unwrap())
+    });
+    g.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
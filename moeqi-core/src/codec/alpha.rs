@@ -0,0 +1,83 @@
+//! Alpha coded apart from colour, see
+//! [`AlphaConfig::separate`](crate::types::AlphaConfig::separate).
+//!
+//! A tile's alpha starts with a kind byte: a constant (varint value follows),
+//! a 0/max mask (varint run lengths follow, alternating transparent and
+//! opaque, starting with transparent) or a plane coded like any other.
+
+use crate::codec::varint;
+use crate::error::{MoeqiError, Result};
+
+pub(crate) const CONSTANT: u8 = 0;
+pub(crate) const MASK: u8 = 1;
+pub(crate) const PLANE: u8 = 2;
+
+/// Cheapest kind for `alpha`, whose samples are at most `max`.
+pub(crate) fn kind(alpha: &[i32], max: i32) -> u8 {
+    match alpha.first() {
+        Some(&a) if alpha.iter().all(|&v| v == a) => CONSTANT,
+        _ if alpha.iter().all(|&v| v == 0 || v == max) => MASK,
+        _ => PLANE,
+    }
+}
+
+/// Run lengths of a 0/max mask.
+pub(crate) fn encode_mask(alpha: &[i32], out: &mut Vec<u8>) {
+    if alpha.is_empty() {
+        return;
+    }
+    let mut opaque = false;
+    let mut run = 0u32;
+    for &v in alpha {
+        if (v != 0) != opaque {
+            varint::encode_u32_var(run, out);
+            opaque = !opaque;
+            run = 0;
+        }
+        run += 1;
+    }
+    varint::encode_u32_var(run, out);
+}
+
+/// Inverse of [`encode_mask`] for `len` samples; returns the bytes used.
+pub(crate) fn decode_mask(input: &[u8], len: usize, max: i32) -> Result<(Vec<i32>, usize)> {
+    let mut alpha = Vec::with_capacity(len);
+    let mut pos = 0;
+    let mut value = 0;
+    while alpha.len() < len {
+        let (run, used) = varint::decode_u32_var(&input[pos..])?;
+        pos += used;
+        if run as usize > len - alpha.len() {
+            return Err(MoeqiError::InvalidData("alpha mask overruns the tile"));
+        }
+        alpha.resize(alpha.len() + run as usize, value);
+        value = max - value;
+    }
+    Ok((alpha, pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_runs_roundtrip() {
+        for alpha in [
+            vec![255, 255, 0, 0, 0, 255],
+            vec![0, 255],
+            vec![255; 7],
+            vec![],
+        ] {
+            let mut out = Vec::new();
+            encode_mask(&alpha, &mut out);
+            assert_eq!(
+                decode_mask(&out, alpha.len(), 255).unwrap(),
+                (alpha, out.len())
+            );
+        }
+        assert_eq!(kind(&[9, 9, 9], 255), CONSTANT);
+        assert_eq!(kind(&[0, 255, 0], 255), MASK);
+        assert_eq!(kind(&[0, 128], 255), PLANE);
+        assert!(decode_mask(&[3], 2, 255).is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! Adaptive binary range coder for zigzagged residuals (`CodecKind::PredictArith`).
+//!
+//! The coder is the LZMA one: 11-bit probabilities adapted by `>> 5` after
+//! every bit, 32-bit range with byte-wise carry propagation. Everything is
+//! integer arithmetic, so native and wasm builds produce identical bytes.
+//!
+//! A residual `z` is binarised as its bit length `k` in unary, then the `k - 1`
+//! bits below the leading one. The unary bits and the first mantissa bit are
+//! modelled per (channel, context); lower mantissa bits are sent raw.
+
+use crate::error::{MoeqiError, Result};
+
+const PROB_BITS: u32 = 11;
+const PROB_INIT: u16 = 1 << (PROB_BITS - 1);
+const MOVE_BITS: u32 = 5;
+const TOP: u32 = 1 << 24;
+
+/// Number of local-activity contexts, see [`context`].
+pub const CONTEXTS: usize = 8;
+/// Longest unary prefix: a full `u32`.
+const MAX_K: usize = 32;
+
+/// Bucket the local gradient activity `|a - c| + |b - c|` of the left (`a`),
+/// up (`b`) and up-left (`c`) neighbours into `0..CONTEXTS`.
+#[inline]
+pub fn context(a: i32, b: i32, c: i32) -> usize {
+    let act = (a - c).unsigned_abs() + (b - c).unsigned_abs();
+    match act {
+        0 => 0,
+        1..=2 => 1,
+        3..=5 => 2,
+        6..=11 => 3,
+        12..=23 => 4,
+        24..=47 => 5,
+        48..=95 => 6,
+        _ => 7,
+    }
+}
+
+#[derive(Clone)]
+struct Model {
+    unary: [u16; MAX_K + 1],
+    mant: [u16; MAX_K + 1],
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Self {
+            unary: [PROB_INIT; MAX_K + 1],
+            mant: [PROB_INIT; MAX_K + 1],
+        }
+    }
+}
+
+pub struct RangeEncoder {
+    low: u64,
+    range: u32,
+    cache: u8,
+    cache_size: u64,
+    out: Vec<u8>,
+}
+
+impl Default for RangeEncoder {
+    fn default() -> Self {
+        Self {
+            low: 0,
+            range: u32::MAX,
+            cache: 0,
+            cache_size: 1,
+            out: Vec::new(),
+        }
+    }
+}
+
+impl RangeEncoder {
+    fn shift_low(&mut self) {
+        if (self.low as u32) < 0xFF00_0000 || (self.low >> 32) != 0 {
+            let carry = (self.low >> 32) as u8;
+            let mut temp = self.cache;
+            loop {
+                self.out.push(temp.wrapping_add(carry));
+                temp = 0xFF;
+                self.cache_size -= 1;
+                if self.cache_size == 0 {
+                    break;
+                }
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.cache_size += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+    }
+
+    #[inline]
+    fn normalize(&mut self) {
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low();
+        }
+    }
+
+    #[inline]
+    pub fn encode_bit(&mut self, p: &mut u16, bit: bool) {
+        let bound = (self.range >> PROB_BITS) * (*p as u32);
+        if bit {
+            self.low += bound as u64;
+            self.range -= bound;
+            *p -= *p >> MOVE_BITS;
+        } else {
+            self.range = bound;
+            *p += ((1 << PROB_BITS) - *p) >> MOVE_BITS;
+        }
+        self.normalize();
+    }
+
+    #[inline]
+    pub fn encode_direct(&mut self, bit: bool) {
+        self.range >>= 1;
+        if bit {
+            self.low += self.range as u64;
+        }
+        self.normalize();
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        for _ in 0..5 {
+            self.shift_low();
+        }
+        self.out
+    }
+}
+
+pub struct RangeDecoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+    range: u32,
+    code: u32,
+}
+
+impl<'a> RangeDecoder<'a> {
+    pub fn new(input: &'a [u8]) -> Result<Self> {
+        let mut d = Self {
+            input,
+            pos: 0,
+            range: u32::MAX,
+            code: 0,
+        };
+        for _ in 0..5 {
+            d.code = (d.code << 8) | d.next_byte()? as u32;
+        }
+        Ok(d)
+    }
+
+    #[inline]
+    fn next_byte(&mut self) -> Result<u8> {
+        let b = *self.input.get(self.pos).ok_or(MoeqiError::Eof)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    /// Bytes consumed so far.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    #[inline]
+    fn normalize(&mut self) -> Result<()> {
+        while self.range < TOP {
+            self.range <<= 8;
+            self.code = (self.code << 8) | self.next_byte()? as u32;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn decode_bit(&mut self, p: &mut u16) -> Result<bool> {
+        let bound = (self.range >> PROB_BITS) * (*p as u32);
+        let bit = if self.code < bound {
+            self.range = bound;
+            *p += ((1 << PROB_BITS) - *p) >> MOVE_BITS;
+            false
+        } else {
+            self.code -= bound;
+            self.range -= bound;
+            *p -= *p >> MOVE_BITS;
+            true
+        };
+        self.normalize()?;
+        Ok(bit)
+    }
+
+    #[inline]
+    pub fn decode_direct(&mut self) -> Result<bool> {
+        self.range >>= 1;
+        let bit = self.code >= self.range;
+        if bit {
+            self.code -= self.range;
+        }
+        self.normalize()?;
+        Ok(bit)
+    }
+}
+
+/// Context models for `channels` interleaved channels.
+#[derive(Clone)]
+pub struct ResidualModels {
+    models: Vec<Model>,
+}
+
+impl ResidualModels {
+    pub fn new(channels: usize) -> Self {
+        Self {
+            models: vec![Model::default(); channels * CONTEXTS],
+        }
+    }
+
+    pub fn encode(&mut self, enc: &mut RangeEncoder, channel: usize, ctx: usize, z: u32) {
+        let m = &mut self.models[channel * CONTEXTS + ctx];
+        let k = (32 - z.leading_zeros()) as usize;
+        for i in 0..k {
+            enc.encode_bit(&mut m.unary[i], true);
+        }
+        if k < MAX_K {
+            enc.encode_bit(&mut m.unary[k], false);
+        }
+        if k >= 2 {
+            enc.encode_bit(&mut m.mant[k], (z >> (k - 2)) & 1 != 0);
+            for i in (0..k - 2).rev() {
+                enc.encode_direct((z >> i) & 1 != 0);
+            }
+        }
+    }
+
+    pub fn decode(&mut self, dec: &mut RangeDecoder, channel: usize, ctx: usize) -> Result<u32> {
+        let m = &mut self.models[channel * CONTEXTS + ctx];
+        let mut k = 0usize;
+        while k < MAX_K && dec.decode_bit(&mut m.unary[k])? {
+            k += 1;
+        }
+        if k == 0 {
+            return Ok(0);
+        }
+        let mut z = 1u32;
+        if k >= 2 {
+            z = (z << 1) | dec.decode_bit(&mut m.mant[k])? as u32;
+            for _ in 0..k - 2 {
+                z = (z << 1) | dec.decode_direct()? as u32;
+            }
+        }
+        Ok(z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_mixed_magnitudes() {
+        let mut seed = 1u32;
+        let vals: Vec<(usize, usize, u32)> = (0..20_000)
+            .map(|i| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let z = match seed >> 29 {
+                    0..=4 => seed >> 30,
+                    5 | 6 => (seed >> 20) & 0xff,
+                    _ => seed,
+                };
+                (i % 3, (seed as usize >> 4) % CONTEXTS, z)
+            })
+            .collect();
+
+        let mut models = ResidualModels::new(3);
+        let mut enc = RangeEncoder::default();
+        for &(c, ctx, z) in &vals {
+            models.encode(&mut enc, c, ctx, z);
+        }
+        let bytes = enc.finish();
+
+        let mut models = ResidualModels::new(3);
+        let mut dec = RangeDecoder::new(&bytes).unwrap();
+        for &(c, ctx, z) in &vals {
+            assert_eq!(models.decode(&mut dec, c, ctx).unwrap(), z);
+        }
+        assert_eq!(dec.position(), bytes.len());
+    }
+}
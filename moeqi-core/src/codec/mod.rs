@@ -1,76 +1,314 @@
+mod alpha;
+pub mod arith;
+pub mod palette;
+pub mod predict;
 pub mod quant;
+pub mod tile;
 pub mod varint;
 
+use crate::color;
 use crate::error::{MoeqiError, Result};
-use crate::types::{CodecConfig, ColorTransform, Image, PixelFormat};
+use crate::parallel;
+use crate::types::{
+    ChannelLayout, ChromaSiting, CodecConfig, CodecKind, ColorTransform, Image, PixelFormat,
+    Upsampling,
+};
+use predict::predict;
 use quant::SignedUniformQuant;
+use tile::TileGrid;
 
 #[inline]
-fn zigzag_i16(v: i16) -> u16 {
-    let x = v as i32;
-    ((x << 1) ^ (x >> 15)) as u16
+fn zigzag_i32(v: i32) -> u32 {
+    ((v << 1) ^ (v >> 31)) as u32
 }
 #[inline]
-fn unzigzag_u16(v: u16) -> i16 {
-    let x = v as i32;
-    ((x >> 1) ^ (-(x & 1))) as i16
+fn unzigzag_u32(v: u32) -> i32 {
+    ((v >> 1) as i32) ^ -((v & 1) as i32)
 }
 
-/// Encode image pixels to payload bytes (no container header).
-pub fn encode_payload(img: &Image, cfg: CodecConfig) -> Result<Vec<u8>> {
-    if !img.validate() {
-        return Err(MoeqiError::InvalidData("image data length mismatch"));
+/// Left, up and up-left neighbours of sample `idx` (at `x, y`) in the same
+/// channel, from already-coded samples; 0 outside the image.
+#[inline]
+fn neighbours(
+    seen: &[i32],
+    idx: usize,
+    stride: usize,
+    ch: usize,
+    x: usize,
+    y: usize,
+) -> (i32, i32, i32) {
+    let a = if x > 0 { seen[idx - ch] } else { 0 };
+    let b = if y > 0 { seen[idx - stride] } else { 0 };
+    let c = if x > 0 && y > 0 {
+        seen[idx - stride - ch]
+    } else {
+        0
+    };
+    (a, b, c)
+}
+
+/// Entropy-coding back end of [`encode_payload`], picked by [`CodecKind`].
+enum ResidualWriter {
+    Varint(Vec<u8>),
+    /// Residuals are multiples of `step`, so only the quotient is coded.
+    Arith {
+        enc: arith::RangeEncoder,
+        models: arith::ResidualModels,
+        step: i32,
+    },
+}
+
+impl ResidualWriter {
+    fn new(codec: CodecKind, channels: usize, step: i32, capacity: usize) -> Self {
+        match codec {
+            CodecKind::PredictVarint => ResidualWriter::Varint(Vec::with_capacity(capacity)),
+            CodecKind::PredictArith => ResidualWriter::Arith {
+                enc: arith::RangeEncoder::default(),
+                // one more for run lengths
+                models: arith::ResidualModels::new(channels + 1),
+                step,
+            },
+        }
     }
 
-    let mut buf = img.data.clone();
+    #[inline]
+    fn put(&mut self, res: i32, channel: usize, ctx: usize) {
+        match self {
+            ResidualWriter::Varint(out) => varint::encode_u32_var(zigzag_i32(res), out),
+            ResidualWriter::Arith { enc, models, step } => {
+                models.encode(enc, channel, ctx, zigzag_i32(res / *step))
+            }
+        }
+    }
 
-    if cfg.color_transform == ColorTransform::YCoCgR {
-        match img.format {
-            PixelFormat::Rgb8 => rgb_to_ycocg(&mut buf, false),
-            PixelFormat::Rgba8 => rgb_to_ycocg(&mut buf, true),
-            PixelFormat::Gray8 => {}
+    /// A run length; `channels` is the first model past the sample ones.
+    #[inline]
+    fn put_run(&mut self, len: u32, channels: usize) {
+        match self {
+            ResidualWriter::Varint(out) => varint::encode_u32_var(len, out),
+            ResidualWriter::Arith { enc, models, .. } => models.encode(enc, channels, 0, len),
         }
     }
 
-    let q = if cfg.quant_bits == 0 {
-        None
-    } else {
-        Some(SignedUniformQuant::new(cfg.quant_bits))
-    };
+    fn finish(self) -> Vec<u8> {
+        match self {
+            ResidualWriter::Varint(out) => out,
+            ResidualWriter::Arith { enc, .. } => enc.finish(),
+        }
+    }
+}
 
-    let ch = img.format.channels();
-    let w = img.width as usize;
-    let h = img.height as usize;
+/// Decoding counterpart of [`ResidualWriter`].
+enum ResidualReader<'a> {
+    Varint {
+        payload: &'a [u8],
+        pos: usize,
+    },
+    Arith {
+        dec: arith::RangeDecoder<'a>,
+        models: arith::ResidualModels,
+        step: i32,
+    },
+}
 
-    let mut out = Vec::with_capacity(buf.len() / 2);
+impl<'a> ResidualReader<'a> {
+    fn new(codec: CodecKind, payload: &'a [u8], channels: usize, step: i32) -> Result<Self> {
+        Ok(match codec {
+            CodecKind::PredictVarint => ResidualReader::Varint { payload, pos: 0 },
+            CodecKind::PredictArith => ResidualReader::Arith {
+                dec: arith::RangeDecoder::new(payload)?,
+                models: arith::ResidualModels::new(channels + 1),
+                step,
+            },
+        })
+    }
 
-    for y in 0..h {
-        for c in 0..ch {
-            let mut prev: i16 = 0;
-            for x in 0..w {
-                let idx = (y * w + x) * ch + c;
-                let cur = buf[idx] as i16;
+    #[inline]
+    fn get_run(&mut self, channels: usize) -> Result<u32> {
+        match self {
+            ResidualReader::Varint { payload, pos } => {
+                let (len, used) = varint::decode_u32_var(&payload[*pos..])?;
+                *pos += used;
+                Ok(len)
+            }
+            ResidualReader::Arith { dec, models, .. } => models.decode(dec, channels, 0),
+        }
+    }
 
-                let mut res = cur - prev;
-                if let Some(q) = &q {
-                    res = q.quantize(res);
-                }
+    #[inline]
+    fn get(&mut self, channel: usize, ctx: usize) -> Result<i32> {
+        match self {
+            ResidualReader::Varint { payload, pos } => {
+                let (zz, used) = varint::decode_u32_var(&payload[*pos..])?;
+                *pos += used;
+                Ok(unzigzag_u32(zz))
+            }
+            ResidualReader::Arith { dec, models, step } => {
+                let zz = models.decode(dec, channel, ctx)?;
+                Ok(unzigzag_u32(zz).wrapping_mul(*step))
+            }
+        }
+    }
+}
 
-                varint::encode_u32_var(zigzag_i16(res) as u32, &mut out);
+/// How the samples of a tile become coded planes.
+#[derive(Clone, Copy)]
+enum Mode {
+    /// One plane with interleaved channels, optionally through YCoCg-R.
+    Interleaved { ycocg: bool },
+    /// One plane per channel, optionally through YCoCg-R.
+    Planar { ycocg: bool },
+    /// Y, Cb, Cr (and alpha) planes, chroma decimated by `factors`.
+    YCbCr {
+        factors: (usize, usize),
+        siting: ChromaSiting,
+        upsampling: Upsampling,
+    },
+}
 
-                // 👇 THIS is the anti-artifact rule:
-                // update predictor using reconstructed value (same as decoder).
-                if cfg.strict_recon {
-                    let recon = (prev + res).clamp(0, 255);
-                    prev = recon;
-                } else {
-                    prev = cur;
+/// Sample layout of one encode/decode call.
+struct Layout {
+    ch: usize,
+    depth: u8,
+    mode: Mode,
+    /// Channel 3 is alpha, coded apart from colour.
+    alpha: bool,
+    /// Inclusive range of each coded channel.
+    range: Vec<(i32, i32)>,
+}
+
+impl Layout {
+    fn new(format: PixelFormat, cfg: CodecConfig) -> Result<Self> {
+        let depth = format
+            .bit_depth(cfg.bit_depth)
+            .ok_or(MoeqiError::InvalidData(
+                "bit depth does not fit the pixel format",
+            ))?;
+        let max = (1i32 << depth) - 1;
+        let ch = format.channels();
+        // near-lossless bounds the error per source channel, so no transform
+        let colour = ch >= 3 && cfg.max_abs_error == 0;
+        let planar = cfg.layout == ChannelLayout::Planar;
+        let ycocg = colour && cfg.color_transform == ColorTransform::YCoCgR;
+        let alpha = ch == 4 && cfg.alpha.separate;
+        let mode = match cfg.color_transform {
+            ColorTransform::YCbCr {
+                subsampling,
+                siting,
+                upsampling,
+            } => {
+                if ch < 3 {
+                    return Err(MoeqiError::Unsupported("YCbCr needs an RGB image"));
+                }
+                if cfg.max_abs_error != 0 {
+                    return Err(MoeqiError::Unsupported(
+                        "YCbCr does not bound the error; use near-lossless without it",
+                    ));
+                }
+                if depth != 8 {
+                    return Err(MoeqiError::Unsupported("YCbCr needs 8-bit samples"));
+                }
+                Mode::YCbCr {
+                    factors: subsampling.factors(),
+                    siting,
+                    upsampling,
                 }
             }
+            _ if planar => Mode::Planar { ycocg },
+            _ if cfg.has_plane_overrides() && !alpha => {
+                return Err(MoeqiError::InvalidData(
+                    "per-plane parameters need separately coded planes",
+                ))
+            }
+            _ => Mode::Interleaved { ycocg },
+        };
+        let mut range = vec![(0, max); ch];
+        if ycocg {
+            // Co and Cg are differences of two samples
+            range[1] = (-max, max);
+            range[2] = (-max, max);
+        }
+        Ok(Self {
+            ch,
+            depth,
+            mode,
+            alpha,
+            range,
+        })
+    }
+
+    /// Layout of the colour channels once alpha is split off.
+    fn colour(&self) -> Self {
+        Self {
+            ch: 3,
+            depth: self.depth,
+            mode: self.mode,
+            alpha: false,
+            range: self.range[..3].to_vec(),
         }
     }
 
-    Ok(out)
+    /// Layout of channel `c` coded on its own.
+    fn plane(&self, c: usize) -> Self {
+        Self {
+            ch: 1,
+            depth: self.depth,
+            mode: Mode::Interleaved { ycocg: false },
+            alpha: false,
+            range: vec![self.range[c]],
+        }
+    }
+
+    fn quant(&self, cfg: CodecConfig) -> Option<SignedUniformQuant> {
+        match (cfg.max_abs_error, cfg.quant_bits) {
+            (0, 0) => None,
+            (0, bits) => Some(SignedUniformQuant::with_depth(bits, self.depth)),
+            (near, _) => Some(SignedUniformQuant::near_lossless(near)),
+        }
+    }
+
+    /// Context bucket; activity is measured on the 8-bit scale.
+    #[inline]
+    fn context(&self, a: i32, b: i32, c: i32) -> usize {
+        let s = self.depth - 8;
+        arith::context(a >> s, b >> s, c >> s)
+    }
+}
+
+/// Encode image pixels to payload bytes (no container header).
+///
+/// Tiled configs have no single payload; use [`encode_tiles`].
+pub fn encode_payload(img: &Image, cfg: CodecConfig) -> Result<Vec<u8>> {
+    if cfg.tile_size != 0 {
+        return Err(MoeqiError::Unsupported("tiled config needs a tile index"));
+    }
+    let layout = Layout::new(img.format, cfg)?;
+    let buf = source_samples(img, &layout, cfg)?;
+    let w = img.width as usize;
+    let h = img.height as usize;
+    Ok(encode_tile(buf, w, h, &layout, cfg))
+}
+
+/// Encode each tile of `cfg.tile_size` independently, one stream per tile in
+/// [`TileGrid`] order. An untiled config yields a single stream equal to
+/// [`encode_payload`].
+pub fn encode_tiles(img: &Image, cfg: CodecConfig) -> Result<Vec<Vec<u8>>> {
+    let layout = Layout::new(img.format, cfg)?;
+    let buf = source_samples(img, &layout, cfg)?;
+    let ch = layout.ch;
+    let stride = img.width as usize * ch;
+    let grid = TileGrid::new(img.width, img.height, cfg.tile_size);
+
+    parallel::map_tiles(grid.count(), |i| {
+        let (x, y, w, h) = grid.rect(i);
+        let (x, y, w, h) = (x as usize, y as usize, w as usize, h as usize);
+        let mut tile_buf = Vec::with_capacity(w * h * ch);
+        for row in y..y + h {
+            let start = row * stride + x * ch;
+            tile_buf.extend_from_slice(&buf[start..start + w * ch]);
+        }
+        Ok(encode_tile(tile_buf, w, h, &layout, cfg))
+    })
 }
 
 /// Decode payload to Image (no container header).
@@ -81,84 +319,1125 @@ pub fn decode_payload(
     format: PixelFormat,
     cfg: CodecConfig,
 ) -> Result<Image> {
-    let q = if cfg.quant_bits == 0 {
-        None
+    if cfg.tile_size != 0 {
+        return Err(MoeqiError::Unsupported("tiled config needs a tile index"));
+    }
+    let layout = Layout::new(format, cfg)?;
+    let buf = decode_tile(payload, width as usize, height as usize, &layout, cfg)?;
+    Ok(to_image(&buf, width, height, format))
+}
+
+/// Decode the `w`×`h` region at `(x, y)` of a tiled image, touching only the
+/// tiles that overlap it. `tiles` holds every tile stream in [`TileGrid`] order.
+#[allow(clippy::too_many_arguments)]
+pub fn decode_region(
+    tiles: &[&[u8]],
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+    cfg: CodecConfig,
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+) -> Result<Image> {
+    if x as u64 + w as u64 > width as u64 || y as u64 + h as u64 > height as u64 {
+        return Err(MoeqiError::InvalidData("region outside the image"));
+    }
+    let grid = TileGrid::new(width, height, cfg.tile_size);
+    if tiles.len() != grid.count() {
+        return Err(MoeqiError::InvalidData("tile count mismatch"));
+    }
+
+    let layout = Layout::new(format, cfg)?;
+    let ch = layout.ch;
+    let stride = w as usize * ch;
+    let needed: Vec<usize> = grid.overlapping(x, y, w, h).collect();
+    let decoded = parallel::map_tiles(needed.len(), |k| {
+        let (_, _, tw, th) = grid.rect(needed[k]);
+        decode_tile(tiles[needed[k]], tw as usize, th as usize, &layout, cfg)
+    })?;
+
+    let mut buf = vec![0i32; h as usize * stride];
+    for (&i, tile) in needed.iter().zip(&decoded) {
+        let (tx, ty, tw, th) = grid.rect(i);
+        // intersection of the tile and the region, in image coordinates
+        let (x0, x1) = (tx.max(x), (tx + tw).min(x + w));
+        let (y0, y1) = (ty.max(y), (ty + th).min(y + h));
+        let len = (x1 - x0) as usize * ch;
+        for row in y0..y1 {
+            let src = ((row - ty) as usize * tw as usize + (x0 - tx) as usize) * ch;
+            let dst = (row - y) as usize * stride + (x0 - x) as usize * ch;
+            buf[dst..dst + len].copy_from_slice(&tile[src..src + len]);
+        }
+    }
+    Ok(to_image(&buf, w, h, format))
+}
+
+/// Samples of `img`, widened and checked against the bit depth, with the
+/// alpha options of `cfg` applied.
+fn source_samples(img: &Image, layout: &Layout, cfg: CodecConfig) -> Result<Vec<i32>> {
+    if !img.validate() {
+        return Err(MoeqiError::InvalidData("image data length mismatch"));
+    }
+    let max = (1u32 << layout.depth) - 1;
+    let mut buf = Vec::with_capacity(img.data.len() / img.format.bytes_per_sample());
+    for v in img.samples() {
+        if v as u32 > max {
+            return Err(MoeqiError::InvalidData("sample exceeds bit depth"));
+        }
+        buf.push(v as i32);
+    }
+    if layout.ch == 4 {
+        for px in buf.chunks_exact_mut(4) {
+            if cfg.alpha.premultiplied && px[..3].iter().any(|&c| c > px[3]) {
+                return Err(MoeqiError::InvalidData(
+                    "premultiplied colour exceeds alpha",
+                ));
+            }
+            if cfg.alpha.clear_transparent && px[3] == 0 {
+                px[..3].fill(0);
+            }
+        }
+    }
+    Ok(buf)
+}
+
+fn to_image(buf: &[i32], width: u32, height: u32, format: PixelFormat) -> Image {
+    let samples: Vec<u16> = buf.iter().map(|&v| v as u16).collect();
+    Image::from_samples(width, height, format, &samples)
+}
+
+/// Code the interleaved source samples of one tile.
+fn encode_tile(buf: Vec<i32>, w: usize, h: usize, layout: &Layout, cfg: CodecConfig) -> Vec<u8> {
+    if !layout.alpha {
+        return encode_colour(buf, w, h, layout, cfg);
+    }
+    let a: Vec<i32> = buf.iter().skip(3).step_by(4).copied().collect();
+    let rgb: Vec<i32> = buf
+        .chunks_exact(4)
+        .flat_map(|px| &px[..3])
+        .copied()
+        .collect();
+    let kind = alpha::kind(&a, (1 << layout.depth) - 1);
+    let mut out = vec![kind];
+    match kind {
+        alpha::CONSTANT => varint::encode_u32_var(a[0] as u32, &mut out),
+        alpha::MASK => alpha::encode_mask(&a, &mut out),
+        _ => {
+            let coded = encode_plane(&a, w, h, &layout.plane(3), cfg.plane(3));
+            push_plane(&mut out, &coded);
+        }
+    }
+    out.extend(encode_colour(rgb, w, h, &layout.colour(), cfg));
+    out
+}
+
+/// Inverse of [`encode_tile`]: interleaved samples in the source format.
+fn decode_tile(
+    payload: &[u8],
+    w: usize,
+    h: usize,
+    layout: &Layout,
+    cfg: CodecConfig,
+) -> Result<Vec<i32>> {
+    let mut buf = if layout.alpha {
+        let max = (1 << layout.depth) - 1;
+        let (&kind, rest) = payload.split_first().ok_or(MoeqiError::Eof)?;
+        let (a, used) = match kind {
+            alpha::CONSTANT => {
+                let (v, used) = varint::decode_u32_var(rest)?;
+                if v > max as u32 {
+                    return Err(MoeqiError::InvalidData("alpha exceeds bit depth"));
+                }
+                (vec![v as i32; w * h], used)
+            }
+            alpha::MASK => alpha::decode_mask(rest, w * h, max)?,
+            alpha::PLANE => {
+                let mut planes = PlaneReader {
+                    payload: rest,
+                    pos: 0,
+                };
+                let a = decode_plane(planes.next()?, w, h, &layout.plane(3), cfg.plane(3))?;
+                (a, planes.pos)
+            }
+            _ => return Err(MoeqiError::InvalidData("bad alpha kind")),
+        };
+        let rgb = decode_colour(&rest[used..], w, h, &layout.colour(), cfg)?;
+        rgb.chunks_exact(3)
+            .zip(a)
+            .flat_map(|(px, a)| [px[0], px[1], px[2], a])
+            .collect()
     } else {
-        Some(SignedUniformQuant::new(cfg.quant_bits))
+        decode_colour(payload, w, h, layout, cfg)?
     };
+    if layout.ch == 4 && cfg.alpha.premultiplied {
+        for px in buf.chunks_exact_mut(4) {
+            let a = px[3];
+            px[..3].iter_mut().for_each(|c| *c = (*c).min(a));
+        }
+    }
+    Ok(buf)
+}
+
+/// Colour-transform and code interleaved samples.
+fn encode_colour(
+    mut buf: Vec<i32>,
+    w: usize,
+    h: usize,
+    layout: &Layout,
+    cfg: CodecConfig,
+) -> Vec<u8> {
+    let ch = layout.ch;
+    match layout.mode {
+        Mode::Interleaved { ycocg } => {
+            if ycocg {
+                rgb_to_ycocg(&mut buf, ch);
+            }
+            encode_plane(&buf, w, h, layout, cfg)
+        }
+        Mode::Planar { ycocg } => {
+            if ycocg {
+                rgb_to_ycocg(&mut buf, ch);
+            }
+            let mut out = Vec::new();
+            for c in 0..ch {
+                let plane: Vec<i32> = buf.iter().skip(c).step_by(ch).copied().collect();
+                let coded = encode_plane(&plane, w, h, &layout.plane(c), cfg.plane(c));
+                push_plane(&mut out, &coded);
+            }
+            out
+        }
+        Mode::YCbCr {
+            factors, siting, ..
+        } => {
+            let rgb: Vec<u8> = buf
+                .chunks_exact(ch)
+                .flat_map(|px| [px[0] as u8, px[1] as u8, px[2] as u8])
+                .collect();
+            let (y, cb, cr) = color::rgb_to_ycbcr_planar(&rgb, w, h);
+            let (cb, cw, chh) = color::downsample(&cb, w, h, factors, siting);
+            let (cr, _, _) = color::downsample(&cr, w, h, factors, siting);
+
+            let mut planes = vec![(y, w, h), (cb, cw, chh), (cr, cw, chh)];
+            if ch == 4 {
+                planes.push((
+                    buf.iter().skip(3).step_by(4).map(|&a| a as u8).collect(),
+                    w,
+                    h,
+                ));
+            }
+            let mut out = Vec::new();
+            for (c, (samples, pw, ph)) in planes.into_iter().enumerate() {
+                let samples: Vec<i32> = samples.into_iter().map(i32::from).collect();
+                let coded = encode_plane(&samples, pw, ph, &layout.plane(c), cfg.plane(c));
+                push_plane(&mut out, &coded);
+            }
+            out
+        }
+    }
+}
+
+/// Inverse of [`encode_colour`].
+fn decode_colour(
+    payload: &[u8],
+    w: usize,
+    h: usize,
+    layout: &Layout,
+    cfg: CodecConfig,
+) -> Result<Vec<i32>> {
+    let ch = layout.ch;
+    let max = (1 << layout.depth) - 1;
+    match layout.mode {
+        Mode::Interleaved { ycocg } => {
+            let mut buf = decode_plane(payload, w, h, layout, cfg)?;
+            if ycocg {
+                ycocg_to_rgb(&mut buf, ch, max);
+            }
+            Ok(buf)
+        }
+        Mode::Planar { ycocg } => {
+            let mut planes = PlaneReader { payload, pos: 0 };
+            let mut buf = vec![0i32; w * h * ch];
+            for c in 0..ch {
+                let plane = decode_plane(planes.next()?, w, h, &layout.plane(c), cfg.plane(c))?;
+                for (dst, v) in buf.iter_mut().skip(c).step_by(ch).zip(plane) {
+                    *dst = v;
+                }
+            }
+            if ycocg {
+                ycocg_to_rgb(&mut buf, ch, max);
+            }
+            Ok(buf)
+        }
+        Mode::YCbCr {
+            factors,
+            siting,
+            upsampling,
+        } => {
+            let (cw, chh) = (w.div_ceil(factors.0), h.div_ceil(factors.1));
+            let mut planes = PlaneReader { payload, pos: 0 };
+            let mut next = |c: usize, pw: usize, ph: usize| -> Result<Vec<u8>> {
+                let samples = decode_plane(planes.next()?, pw, ph, &layout.plane(c), cfg.plane(c))?;
+                Ok(samples.into_iter().map(|v| v as u8).collect())
+            };
+            let y = next(0, w, h)?;
+            let cb = next(1, cw, chh)?;
+            let cr = next(2, cw, chh)?;
+            let alpha = if ch == 4 { next(3, w, h)? } else { Vec::new() };
+
+            let up = |c: &[u8]| color::upsample(c, (cw, chh), (w, h), factors, siting, upsampling);
+            let rgb = color::ycbcr_to_rgb_planar(&y, &up(&cb), &up(&cr), w, h);
+            let mut buf = Vec::with_capacity(w * h * ch);
+            for (i, px) in rgb.chunks_exact(3).enumerate() {
+                buf.extend(px.iter().map(|&v| v as i32));
+                if ch == 4 {
+                    buf.push(alpha[i] as i32);
+                }
+            }
+            Ok(buf)
+        }
+    }
+}
+
+/// Append one separately coded plane, prefixed with its varint length.
+fn push_plane(out: &mut Vec<u8>, coded: &[u8]) {
+    varint::encode_u32_var(coded.len() as u32, out);
+    out.extend_from_slice(coded);
+}
+
+/// Splits a tile payload back into the planes of [`push_plane`].
+struct PlaneReader<'a> {
+    payload: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PlaneReader<'a> {
+    fn next(&mut self) -> Result<&'a [u8]> {
+        let (len, used) = varint::decode_u32_var(&self.payload[self.pos..])?;
+        let start = self.pos + used;
+        let plane = self
+            .payload
+            .get(start..start + len as usize)
+            .ok_or(MoeqiError::Eof)?;
+        self.pos = start + len as usize;
+        Ok(plane)
+    }
+}
+
+/// Predict and entropy-code one tightly packed `w`×`h` buffer of samples.
+///
+/// With [`CodecConfig::run_mode`] the plane is coded with and without runs
+/// and the smaller wins, announced by a leading byte. The range coder
+/// already spends next to nothing on zero residuals, so runs mostly pay
+/// off with the varint back end.
+fn encode_plane(buf: &[i32], w: usize, h: usize, layout: &Layout, cfg: CodecConfig) -> Vec<u8> {
+    if !cfg.run_mode {
+        return code_plane(buf, w, h, layout, cfg, false);
+    }
+    let mut plain = vec![0];
+    plain.extend(code_plane(buf, w, h, layout, cfg, false));
+    let mut runs = vec![1];
+    runs.extend(code_plane(buf, w, h, layout, cfg, true));
+    if runs.len() < plain.len() {
+        runs
+    } else {
+        plain
+    }
+}
+
+/// Whether sample `idx` starts a run: its left, up and up-left neighbours
+/// are equal, or on the first row, its two left neighbours.
+#[inline]
+fn starts_run(seen: &[i32], idx: usize, ch: usize, x: usize, y: usize, n: (i32, i32, i32)) -> bool {
+    let (a, b, ul) = n;
+    match y {
+        0 => x >= 2 && a == seen[idx - 2 * ch],
+        _ => x > 0 && a == b && b == ul,
+    }
+}
 
-    let ch = format.channels();
-    let w = width as usize;
-    let h = height as usize;
+/// Longest run one length may cover; a length of exactly `MAX_RUN` is
+/// followed by another. Keeps the payload proportional to the row width,
+/// which the container relies on to bound the dimensions it accepts.
+pub(crate) const MAX_RUN: usize = 256;
 
-    let mut data = vec![0u8; w * h * ch];
-    let mut i = 0usize;
+/// [`encode_plane`] for one choice of run mode. In run mode a sample that
+/// [`starts_run`] is followed by lengths (see [`MAX_RUN`]) counting the
+/// samples from there on that reconstruct to the left neighbour; the sample
+/// breaking the run (if any before the row ends) is coded normally.
+fn code_plane(
+    buf: &[i32],
+    w: usize,
+    h: usize,
+    layout: &Layout,
+    cfg: CodecConfig,
+    runs: bool,
+) -> Vec<u8> {
+    let ch = layout.ch;
+    let q = layout.quant(cfg);
+    let step = q.as_ref().map_or(1, |q| q.step());
+    let mut out = ResidualWriter::new(cfg.codec, ch, step, buf.len() / 2);
+    // what the predictor sees: reconstructed samples under strict_recon, source
+    // otherwise. Contexts and runs always come from `recon`, the decoder's view.
+    let strict = cfg.strict_recon || cfg.max_abs_error != 0;
+    let quantize = |res: i32| q.as_ref().map_or(res, |q| q.quantize(res));
+    let mut recon = vec![0i32; buf.len()];
 
     for y in 0..h {
         for c in 0..ch {
-            let mut prev: i16 = 0;
-            for x in 0..w {
-                let (zz, used) = varint::decode_u32_var(&payload[i..])?;
-                i += used;
+            let (lo, hi) = layout.range[c];
+            let mut x = 0;
+            while x < w {
+                let idx = (y * w + x) * ch + c;
+                let mut n_recon = neighbours(&recon, idx, w * ch, ch, x, y);
 
-                let mut res = unzigzag_u16(zz as u16);
-                if let Some(q) = &q {
-                    res = q.dequantize(res);
+                if runs && starts_run(&recon, idx, ch, x, y, n_recon) {
+                    let a = n_recon.0;
+                    let mut n = 0;
+                    while x + n < w && quantize(buf[idx + n * ch] - a) == 0 {
+                        recon[idx + n * ch] = a;
+                        n += 1;
+                    }
+                    let mut left = n;
+                    loop {
+                        let len = left.min(MAX_RUN);
+                        out.put_run(len as u32, ch);
+                        left -= len;
+                        if len < MAX_RUN {
+                            break;
+                        }
+                    }
+                    x += n;
+                    if x == w {
+                        break;
+                    }
+                    n_recon = neighbours(&recon, idx + n * ch, w * ch, ch, x, y);
                 }
 
-                let cur = (prev + res).clamp(0, 255);
                 let idx = (y * w + x) * ch + c;
-                data[idx] = cur as u8;
-                prev = cur;
+                let (a, b, ul) = match strict {
+                    true => n_recon,
+                    false => neighbours(buf, idx, w * ch, ch, x, y),
+                };
+                let res = quantize(buf[idx] - predict(cfg.predictor, a, b, ul, x, y));
+
+                let (a, b, ul) = n_recon;
+                out.put(res, c, layout.context(a, b, ul));
+
+                // 👇 THIS is the anti-artifact rule:
+                // update predictor using reconstructed value (same as decoder).
+                let pred = predict(cfg.predictor, a, b, ul, x, y);
+                recon[idx] = pred.saturating_add(res).clamp(lo, hi);
+                x += 1;
             }
         }
     }
 
-    if cfg.color_transform == ColorTransform::YCoCgR {
-        match format {
-            PixelFormat::Rgb8 => ycocg_to_rgb(&mut data, false),
-            PixelFormat::Rgba8 => ycocg_to_rgb(&mut data, true),
-            PixelFormat::Gray8 => {}
+    out.finish()
+}
+
+/// Inverse of [`encode_plane`]; colour transform not undone.
+fn decode_plane(
+    payload: &[u8],
+    w: usize,
+    h: usize,
+    layout: &Layout,
+    cfg: CodecConfig,
+) -> Result<Vec<i32>> {
+    let ch = layout.ch;
+    let q = layout.quant(cfg);
+    let step = q.as_ref().map_or(1, |q| q.step());
+    let mut seen = vec![0i32; w * h * ch];
+    let (runs, payload) = match cfg.run_mode {
+        true => match payload.split_first() {
+            Some((&flag @ (0 | 1), rest)) => (flag == 1, rest),
+            Some(_) => return Err(MoeqiError::InvalidData("bad run mode flag")),
+            None => return Err(MoeqiError::Eof),
+        },
+        false => (false, payload),
+    };
+    let mut input = ResidualReader::new(cfg.codec, payload, ch, step)?;
+
+    for y in 0..h {
+        for c in 0..ch {
+            let (lo, hi) = layout.range[c];
+            let mut x = 0;
+            while x < w {
+                let idx = (y * w + x) * ch + c;
+                let (mut a, mut b, mut ul) = neighbours(&seen, idx, w * ch, ch, x, y);
+
+                if runs && starts_run(&seen, idx, ch, x, y, (a, b, ul)) {
+                    let mut n = 0;
+                    loop {
+                        let len = input.get_run(ch)? as usize;
+                        if len > MAX_RUN || len > w - x - n {
+                            return Err(MoeqiError::InvalidData("run overruns the row"));
+                        }
+                        n += len;
+                        if len < MAX_RUN {
+                            break;
+                        }
+                    }
+                    for k in 0..n {
+                        seen[idx + k * ch] = a;
+                    }
+                    x += n;
+                    if x == w {
+                        break;
+                    }
+                    (a, b, ul) = neighbours(&seen, idx + n * ch, w * ch, ch, x, y);
+                }
+
+                let idx = (y * w + x) * ch + c;
+                let mut res = input.get(c, layout.context(a, b, ul))?;
+                if let Some(q) = &q {
+                    res = q.dequantize(res);
+                }
+
+                let pred = predict(cfg.predictor, a, b, ul, x, y);
+                seen[idx] = pred.saturating_add(res).clamp(lo, hi);
+                x += 1;
+            }
         }
     }
 
-    Ok(Image { width, height, format, data })
+    Ok(seen)
 }
 
-// --- Reversible YCoCg-R in 8-bit lanes (with i16 math) ---
+// --- Reversible YCoCg-R; Co/Cg need one bit more than the samples ---
 
-fn rgb_to_ycocg(buf: &mut [u8], has_alpha: bool) {
-    let stride = if has_alpha { 4 } else { 3 };
-    for px in buf.chunks_exact_mut(stride) {
-        let r = px[0] as i16;
-        let g = px[1] as i16;
-        let b = px[2] as i16;
+fn rgb_to_ycocg(buf: &mut [i32], ch: usize) {
+    for px in buf.chunks_exact_mut(ch) {
+        let (r, g, b) = (px[0], px[1], px[2]);
 
         let co = r - b;
         let t = b + (co >> 1);
         let cg = g - t;
         let y = t + (cg >> 1);
 
-        px[0] = (y & 0xFF) as u8;
-        px[1] = (co & 0xFF) as u8;
-        px[2] = (cg & 0xFF) as u8;
+        px[0] = y;
+        px[1] = co;
+        px[2] = cg;
     }
 }
 
-fn ycocg_to_rgb(buf: &mut [u8], has_alpha: bool) {
-    let stride = if has_alpha { 4 } else { 3 };
-    for px in buf.chunks_exact_mut(stride) {
-        let y = px[0] as i16;
-        let co = px[1] as i16;
-        let cg = px[2] as i16;
+fn ycocg_to_rgb(buf: &mut [i32], ch: usize, max: i32) {
+    for px in buf.chunks_exact_mut(ch) {
+        let (y, co, cg) = (px[0], px[1], px[2]);
 
         let t = y - (cg >> 1);
         let g = cg + t;
         let b = t - (co >> 1);
         let r = b + co;
 
-        px[0] = r.clamp(0, 255) as u8;
-        px[1] = g.clamp(0, 255) as u8;
-        px[2] = b.clamp(0, 255) as u8;
+        px[0] = r.clamp(0, max);
+        px[1] = g.clamp(0, max);
+        px[2] = b.clamp(0, max);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AlphaConfig, CodecKind, PlaneConfig, Predictor, Subsampling};
+    use proptest::prelude::*;
+
+    const PREDICTORS: [Predictor; 5] = [
+        Predictor::Left,
+        Predictor::Up,
+        Predictor::Average,
+        Predictor::Paeth,
+        Predictor::Med,
+    ];
+
+    const FORMATS: [PixelFormat; 6] = [
+        PixelFormat::Gray8,
+        PixelFormat::Rgb8,
+        PixelFormat::Rgba8,
+        PixelFormat::Gray16,
+        PixelFormat::Rgb16,
+        PixelFormat::Rgba16,
+    ];
+
+    /// Diagonal ramps per channel plus some noise, at `depth` bits.
+    fn sample_depth(w: u32, h: u32, format: PixelFormat, depth: u8) -> Image {
+        let ch = format.channels() as u32;
+        let scale = 1u32 << (depth - 8);
+        let mut seed = 0x9e37_79b9u32;
+        let samples: Vec<u16> = (0..w * h * ch)
+            .map(|i| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let (p, c) = (i / ch, i % ch);
+                let (x, y) = (p % w, p / w);
+                let v = (x * (c + 1) + y * 3) * scale + (seed >> (28 - depth + 8));
+                (v % (256 * scale)) as u16
+            })
+            .collect();
+        Image::from_samples(w, h, format, &samples)
+    }
+
+    fn sample(w: u32, h: u32, format: PixelFormat) -> Image {
+        sample_depth(w, h, format, 8 * format.bytes_per_sample() as u8)
+    }
+
+    #[test]
+    fn lossless_roundtrip_every_predictor() {
+        for format in FORMATS {
+            let img = sample(19, 11, format);
+            for codec in [CodecKind::PredictVarint, CodecKind::PredictArith] {
+                for (i, predictor) in PREDICTORS.into_iter().enumerate() {
+                    let color_transform = match i % 2 {
+                        0 => ColorTransform::YCoCgR,
+                        _ => ColorTransform::None,
+                    };
+                    let cfg = CodecConfig {
+                        codec,
+                        color_transform,
+                        predictor,
+                        ..CodecConfig::default()
+                    };
+                    let payload = encode_payload(&img, cfg).unwrap();
+                    let out = decode_payload(&payload, img.width, img.height, format, cfg).unwrap();
+                    assert_eq!(out, img, "{codec:?} {predictor:?} {format:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn partial_bit_depths() {
+        let img = sample_depth(17, 9, PixelFormat::Rgb16, 12);
+        for quant_bits in [0, 6] {
+            let cfg = CodecConfig {
+                codec: CodecKind::PredictArith,
+                quant_bits,
+                bit_depth: 12,
+                predictor: Predictor::Med,
+                color_transform: ColorTransform::None,
+                ..CodecConfig::default()
+            };
+            let payload = encode_payload(&img, cfg).unwrap();
+            let out = decode_payload(&payload, img.width, img.height, img.format, cfg).unwrap();
+            let step = SignedUniformQuant::with_depth(quant_bits, 12).step();
+            let (a, b) = (img.samples(), out.samples());
+            let tol = if quant_bits == 0 { 0 } else { step / 2 };
+            assert!(a
+                .iter()
+                .zip(&b)
+                .all(|(&x, &y)| (x as i32 - y as i32).abs() <= tol));
+            assert!(b.iter().all(|&v| v < 1 << 12));
+        }
+
+        let too_wide = sample(4, 4, PixelFormat::Gray16);
+        let cfg = CodecConfig {
+            bit_depth: 12,
+            ..CodecConfig::default()
+        };
+        assert!(encode_payload(&too_wide, cfg).is_err());
+        assert!(encode_payload(&sample(4, 4, PixelFormat::Gray8), cfg).is_err());
+    }
+
+    #[test]
+    fn quant_step_scales_with_depth() {
+        let s8 = SignedUniformQuant::with_depth(5, 8).step();
+        assert_eq!(SignedUniformQuant::new(5).step(), s8);
+        assert_eq!(
+            SignedUniformQuant::with_depth(5, 16).step(),
+            (65535 + 14) / 15
+        );
+        assert!(SignedUniformQuant::with_depth(5, 12).step() > s8 * 15);
+    }
+
+    #[test]
+    fn strict_recon_bounds_lossy_error_for_every_predictor() {
+        let img = sample(32, 24, PixelFormat::Gray8);
+        for predictor in PREDICTORS {
+            let cfg = CodecConfig { quant_bits: 5, predictor, ..CodecConfig::default() };
+            let step = SignedUniformQuant::new(cfg.quant_bits).step();
+            let payload = encode_payload(&img, cfg).unwrap();
+            let out = decode_payload(&payload, img.width, img.height, img.format, cfg).unwrap();
+            for (&a, &b) in img.data.iter().zip(out.data.iter()) {
+                assert!((a as i32 - b as i32).abs() <= step / 2, "{predictor:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn strict_recon_bounds_lossy_arith_error() {
+        let img = sample(32, 24, PixelFormat::Gray8);
+        for predictor in PREDICTORS {
+            let cfg = CodecConfig {
+                codec: CodecKind::PredictArith,
+                quant_bits: 5,
+                predictor,
+                ..CodecConfig::default()
+            };
+            let step = SignedUniformQuant::new(cfg.quant_bits).step();
+            let payload = encode_payload(&img, cfg).unwrap();
+            let out = decode_payload(&payload, img.width, img.height, img.format, cfg).unwrap();
+            for (&a, &b) in img.data.iter().zip(&out.data) {
+                assert!((a as i32 - b as i32).abs() <= step / 2, "{predictor:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn non_strict_lossy_arith_roundtrips() {
+        let img = sample(40, 30, PixelFormat::Rgb8);
+        let cfg = CodecConfig {
+            codec: CodecKind::PredictArith,
+            quant_bits: 5,
+            predictor: Predictor::Med,
+            strict_recon: false,
+            ..CodecConfig::default()
+        };
+        let payload = encode_payload(&img, cfg).unwrap();
+        let out = decode_payload(&payload, img.width, img.height, img.format, cfg).unwrap();
+        // same residuals as varint, so the same (drifting) reconstruction
+        let varint = CodecConfig {
+            codec: CodecKind::PredictVarint,
+            ..cfg
+        };
+        let payload = encode_payload(&img, varint).unwrap();
+        let expected = decode_payload(&payload, img.width, img.height, img.format, varint).unwrap();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn non_strict_run_mode_roundtrips() {
+        // flat left half, noisy right half
+        let mut img = sample(32, 16, PixelFormat::Gray8);
+        for row in img.data.chunks_exact_mut(32) {
+            row[..16].fill(120);
+        }
+        let step = SignedUniformQuant::new(4).step();
+        for codec in [CodecKind::PredictVarint, CodecKind::PredictArith] {
+            for strict_recon in [true, false] {
+                let cfg = CodecConfig {
+                    codec,
+                    quant_bits: 4,
+                    run_mode: true,
+                    strict_recon,
+                    ..CodecConfig::default()
+                };
+                let payload = encode_payload(&img, cfg).unwrap();
+                let out = decode_payload(&payload, 32, 16, img.format, cfg).unwrap();
+                if strict_recon {
+                    for (&a, &b) in img.data.iter().zip(&out.data) {
+                        assert!((a as i32 - b as i32).abs() <= step / 2, "{codec:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn vertical_predictors_beat_left_on_vertical_stripes() {
+        let (w, h) = (64u32, 64u32);
+        let data = (0..w * h).map(|i| ((i % w) * 37 % 251) as u8).collect();
+        let img = Image { width: w, height: h, format: PixelFormat::Gray8, data };
+        let size = |predictor| {
+            let cfg = CodecConfig { predictor, ..CodecConfig::default() };
+            encode_payload(&img, cfg).unwrap().len()
+        };
+        assert!(size(Predictor::Up) < size(Predictor::Left));
+        assert!(size(Predictor::Med) < size(Predictor::Left));
+    }
+
+    #[test]
+    fn arith_is_much_smaller_than_varint_on_smooth_images() {
+        let (w, h) = (96u32, 64u32);
+        let data = (0..w * h)
+            .map(|i| ((i % w) / 3 + (i / w) / 2) as u8)
+            .collect();
+        let img = Image {
+            width: w,
+            height: h,
+            format: PixelFormat::Gray8,
+            data,
+        };
+        let size = |codec| {
+            let cfg = CodecConfig {
+                codec,
+                predictor: Predictor::Med,
+                ..CodecConfig::default()
+            };
+            encode_payload(&img, cfg).unwrap().len()
+        };
+        assert!(size(CodecKind::PredictArith) * 4 < size(CodecKind::PredictVarint));
+    }
+
+    #[test]
+    fn tiled_region_matches_full_image() {
+        let img = sample(23, 17, PixelFormat::Rgb8);
+        for codec in [CodecKind::PredictVarint, CodecKind::PredictArith] {
+            let cfg = CodecConfig {
+                codec,
+                predictor: Predictor::Paeth,
+                tile_size: 8,
+                color_transform: ColorTransform::None,
+                ..CodecConfig::default()
+            };
+            let tiles = encode_tiles(&img, cfg).unwrap();
+            assert_eq!(tiles.len(), 9);
+            let tiles: Vec<&[u8]> = tiles.iter().map(Vec::as_slice).collect();
+            let (w, h, f) = (img.width, img.height, img.format);
+            let full = decode_region(&tiles, w, h, f, cfg, 0, 0, w, h).unwrap();
+            assert_eq!(full, img);
+
+            let (x, y, rw, rh) = (5, 7, 12, 3);
+            let region = decode_region(&tiles, w, h, f, cfg, x, y, rw, rh).unwrap();
+            for row in 0..rh as usize {
+                let src = ((y as usize + row) * w as usize + x as usize) * 3;
+                let dst = row * rw as usize * 3;
+                assert_eq!(
+                    region.data[dst..dst + rw as usize * 3],
+                    full.data[src..src + rw as usize * 3]
+                );
+            }
+            assert!(decode_region(&tiles, w, h, f, cfg, 20, 0, 4, 1).is_err());
+        }
+    }
+
+    /// Smooth colour gradients, loosely photo-like.
+    fn smooth(w: u32, h: u32, format: PixelFormat) -> Image {
+        let ch = format.channels() as u32;
+        let data = (0..w * h * ch)
+            .map(|i| {
+                let (p, c) = (i / ch, i % ch);
+                let (x, y) = (p % w, p / w);
+                match c {
+                    0 => (x * 4 + y) % 256,
+                    1 => (y * 3 + 40) % 256,
+                    2 => 255 - (x + y) % 200,
+                    _ => (x * 7 + y * 13) % 256,
+                }
+            })
+            .map(|v| v as u8)
+            .collect();
+        Image {
+            width: w,
+            height: h,
+            format,
+            data,
+        }
+    }
+
+    #[test]
+    fn ycbcr_subsampling_roundtrip() {
+        let ycbcr = |subsampling, upsampling| CodecConfig {
+            codec: CodecKind::PredictArith,
+            predictor: Predictor::Med,
+            color_transform: ColorTransform::YCbCr {
+                subsampling,
+                siting: ChromaSiting::Centered,
+                upsampling,
+            },
+            ..CodecConfig::default()
+        };
+        let img = smooth(61, 37, PixelFormat::Rgba8);
+        let mut sizes = Vec::new();
+        for sub in [Subsampling::S444, Subsampling::S422, Subsampling::S420] {
+            for up in [Upsampling::Nearest, Upsampling::Bilinear] {
+                let cfg = ycbcr(sub, up);
+                let payload = encode_payload(&img, cfg).unwrap();
+                let out = decode_payload(&payload, 61, 37, img.format, cfg).unwrap();
+                assert!(crate::train::eval::psnr(&img, &out).unwrap() > 30.0);
+                // alpha is coded losslessly at full resolution
+                let alpha = |i: &Image| {
+                    i.data
+                        .iter()
+                        .skip(3)
+                        .step_by(4)
+                        .copied()
+                        .collect::<Vec<_>>()
+                };
+                assert_eq!(alpha(&out), alpha(&img));
+                sizes.push(payload.len());
+            }
+        }
+        assert!(sizes[4] < sizes[2] && sizes[2] < sizes[0]);
+
+        // odd tiles decimate their own chroma and reassemble seamlessly
+        let cfg = CodecConfig {
+            tile_size: 16,
+            ..ycbcr(Subsampling::S420, Upsampling::Bilinear)
+        };
+        let tiles = encode_tiles(&img, cfg).unwrap();
+        let tiles: Vec<&[u8]> = tiles.iter().map(Vec::as_slice).collect();
+        let out = decode_region(&tiles, 61, 37, img.format, cfg, 0, 0, 61, 37).unwrap();
+        assert!(crate::train::eval::psnr(&img, &out).unwrap() > 30.0);
+
+        // 16-bit, gray and near-lossless images are refused, not coded without it
+        let cfg = ycbcr(Subsampling::S420, Upsampling::Bilinear);
+        let near = CodecConfig {
+            max_abs_error: 2,
+            ..cfg
+        };
+        for (format, cfg) in [
+            (PixelFormat::Rgb16, cfg),
+            (PixelFormat::Gray8, cfg),
+            (PixelFormat::Rgb8, near),
+        ] {
+            let img = sample(8, 8, format);
+            assert!(matches!(
+                encode_payload(&img, cfg),
+                Err(MoeqiError::Unsupported(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn planar_layout_with_per_plane_parameters() {
+        for format in FORMATS {
+            let img = sample(19, 11, format);
+            let cfg = CodecConfig {
+                codec: CodecKind::PredictArith,
+                layout: ChannelLayout::Planar,
+                ..CodecConfig::default()
+            };
+            let payload = encode_payload(&img, cfg).unwrap();
+            assert_eq!(decode_payload(&payload, 19, 11, format, cfg).unwrap(), img);
+        }
+
+        // lossless alpha and luma, coarse chroma with its own predictor
+        let img = smooth(40, 30, PixelFormat::Rgba8);
+        let chroma = PlaneConfig {
+            predictor: Some(Predictor::Paeth),
+            quant_bits: Some(3),
+            ..PlaneConfig::default()
+        };
+        let cfg = CodecConfig {
+            codec: CodecKind::PredictArith,
+            predictor: Predictor::Med,
+            layout: ChannelLayout::Planar,
+            planes: [
+                PlaneConfig::default(),
+                chroma,
+                chroma,
+                PlaneConfig::default(),
+            ],
+            ..CodecConfig::default()
+        };
+        let lossless = CodecConfig {
+            planes: [PlaneConfig::default(); 4],
+            ..cfg
+        };
+        let payload = encode_payload(&img, cfg).unwrap();
+        let out = decode_payload(&payload, 40, 30, img.format, cfg).unwrap();
+        assert!(payload.len() < encode_payload(&img, lossless).unwrap().len());
+        assert_ne!(out, img);
+        for (a, b) in out.data.chunks(4).zip(img.data.chunks(4)) {
+            assert_eq!(a[3], b[3]);
+        }
+
+        let interleaved = CodecConfig {
+            layout: ChannelLayout::Interleaved,
+            ..cfg
+        };
+        assert!(encode_payload(&img, interleaved).is_err());
+    }
+
+    #[test]
+    fn separate_alpha_kinds() {
+        let (w, h) = (37u32, 29u32);
+        let sprite = |f: &dyn Fn(u32, u32) -> u8| {
+            let mut img = smooth(w, h, PixelFormat::Rgba8);
+            for (i, px) in img.data.chunks_exact_mut(4).enumerate() {
+                px[3] = f(i as u32 % w, i as u32 / w);
+            }
+            img
+        };
+        let cfg = CodecConfig {
+            codec: CodecKind::PredictArith,
+            predictor: Predictor::Med,
+            tile_size: 16,
+            ..CodecConfig::default()
+        };
+        let separate = CodecConfig {
+            alpha: AlphaConfig {
+                separate: true,
+                ..AlphaConfig::default()
+            },
+            ..cfg
+        };
+        let size = |img: &Image, cfg| encode_tiles(img, cfg).unwrap().concat().len();
+        let roundtrip = |img: &Image, cfg| {
+            let tiles = encode_tiles(img, cfg).unwrap();
+            let tiles: Vec<&[u8]> = tiles.iter().map(Vec::as_slice).collect();
+            decode_region(&tiles, w, h, img.format, cfg, 0, 0, w, h).unwrap()
+        };
+
+        let opaque = sprite(&|_, _| 255);
+        let disc = sprite(&|x, y| {
+            if x.abs_diff(18).pow(2) + y.abs_diff(14).pow(2) < 150 {
+                255
+            } else {
+                0
+            }
+        });
+        let soft = sprite(&|x, y| (x * 7 + y) as u8);
+        for img in [&opaque, &disc, &soft] {
+            assert_eq!(&roundtrip(img, separate), img);
+        }
+        assert!(size(&opaque, separate) < size(&opaque, cfg));
+        assert!(size(&disc, separate) < size(&disc, cfg));
+
+        // colour under transparent pixels is dropped
+        let cleared = CodecConfig {
+            alpha: AlphaConfig {
+                clear_transparent: true,
+                ..separate.alpha
+            },
+            ..cfg
+        };
+        let out = roundtrip(&disc, cleared);
+        for (a, b) in out.data.chunks(4).zip(disc.data.chunks(4)) {
+            assert_eq!(a, if b[3] == 0 { &[0; 4] } else { b });
+        }
+
+        // premultiplied colour may not exceed alpha, before or after coding
+        let premultiplied = CodecConfig {
+            quant_bits: 4,
+            alpha: AlphaConfig {
+                premultiplied: true,
+                ..separate.alpha
+            },
+            ..cfg
+        };
+        assert!(encode_tiles(&soft, premultiplied).is_err());
+        let mut pm = soft.clone();
+        for px in pm.data.chunks_exact_mut(4) {
+            for c in 0..3 {
+                px[c] = (px[c] as u32 * px[3] as u32 / 255) as u8;
+            }
+        }
+        let out = roundtrip(&pm, premultiplied);
+        assert!(out
+            .data
+            .chunks(4)
+            .all(|px| px[..3].iter().all(|&c| c <= px[3])));
+    }
+
+    #[test]
+    fn runs_longer_than_max_run_roundtrip() {
+        for w in [MAX_RUN + 1, MAX_RUN + 2, 2 * MAX_RUN + 2, 3 * MAX_RUN + 7] {
+            let mut img = sample(w as u32, 3, PixelFormat::Gray8);
+            img.data[..2 * w].fill(9);
+            for codec in [CodecKind::PredictVarint, CodecKind::PredictArith] {
+                let cfg = CodecConfig {
+                    codec,
+                    run_mode: true,
+                    ..CodecConfig::default()
+                };
+                let payload = encode_payload(&img, cfg).unwrap();
+                let out = decode_payload(&payload, w as u32, 3, img.format, cfg).unwrap();
+                assert_eq!(out, img, "{w} {codec:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn run_mode_collapses_flat_graphics() {
+        // flat panels with a few hard edges and a noisy icon, like a screenshot
+        let (w, h) = (256u32, 192u32);
+        let mut img = sample(w, h, PixelFormat::Rgb8);
+        for (i, px) in img.data.chunks_exact_mut(3).enumerate() {
+            let (x, y) = (i as u32 % w, i as u32 / w);
+            if !(100..120).contains(&x) || !(50..70).contains(&y) {
+                let panel = (x / 64 + y / 48 * 4) as u8;
+                px.copy_from_slice(&[panel * 16, 200 - panel * 9, 90]);
+            }
+        }
+        for codec in [CodecKind::PredictVarint, CodecKind::PredictArith] {
+            for predictor in PREDICTORS {
+                let cfg = CodecConfig {
+                    codec,
+                    predictor,
+                    ..CodecConfig::default()
+                };
+                let runs = CodecConfig {
+                    run_mode: true,
+                    ..cfg
+                };
+                let payload = encode_payload(&img, runs).unwrap();
+                assert_eq!(
+                    decode_payload(&payload, w, h, img.format, runs).unwrap(),
+                    img
+                );
+                let plain = encode_payload(&img, cfg).unwrap().len();
+                assert!(payload.len() <= plain + 3, "{codec:?} {predictor:?}");
+                if codec == CodecKind::PredictVarint {
+                    assert!(payload.len() * 10 < plain, "{predictor:?}");
+                }
+            }
+        }
+        let flat = smooth(1, 1, PixelFormat::Rgb8).data;
+        let flat = Image {
+            width: w,
+            height: h,
+            format: PixelFormat::Rgb8,
+            data: flat.repeat((w * h) as usize),
+        };
+        let cfg = CodecConfig {
+            run_mode: true,
+            ..CodecConfig::default()
+        };
+        let payload = encode_payload(&flat, cfg).unwrap();
+        assert!(payload.len() * 50 < flat.data.len());
+        let arith = CodecConfig {
+            codec: CodecKind::PredictArith,
+            ..cfg
+        };
+        let payload = encode_payload(&flat, arith).unwrap();
+        assert!(payload.len() * 100 < flat.data.len());
+    }
+
+    /// A random image: any format, up to 24×24, at a random legal depth.
+    fn any_image() -> impl Strategy<Value = (Image, u8)> {
+        (0..FORMATS.len(), 1u32..24, 1u32..24, 9u8..=16).prop_flat_map(|(f, w, h, wide)| {
+            let format = FORMATS[f];
+            let depth = if format.bytes_per_sample() == 1 {
+                8
+            } else {
+                wide
+            };
+            let len = (w * h) as usize * format.channels();
+            proptest::collection::vec(0u16..=((1u32 << depth) - 1) as u16, len).prop_map(
+                move |samples| {
+                    let img = Image::from_samples(w, h, format, &samples);
+                    (img, if depth == 8 { 0 } else { depth })
+                },
+            )
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn near_lossless_error_is_bounded(
+            (img, bit_depth) in any_image(),
+            near in 1u8..=20,
+            predictor in 0..PREDICTORS.len(),
+            arith: bool,
+            strict_recon: bool,
+            run_mode: bool,
+        ) {
+            let cfg = CodecConfig {
+                codec: if arith { CodecKind::PredictArith } else { CodecKind::PredictVarint },
+                predictor: PREDICTORS[predictor],
+                max_abs_error: near,
+                bit_depth,
+                strict_recon,
+                run_mode,
+                quant_bits: 7,
+                ..CodecConfig::default()
+            };
+            let payload = encode_payload(&img, cfg).unwrap();
+            let out = decode_payload(&payload, img.width, img.height, img.format, cfg).unwrap();
+            for (&a, &b) in img.samples().iter().zip(out.samples().iter()) {
+                prop_assert!((a as i32 - b as i32).abs() <= near as i32);
+            }
+        }
     }
 }
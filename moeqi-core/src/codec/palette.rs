@@ -0,0 +1,166 @@
+//! Indexed colour for images with few distinct colours, see
+//! [`CodecConfig::palette`].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::error::{MoeqiError, Result};
+use crate::types::{ChannelLayout, CodecConfig, ColorTransform, Image, PixelFormat};
+
+/// Most entries a palette holds; indices are coded as `Gray8`.
+pub const MAX_COLOURS: usize = 256;
+
+/// Distinct colours of an `Rgb8` or `Rgba8` image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    /// Bytes per entry: 3 or 4, as in the image.
+    pub channels: usize,
+    /// Entries back to back.
+    pub colours: Vec<u8>,
+}
+
+impl Palette {
+    pub fn len(&self) -> usize {
+        self.colours.len() / self.channels
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colours.is_empty()
+    }
+
+    /// Palette of `img` and its pixels as a `Gray8` image of indices, if
+    /// `img` has at most [`MAX_COLOURS`] colours and `cfg` codes them
+    /// losslessly. Entries are sorted by luma, so neighbouring indices tend
+    /// to be similar colours. Honours the alpha options of `cfg`.
+    pub fn index(img: &Image, cfg: CodecConfig) -> Result<Option<(Palette, Image)>> {
+        let ch = match img.format {
+            PixelFormat::Rgb8 => 3,
+            PixelFormat::Rgba8 => 4,
+            _ => return Ok(None),
+        };
+        let lossless = cfg.quant_bits == 0
+            && cfg.max_abs_error == 0
+            && cfg.plane(0).quant_bits == 0
+            && !matches!(cfg.color_transform, ColorTransform::YCbCr { .. });
+        // a gray index image has one plane, so overrides need the planar layout
+        let planes_ok = !cfg.has_plane_overrides() || cfg.layout == ChannelLayout::Planar;
+        if !img.validate() || img.data.is_empty() || !lossless || !planes_ok {
+            return Ok(None);
+        }
+
+        let pixel = |px: &[u8]| -> Result<[u8; 4]> {
+            let mut c = [0, 0, 0, 255];
+            c[..ch].copy_from_slice(px);
+            if ch == 4 {
+                if cfg.alpha.premultiplied && c[..3].iter().any(|&v| v > c[3]) {
+                    return Err(MoeqiError::InvalidData(
+                        "premultiplied colour exceeds alpha",
+                    ));
+                }
+                if cfg.alpha.clear_transparent && c[3] == 0 {
+                    c = [0; 4];
+                }
+            }
+            Ok(c)
+        };
+        let mut set = BTreeSet::new();
+        for px in img.data.chunks_exact(ch) {
+            set.insert(pixel(px)?);
+            if set.len() > MAX_COLOURS {
+                return Ok(None);
+            }
+        }
+
+        let mut sorted: Vec<[u8; 4]> = set.into_iter().collect();
+        sorted.sort_by_key(|c| {
+            (
+                299 * c[0] as u32 + 587 * c[1] as u32 + 114 * c[2] as u32,
+                *c,
+            )
+        });
+        let index: BTreeMap<[u8; 4], u8> = sorted
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| (c, i as u8))
+            .collect();
+        let data = img
+            .data
+            .chunks_exact(ch)
+            .map(|px| pixel(px).map(|c| index[&c]))
+            .collect::<Result<_>>()?;
+
+        let palette = Palette {
+            channels: ch,
+            colours: sorted.iter().flat_map(|c| &c[..ch]).copied().collect(),
+        };
+        let indices = Image {
+            width: img.width,
+            height: img.height,
+            format: PixelFormat::Gray8,
+            data,
+        };
+        Ok(Some((palette, indices)))
+    }
+
+    /// Inverse of [`Palette::index`]: the pixels of a `Gray8` index image.
+    pub fn expand(&self, indices: &Image) -> Result<Image> {
+        let format = match self.channels {
+            3 => PixelFormat::Rgb8,
+            _ => PixelFormat::Rgba8,
+        };
+        let mut data = Vec::with_capacity(indices.data.len() * self.channels);
+        for &i in &indices.data {
+            let start = i as usize * self.channels;
+            let entry = self
+                .colours
+                .get(start..start + self.channels)
+                .ok_or(MoeqiError::InvalidData("palette index out of range"))?;
+            data.extend_from_slice(entry);
+        }
+        Ok(Image {
+            width: indices.width,
+            height: indices.height,
+            format,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_and_expand() {
+        let img = Image {
+            width: 3,
+            height: 2,
+            format: PixelFormat::Rgba8,
+            data: [[9, 9, 9, 255], [200, 0, 0, 0], [0, 0, 0, 255]]
+                .repeat(2)
+                .concat(),
+        };
+        let (palette, indices) = Palette::index(&img, CodecConfig::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(palette.len(), 3);
+        assert_eq!(indices.data, [1, 2, 0, 1, 2, 0]);
+        assert_eq!(palette.expand(&indices).unwrap(), img);
+
+        let lossy = CodecConfig {
+            quant_bits: 3,
+            ..CodecConfig::default()
+        };
+        assert!(Palette::index(&img, lossy).unwrap().is_none());
+        let many = Image {
+            width: 300,
+            height: 1,
+            format: PixelFormat::Rgb8,
+            data: (0..300u32)
+                .flat_map(|p| [p as u8, (p >> 8) as u8, 0])
+                .collect(),
+        };
+        assert!(Palette::index(&many, CodecConfig::default())
+            .unwrap()
+            .is_none());
+    }
+}
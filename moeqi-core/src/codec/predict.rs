@@ -0,0 +1,43 @@
+use crate::types::Predictor;
+
+/// Predict the sample at `(x, y)` from its reconstructed left (`a`), up (`b`) and
+/// up-left (`c`) neighbours.
+///
+/// Neighbours outside the image read as 0, except that every predictor but
+/// [`Predictor::Left`] falls back to `a` on the first row and `b` in the first column.
+#[inline]
+pub fn predict(p: Predictor, a: i32, b: i32, c: i32, x: usize, y: usize) -> i32 {
+    if p == Predictor::Left || y == 0 {
+        return a;
+    }
+    if x == 0 {
+        return b;
+    }
+    match p {
+        Predictor::Left => a,
+        Predictor::Up => b,
+        Predictor::Average => (a + b) >> 1,
+        Predictor::Paeth => {
+            let base = a + b - c;
+            let pa = (base - a).abs();
+            let pb = (base - b).abs();
+            let pc = (base - c).abs();
+            if pa <= pb && pa <= pc {
+                a
+            } else if pb <= pc {
+                b
+            } else {
+                c
+            }
+        }
+        Predictor::Med => {
+            if c >= a.max(b) {
+                a.min(b)
+            } else if c <= a.min(b) {
+                a.max(b)
+            } else {
+                a + b - c
+            }
+        }
+    }
+}
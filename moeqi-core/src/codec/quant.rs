@@ -2,38 +2,56 @@
 #[derive(Debug, Clone)]
 pub struct SignedUniformQuant {
     bits: u8,
-    step: i16,
+    step: i32,
 }
 
 impl SignedUniformQuant {
     pub fn new(bits: u8) -> Self {
-        let bits = bits.max(1).min(15); // keep sane
+        Self::with_depth(bits, 8)
+    }
+
+    /// Step scaled so `bits` cover the residual range of `depth`-bit samples.
+    pub fn with_depth(bits: u8, depth: u8) -> Self {
+        let bits = bits.clamp(1, 15); // keep sane
         let levels = 1i32 << bits;
-        let half = (levels / 2) - 1;
+        let half = ((levels / 2) - 1).max(1);
+        let max = (1i32 << depth.clamp(1, 16)) - 1;
 
-        // pick step so half*step >= 255
-        let step = ((255 + half - 1) / half).max(1) as i16;
+        // pick step so half*step >= max
+        let step = ((max + half - 1) / half).max(1);
         Self { bits, step }
     }
 
+    /// Step `2 * near + 1`: rounding to the nearest multiple is then off by
+    /// at most `near`. [`bits`](Self::bits) is 0.
+    pub fn near_lossless(near: u8) -> Self {
+        Self {
+            bits: 0,
+            step: 2 * near as i32 + 1,
+        }
+    }
+
+    #[inline]
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+
     #[inline]
-    pub fn step(&self) -> i16 {
+    pub fn step(&self) -> i32 {
         self.step
     }
 
     #[inline]
-    pub fn quantize(&self, r: i16) -> i16 {
-        let s = self.step as i32;
-        let ri = r as i32;
-        let sign = if ri > 0 { 1 } else if ri < 0 { -1 } else { 0 };
+    pub fn quantize(&self, r: i32) -> i32 {
+        let s = self.step;
+        let sign = r.signum();
 
         // mid-tread rounding toward nearest quant bin
-        let q = ((ri + (s / 2) * sign) / s) * s;
-        q.clamp(-32768, 32767) as i16
+        ((r + (s / 2) * sign) / s) * s
     }
 
     #[inline]
-    pub fn dequantize(&self, q: i16) -> i16 {
+    pub fn dequantize(&self, q: i32) -> i32 {
         q
     }
 }
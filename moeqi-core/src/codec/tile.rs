@@ -0,0 +1,80 @@
+//! Square tile grid for [`CodecConfig::tile_size`](crate::types::CodecConfig::tile_size).
+
+/// Raster-order grid of `tile`×`tile` tiles; the last column/row is clipped to
+/// the image. A tile size of 0 means one tile covering the whole image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileGrid {
+    pub width: u32,
+    pub height: u32,
+    pub tile: u32,
+}
+
+impl TileGrid {
+    pub fn new(width: u32, height: u32, tile_size: u32) -> Self {
+        let tile = match tile_size {
+            0 => width.max(height).max(1),
+            t => t,
+        };
+        Self {
+            width,
+            height,
+            tile,
+        }
+    }
+
+    pub fn cols(&self) -> u32 {
+        self.width.div_ceil(self.tile)
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.height.div_ceil(self.tile)
+    }
+
+    /// Number of tiles; 0 for an empty image.
+    pub fn count(&self) -> usize {
+        self.cols() as usize * self.rows() as usize
+    }
+
+    /// `(x, y, w, h)` of tile `i`.
+    pub fn rect(&self, i: usize) -> (u32, u32, u32, u32) {
+        let cols = self.cols() as usize;
+        let x = (i % cols) as u32 * self.tile;
+        let y = (i / cols) as u32 * self.tile;
+        (
+            x,
+            y,
+            self.tile.min(self.width - x),
+            self.tile.min(self.height - y),
+        )
+    }
+
+    /// Indices of the tiles overlapping the region, in raster order. The
+    /// region must lie inside the image.
+    pub fn overlapping(&self, x: u32, y: u32, w: u32, h: u32) -> impl Iterator<Item = usize> {
+        let cols = self.cols() as usize;
+        let t = self.tile;
+        let (c0, c1) = (x / t, (x + w).div_ceil(t));
+        let (r0, r1) = (y / t, (y + h).div_ceil(t));
+        let empty = w == 0 || h == 0;
+        (r0..r1)
+            .filter(move |_| !empty)
+            .flat_map(move |r| (c0..c1).map(move |c| r as usize * cols + c as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clipped_edges_and_overlap() {
+        let g = TileGrid::new(10, 7, 4);
+        assert_eq!((g.cols(), g.rows(), g.count()), (3, 2, 6));
+        assert_eq!(g.rect(2), (8, 0, 2, 4));
+        assert_eq!(g.rect(5), (8, 4, 2, 3));
+        assert_eq!(g.overlapping(3, 3, 2, 2).collect::<Vec<_>>(), [0, 1, 3, 4]);
+        assert_eq!(g.overlapping(8, 0, 2, 1).collect::<Vec<_>>(), [2]);
+        assert_eq!(g.overlapping(4, 4, 0, 3).count(), 0);
+        assert_eq!(TileGrid::new(10, 7, 0).count(), 1);
+    }
+}
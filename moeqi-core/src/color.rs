@@ -1,5 +1,7 @@
 // crates/moeqi-core/src/color.rs
 
+use crate::types::{ChromaSiting, Upsampling};
+
 #[inline] fn clamp_u8(x: i32) -> u8 {
     if x < 0 { 0 } else if x > 255 { 255 } else { x as u8 }
 }
@@ -12,7 +14,7 @@ pub fn rgb_to_ycbcr_planar(rgb: &[u8], w: usize, h: usize) -> (Vec<u8>, Vec<u8>,
     let mut cr = vec![0u8; w*h];
 
     for i in 0..(w*h) {
-        let r = rgb[i*3+0] as i32;
+        let r = rgb[i*3] as i32;
         let g = rgb[i*3+1] as i32;
         let b = rgb[i*3+2] as i32;
 
@@ -45,7 +47,7 @@ pub fn ycbcr_to_rgb_planar(y: &[u8], cb: &[u8], cr: &[u8], w: usize, h: usize) -
         let g = yy - (( 88 * cbb + 183 * crr) >> 8);
         let b = yy + ((454 * cbb) >> 8);
 
-        rgb[i*3+0] = clamp_u8(r);
+        rgb[i*3] = clamp_u8(r);
         rgb[i*3+1] = clamp_u8(g);
         rgb[i*3+2] = clamp_u8(b);
     }
@@ -54,8 +56,8 @@ pub fn ycbcr_to_rgb_planar(y: &[u8], cb: &[u8], cr: &[u8], w: usize, h: usize) -
 
 /// 4:2:0 downsample (box filter) Cb/Cr full-res -> half-res
 pub fn downsample_420(ch: &[u8], w: usize, h: usize) -> (Vec<u8>, usize, usize) {
-    let w2 = (w + 1) / 2;
-    let h2 = (h + 1) / 2;
+    let w2 = w.div_ceil(2);
+    let h2 = h.div_ceil(2);
     let mut out = vec![0u8; w2*h2];
 
     for y2 in 0..h2 {
@@ -79,13 +81,130 @@ pub fn downsample_420(ch: &[u8], w: usize, h: usize) -> (Vec<u8>, usize, usize)
     (out, w2, h2)
 }
 
-/// Nearest upsample half-res -> full-res
+/// Nearest upsample half-res -> full-res. Reads are clamped to the
+/// `w2`x`h2` plane and to `ch_small`, so mismatched sizes never panic.
 pub fn upsample_420_nn(ch_small: &[u8], w2: usize, h2: usize, w: usize, h: usize) -> Vec<u8> {
+    let at = |x2: usize, y2: usize| {
+        let i = y2.min(h2.saturating_sub(1))*w2 + x2.min(w2.saturating_sub(1));
+        ch_small.get(i).or(ch_small.last()).copied().unwrap_or(0)
+    };
     let mut out = vec![0u8; w*h];
     for y in 0..h {
         for x in 0..w {
-            out[y*w + x] = ch_small[(y/2)*w2 + (x/2)];
+            out[y*w + x] = at(x/2, y/2);
+        }
+    }
+    out
+}
+
+/// Halve one axis of a `w`x`h` plane: box filter for centered chroma,
+/// [1, 2, 1] for cosited. Edges repeat.
+fn down_axis(src: &[u8], w: usize, h: usize, horizontal: bool, siting: ChromaSiting) -> (Vec<u8>, usize, usize) {
+    let (n, lines) = if horizontal { (w, h) } else { (h, w) };
+    let n2 = n.div_ceil(2);
+    let at = |line: usize, i: usize| if horizontal { src[line*w + i] as u32 } else { src[i*w + line] as u32 };
+    let (w2, h2) = if horizontal { (n2, h) } else { (w, n2) };
+    let mut out = vec![0u8; w2*h2];
+    for line in 0..lines {
+        for j in 0..n2 {
+            let i = 2*j;
+            let v = match siting {
+                ChromaSiting::Centered => (at(line, i) + at(line, (i + 1).min(n - 1))).div_ceil(2),
+                ChromaSiting::Cosited => {
+                    (at(line, i.saturating_sub(1)) + 2*at(line, i) + at(line, (i + 1).min(n - 1)) + 2) / 4
+                }
+            };
+            let o = if horizontal { line*w2 + j } else { j*w2 + line };
+            out[o] = v as u8;
+        }
+    }
+    (out, w2, h2)
+}
+
+/// Inverse of [`down_axis`]: bring one axis back to `n` samples.
+fn up_axis(src: &[u8], w2: usize, h2: usize, n: usize, horizontal: bool,
+           siting: ChromaSiting, up: Upsampling) -> Vec<u8> {
+    let (n2, lines) = if horizontal { (w2, h2) } else { (h2, w2) };
+    let at = |line: usize, i: usize| if horizontal { src[line*w2 + i] as u32 } else { src[i*w2 + line] as u32 };
+    let (w, h) = if horizontal { (n, h2) } else { (w2, n) };
+    let mut out = vec![0u8; w*h];
+    for line in 0..lines {
+        for x in 0..n {
+            let v = match up {
+                Upsampling::Nearest => at(line, x / 2),
+                Upsampling::Bilinear => {
+                    // position on the chroma grid, in quarter steps
+                    let p4 = match siting {
+                        ChromaSiting::Centered => (2*x as isize - 1).max(0) as usize,
+                        ChromaSiting::Cosited => 2*x,
+                    }.min(4*(n2 - 1));
+                    let (i0, f) = (p4 / 4, (p4 % 4) as u32);
+                    (at(line, i0)*(4 - f) + at(line, (i0 + 1).min(n2 - 1))*f + 2) / 4
+                }
+            };
+            let o = if horizontal { line*w + x } else { x*w + line };
+            out[o] = v as u8;
         }
     }
     out
 }
+
+/// Decimate a chroma plane by `factors` (1 or 2 per axis).
+pub fn downsample(ch: &[u8], w: usize, h: usize, factors: (usize, usize), siting: ChromaSiting) -> (Vec<u8>, usize, usize) {
+    assert_eq!(ch.len(), w*h);
+    if factors == (2, 2) && siting == ChromaSiting::Centered {
+        return downsample_420(ch, w, h);
+    }
+    let (mut out, mut w2, mut h2) = (ch.to_vec(), w, h);
+    if factors.0 == 2 { (out, w2, h2) = down_axis(&out, w2, h2, true, siting); }
+    if factors.1 == 2 { (out, w2, h2) = down_axis(&out, w2, h2, false, siting); }
+    (out, w2, h2)
+}
+
+/// Bring a plane from [`downsample`] back to `w`x`h`.
+pub fn upsample(small: &[u8], (w2, h2): (usize, usize), (w, h): (usize, usize),
+                factors: (usize, usize), siting: ChromaSiting, up: Upsampling) -> Vec<u8> {
+    assert_eq!(small.len(), w2*h2);
+    if factors == (2, 2) && up == Upsampling::Nearest {
+        return upsample_420_nn(small, w2, h2, w, h);
+    }
+    let mut out = small.to_vec();
+    let mut cur_w = w2;
+    if factors.0 == 2 { out = up_axis(&out, w2, h2, w, true, siting, up); cur_w = w; }
+    if factors.1 == 2 { out = up_axis(&out, cur_w, h2, h, false, siting, up); }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bilinear_follows_ramps_and_keeps_flat_areas() {
+        let (w, h) = (9, 5);
+        let ramp: Vec<u8> = (0..w*h).map(|i| (i % w * 20) as u8).collect();
+        for siting in [ChromaSiting::Centered, ChromaSiting::Cosited] {
+            let (small, w2, h2) = downsample(&ramp, w, h, (2, 2), siting);
+            assert_eq!((w2, h2), (5, 3));
+            let up = upsample(&small, (w2, h2), (w, h), (2, 2), siting, Upsampling::Bilinear);
+            let err = ramp.iter().zip(&up).map(|(&a, &b)| (a as i32 - b as i32).abs()).max().unwrap();
+            // only the clamped edges stray from the ramp
+            assert!(err <= 20, "{siting:?} {err}");
+            let inner = (2..w - 2).all(|x| (ramp[w + x] as i32 - up[w + x] as i32).abs() <= 1);
+            assert!(inner, "{siting:?}");
+
+            let flat = vec![77u8; w*h];
+            let (small, w2, h2) = downsample(&flat, w, h, (2, 1), siting);
+            assert_eq!(upsample(&small, (w2, h2), (w, h), (2, 1), siting, Upsampling::Bilinear), flat);
+        }
+    }
+
+    #[test]
+    fn nearest_420_tolerates_mismatched_sizes() {
+        let small = [1u8, 2, 3, 4];
+        assert_eq!(upsample_420_nn(&small, 2, 2, 3, 3), [1, 1, 2, 1, 1, 2, 3, 3, 4]);
+        // short input repeats its last sample, empty input is black
+        assert_eq!(upsample_420_nn(&small[..3], 2, 2, 4, 4)[12..], [3, 3, 3, 3]);
+        assert_eq!(upsample_420_nn(&[], 2, 2, 2, 1), [0, 0]);
+    }
+}
@@ -0,0 +1,111 @@
+//! Conversions between [`Image`] and [`image::DynamicImage`], with the
+//! `image` feature.
+
+use image::{DynamicImage, ImageBuffer};
+
+use crate::error::{MoeqiError, Result};
+use crate::types::{Image, PixelFormat};
+
+/// Fails with [`MoeqiError::InvalidData`] if `img.data` doesn't match its
+/// dimensions ([`Image::validate`]).
+impl TryFrom<Image> for DynamicImage {
+    type Error = MoeqiError;
+
+    fn try_from(img: Image) -> Result<Self> {
+        let (w, h) = (img.width, img.height);
+        let samples = || img.samples();
+        let wrong_len = MoeqiError::InvalidData("image data length mismatch");
+        if !img.data.len().is_multiple_of(img.format.bytes_per_sample()) {
+            return Err(wrong_len);
+        }
+        Ok(match img.format {
+            PixelFormat::Gray8 => {
+                DynamicImage::ImageLuma8(ImageBuffer::from_raw(w, h, img.data).ok_or(wrong_len)?)
+            }
+            PixelFormat::Rgb8 => {
+                DynamicImage::ImageRgb8(ImageBuffer::from_raw(w, h, img.data).ok_or(wrong_len)?)
+            }
+            PixelFormat::Rgba8 => {
+                DynamicImage::ImageRgba8(ImageBuffer::from_raw(w, h, img.data).ok_or(wrong_len)?)
+            }
+            PixelFormat::Gray16 => {
+                DynamicImage::ImageLuma16(ImageBuffer::from_raw(w, h, samples()).ok_or(wrong_len)?)
+            }
+            PixelFormat::Rgb16 => {
+                DynamicImage::ImageRgb16(ImageBuffer::from_raw(w, h, samples()).ok_or(wrong_len)?)
+            }
+            PixelFormat::Rgba16 => {
+                DynamicImage::ImageRgba16(ImageBuffer::from_raw(w, h, samples()).ok_or(wrong_len)?)
+            }
+        })
+    }
+}
+
+/// The 8- and 16-bit gray, RGB and RGBA variants; the others (gray + alpha,
+/// float) have no [`PixelFormat`] and are [`MoeqiError::Unsupported`].
+impl TryFrom<DynamicImage> for Image {
+    type Error = MoeqiError;
+
+    fn try_from(img: DynamicImage) -> Result<Self> {
+        let (width, height) = (img.width(), img.height());
+        let eight = |format, data| Image {
+            width,
+            height,
+            format,
+            data,
+        };
+        let sixteen =
+            |format, samples: Vec<u16>| Image::from_samples(width, height, format, &samples);
+        Ok(match img {
+            DynamicImage::ImageLuma8(b) => eight(PixelFormat::Gray8, b.into_raw()),
+            DynamicImage::ImageRgb8(b) => eight(PixelFormat::Rgb8, b.into_raw()),
+            DynamicImage::ImageRgba8(b) => eight(PixelFormat::Rgba8, b.into_raw()),
+            DynamicImage::ImageLuma16(b) => sixteen(PixelFormat::Gray16, b.into_raw()),
+            DynamicImage::ImageRgb16(b) => sixteen(PixelFormat::Rgb16, b.into_raw()),
+            DynamicImage::ImageRgba16(b) => sixteen(PixelFormat::Rgba16, b.into_raw()),
+            _ => {
+                return Err(MoeqiError::Unsupported(
+                    "only gray, RGB and RGBA images of 8 or 16 bits",
+                ))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dynamic_image_roundtrip() {
+        for format in [PixelFormat::Gray8, PixelFormat::Rgba8, PixelFormat::Rgb16] {
+            let len = 3 * 2 * format.channels() * format.bytes_per_sample();
+            let img = Image {
+                width: 3,
+                height: 2,
+                format,
+                data: (0..len).map(|i| (i * 41) as u8).collect(),
+            };
+            let dynamic = DynamicImage::try_from(img.clone()).unwrap();
+            assert_eq!((dynamic.width(), dynamic.height()), (3, 2));
+            assert_eq!(Image::try_from(dynamic).unwrap(), img);
+        }
+        for len in [35, 37] {
+            let wrong = Image {
+                width: 3,
+                height: 2,
+                format: PixelFormat::Rgb16,
+                data: vec![0; len],
+            };
+            assert!(matches!(
+                DynamicImage::try_from(wrong),
+                Err(MoeqiError::InvalidData(_))
+            ));
+        }
+        let gray_alpha = DynamicImage::new_luma_a8(2, 2);
+        assert!(matches!(
+            Image::try_from(gray_alpha),
+            Err(MoeqiError::Unsupported(_))
+        ));
+    }
+}
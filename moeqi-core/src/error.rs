@@ -15,8 +15,17 @@ pub enum MoeqiError {
     #[error("unexpected EOF")]
     Eof,
 
+    #[error("corrupt {} chunk: {reason}", String::from_utf8_lossy(chunk))]
+    CorruptChunk {
+        chunk: [u8; 4],
+        reason: &'static str,
+    },
+
     #[error("json error: {0}")]
     Json(#[from] serde_json::Error),
+
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
 }
 
 pub type Result<T> = core::result::Result<T, MoeqiError>;
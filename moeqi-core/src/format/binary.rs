@@ -1,27 +1,91 @@
 use crate::codec::{decode_payload, encode_payload};
 use crate::error::{MoeqiError, Result};
-use crate::types::{CodecConfig, Image, PixelFormat};
+use crate::format::moeqi2;
+use crate::format::tags::*;
+use crate::types::{AlphaConfig, ChannelLayout, CodecConfig, Image};
 
 const MAGIC: &[u8; 6] = b"MOEQI1";
 
+/// Encode into the current container, `MOEQI2`.
 pub fn encode(img: &Image, cfg: CodecConfig) -> Result<Vec<u8>> {
+    moeqi2::encode(img, cfg)
+}
+
+/// Decode a `MOEQI2` or legacy `MOEQI1` file.
+pub fn decode(bytes: &[u8]) -> Result<(Image, CodecConfig)> {
+    if bytes.starts_with(moeqi2::MAGIC) {
+        moeqi2::decode(bytes)
+    } else {
+        decode_v1(bytes)
+    }
+}
+
+/// Decode the `w`×`h` region at `(x, y)`. Only tiled `MOEQI2` files avoid
+/// decoding the whole image.
+pub fn decode_region(bytes: &[u8], x: u32, y: u32, w: u32, h: u32) -> Result<Image> {
+    if bytes.starts_with(moeqi2::MAGIC) {
+        return moeqi2::decode_region(bytes, x, y, w, h);
+    }
+    let (img, _) = decode_v1(bytes)?;
+    if x as u64 + w as u64 > img.width as u64 || y as u64 + h as u64 > img.height as u64 {
+        return Err(MoeqiError::InvalidData("region outside the image"));
+    }
+    let bpp = img.format.channels() * img.format.bytes_per_sample();
+    let (stride, len) = (img.width as usize * bpp, w as usize * bpp);
+    let mut data = Vec::with_capacity(len * h as usize);
+    for row in y as usize..(y + h) as usize {
+        let start = row * stride + x as usize * bpp;
+        data.extend_from_slice(&img.data[start..start + len]);
+    }
+    Ok(Image {
+        width: w,
+        height: h,
+        format: img.format,
+        data,
+    })
+}
+
+/// Encode into the fixed-header `MOEQI1` container. Only the fields of the
+/// original header are representable: tiled, near-lossless, planar,
+/// alpha-aware, run-mode and palette configs are rejected.
+pub fn encode_v1(img: &Image, cfg: CodecConfig) -> Result<Vec<u8>> {
+    if cfg.tile_size != 0 {
+        return Err(MoeqiError::Unsupported("MOEQI1 has no tiles"));
+    }
+    if cfg.max_abs_error != 0 {
+        return Err(MoeqiError::Unsupported("MOEQI1 has no near-lossless mode"));
+    }
+    if cfg.layout != ChannelLayout::Interleaved || cfg.has_plane_overrides() {
+        return Err(MoeqiError::Unsupported(
+            "MOEQI1 has no per-plane parameters",
+        ));
+    }
+    if cfg.alpha != AlphaConfig::default() {
+        return Err(MoeqiError::Unsupported("MOEQI1 has no alpha options"));
+    }
+    if cfg.run_mode {
+        return Err(MoeqiError::Unsupported("MOEQI1 has no run mode"));
+    }
+    if cfg.palette {
+        return Err(MoeqiError::Unsupported("MOEQI1 has no palette"));
+    }
     let payload = encode_payload(img, cfg)?;
 
     let mut out = Vec::with_capacity(32 + payload.len());
     out.extend_from_slice(MAGIC);
     out.extend_from_slice(&img.width.to_le_bytes());
     out.extend_from_slice(&img.height.to_le_bytes());
-    out.push(match img.format {
-        PixelFormat::Gray8 => 1,
-        PixelFormat::Rgb8 => 3,
-        PixelFormat::Rgba8 => 4,
-    });
+    out.push(format_tag(img.format, cfg.bit_depth)?);
     out.push(cfg.quant_bits);
-    out.push(if cfg.strict_recon { 1 } else { 0 });
-    out.push(match cfg.color_transform {
-        crate::types::ColorTransform::None => 0,
-        crate::types::ColorTransform::YCoCgR => 1,
-    });
+    // The strict_recon byte: bit 0 is the flag itself, bits 1..=3 the predictor,
+    // bits 4..=6 the codec. Files written before either existed hold 0/1 there,
+    // i.e. `Left` + `PredictVarint`.
+    out.push(
+        u8::from(cfg.strict_recon)
+            | (predictor_tag(cfg.predictor) << 1)
+            | (codec_tag(cfg.codec) << 4),
+    );
+    out.push(color_transform_tag(cfg.color_transform));
 
     // payload length u32
     out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
@@ -29,7 +93,7 @@ pub fn encode(img: &Image, cfg: CodecConfig) -> Result<Vec<u8>> {
     Ok(out)
 }
 
-pub fn decode(bytes: &[u8]) -> Result<(Image, CodecConfig)> {
+pub fn decode_v1(bytes: &[u8]) -> Result<(Image, CodecConfig)> {
     if bytes.len() < 6 + 4 + 4 + 1 + 1 + 1 + 1 + 4 {
         return Err(MoeqiError::InvalidData("too small"));
     }
@@ -43,23 +107,16 @@ pub fn decode(bytes: &[u8]) -> Result<(Image, CodecConfig)> {
     let height = u32::from_le_bytes(bytes[o..o + 4].try_into().unwrap());
     o += 4;
 
-    let fmt = match bytes[o] {
-        1 => PixelFormat::Gray8,
-        3 => PixelFormat::Rgb8,
-        4 => PixelFormat::Rgba8,
-        _ => return Err(MoeqiError::InvalidData("bad pixel format")),
-    };
+    let (fmt, bit_depth) = format_from_tag(bytes[o])?;
     o += 1;
 
     let quant_bits = bytes[o];
     o += 1;
-    let strict_recon = bytes[o] != 0;
+    let strict_recon = bytes[o] & 1 != 0;
+    let predictor = predictor_from_tag((bytes[o] >> 1) & 0x7)?;
+    let codec = codec_from_tag(bytes[o] >> 4)?;
     o += 1;
-    let color_transform = match bytes[o] {
-        0 => crate::types::ColorTransform::None,
-        1 => crate::types::ColorTransform::YCoCgR,
-        _ => return Err(MoeqiError::InvalidData("bad color transform")),
-    };
+    let color_transform = color_transform_from_tag(bytes[o])?;
     o += 1;
 
     let pay_len = u32::from_le_bytes(bytes[o..o + 4].try_into().unwrap()) as usize;
@@ -70,12 +127,60 @@ pub fn decode(bytes: &[u8]) -> Result<(Image, CodecConfig)> {
     let payload = &bytes[o..o + pay_len];
 
     let cfg = CodecConfig {
-        codec: crate::types::CodecKind::PredictVarint,
+        codec,
         quant_bits,
         strict_recon,
         color_transform,
+        predictor,
+        tile_size: 0,
+        bit_depth,
+        ..CodecConfig::default()
     };
 
     let img = decode_payload(payload, width, height, fmt, cfg)?;
     Ok((img, cfg))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{PixelFormat, Predictor};
+
+    #[test]
+    fn header_records_predictor_and_reads_legacy_strict_byte() {
+        let img = Image { width: 3, height: 2, format: PixelFormat::Gray8, data: vec![1, 2, 3, 4, 5, 6] };
+        let cfg = CodecConfig { predictor: Predictor::Med, ..CodecConfig::default() };
+        let bytes = encode_v1(&img, cfg).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), (img.clone(), cfg));
+        assert_eq!(&encode(&img, cfg).unwrap()[..6], moeqi2::MAGIC);
+
+        // pre-predictor writers stored a plain 0/1 in this byte
+        let legacy_cfg = CodecConfig { predictor: Predictor::Left, ..cfg };
+        let legacy = encode_v1(&img, legacy_cfg).unwrap();
+        assert_eq!(legacy[16], 1);
+        assert_eq!(decode(&legacy).unwrap().1, legacy_cfg);
+    }
+
+    #[test]
+    fn format_byte_carries_bit_depth() {
+        let samples: Vec<u16> = (0..30).map(|i| i * 131).collect();
+        let img = Image::from_samples(5, 2, PixelFormat::Rgb16, &samples);
+        for bit_depth in [12, 0] {
+            let cfg = CodecConfig {
+                bit_depth,
+                ..CodecConfig::default()
+            };
+            let (v1, v2) = (encode_v1(&img, cfg).unwrap(), encode(&img, cfg).unwrap());
+            for bytes in [&v1, &v2] {
+                assert_eq!(decode(bytes).unwrap(), (img.clone(), cfg));
+            }
+            let region = decode_region(&v1, 2, 0, 2, 2).unwrap();
+            assert_eq!(region.data.len(), 2 * 2 * 6);
+            assert_eq!(region.data[..12], img.data[12..24]);
+            assert_eq!(region, decode_region(&v2, 2, 0, 2, 2).unwrap());
+        }
+        assert_eq!(format_tag(PixelFormat::Rgb16, 12).unwrap(), 0xB3);
+        assert_eq!(format_from_tag(0xF1).unwrap(), (PixelFormat::Gray16, 0));
+        assert!(format_from_tag(0x31).is_err());
+    }
+}
@@ -1,2 +1,7 @@
 pub mod binary;
-pub mod json;
\ No newline at end of file
+pub mod crc32;
+pub mod json;
+pub mod moeqi2;
+pub mod pnm;
+pub mod stream;
+pub(crate) mod tags;
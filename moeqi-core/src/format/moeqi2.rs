@@ -0,0 +1,1005 @@
+//! `MOEQI2`: chunked successor of the fixed `MOEQI1` header.
+//!
+//! ```text
+//! "MOEQI2"
+//! { len: u32 LE | type: [u8; 4] | data: [u8; len] | crc32(type ++ data): u32 LE }*
+//! ```
+//!
+//! Chunks, in order: `HEAD`, `CONF`, `PLTE` (palette files only), `TIDX`
+//! (tiled files only), `DATA`, optional `META`, `END `. Files written by
+//! [`stream::Encoder`](crate::format::stream::Encoder) replace `DATA` with
+//! one `BAND` chunk per strip of rows, so no length is needed up front. A chunk
+//! type starting with a lowercase letter is ancillary and skipped when unknown;
+//! any other unknown type is rejected.
+//!
+//! `CONF` fields are appended over time. Readers take the fields they know
+//! and fall back to defaults for missing trailing ones; a field that changes
+//! how `DATA` must be decoded also sets a bit in the `HEAD` feature flags, so
+//! older readers refuse such files instead of misdecoding them.
+
+use crate::codec::palette::{Palette, MAX_COLOURS};
+use crate::codec::tile::TileGrid;
+use crate::codec::{self, varint};
+use crate::error::{MoeqiError, Result};
+use crate::format::crc32::Crc32;
+use crate::format::tags::*;
+use crate::types::{AlphaConfig, ChannelLayout, CodecConfig, Image, PixelFormat, PlaneConfig};
+
+pub const MAGIC: &[u8; 6] = b"MOEQI2";
+/// Container version written into `HEAD`.
+pub const VERSION: u8 = 1;
+/// `DATA` holds independently coded tiles, indexed by `TIDX`.
+pub const FEATURE_TILES: u32 = 1 << 0;
+/// Near-lossless coding, see [`CodecConfig::max_abs_error`].
+pub const FEATURE_NEAR_LOSSLESS: u32 = 1 << 1;
+/// Planes coded separately with the per-plane parameters in `CONF`, see
+/// [`CodecConfig::layout`] and [`CodecConfig::planes`].
+pub const FEATURE_PLANES: u32 = 1 << 2;
+/// Alpha coded apart from colour, see [`AlphaConfig::separate`].
+pub const FEATURE_ALPHA: u32 = 1 << 3;
+/// Run mode, see [`CodecConfig::run_mode`].
+pub const FEATURE_RUNS: u32 = 1 << 4;
+/// `DATA` codes palette indices, the palette is in `PLTE`.
+pub const FEATURE_PALETTE: u32 = 1 << 5;
+/// `BAND` chunks instead of `DATA`, see [`stream`](crate::format::stream).
+pub const FEATURE_BANDS: u32 = 1 << 6;
+/// Feature flags this reader understands.
+pub const KNOWN_FEATURES: u32 = FEATURE_TILES
+    | FEATURE_NEAR_LOSSLESS
+    | FEATURE_PLANES
+    | FEATURE_ALPHA
+    | FEATURE_RUNS
+    | FEATURE_PALETTE
+    | FEATURE_BANDS;
+
+pub const HEAD: [u8; 4] = *b"HEAD";
+pub const CONF: [u8; 4] = *b"CONF";
+pub const PLTE: [u8; 4] = *b"PLTE";
+pub const TIDX: [u8; 4] = *b"TIDX";
+pub const DATA: [u8; 4] = *b"DATA";
+pub const BAND: [u8; 4] = *b"BAND";
+pub const META: [u8; 4] = *b"META";
+pub const END: [u8; 4] = *b"END ";
+
+/// UTF-8 key/value pairs stored in the `META` chunk.
+pub type Metadata = Vec<(String, String)>;
+
+/// Contents of the `HEAD` chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub features: u32,
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    /// [`CodecConfig::bit_depth`], packed into the format byte.
+    pub bit_depth: u8,
+}
+
+/// One chunk of a `MOEQI2` file, CRC already verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunk<'a> {
+    pub kind: [u8; 4],
+    pub data: &'a [u8],
+}
+
+pub(crate) fn corrupt(chunk: [u8; 4], reason: &'static str) -> MoeqiError {
+    MoeqiError::CorruptChunk { chunk, reason }
+}
+
+pub(crate) fn push_chunk(out: &mut Vec<u8>, kind: [u8; 4], data: &[u8]) -> Result<()> {
+    let len = u32::try_from(data.len()).map_err(|_| MoeqiError::Unsupported("chunk > 4 GiB"))?;
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(&kind);
+    out.extend_from_slice(data);
+    let crc = Crc32::default().update(&kind).update(data).finish();
+    out.extend_from_slice(&crc.to_le_bytes());
+    Ok(())
+}
+
+/// Split a `MOEQI2` file into its chunks up to and including `END `, checking
+/// every CRC. Bytes after `END ` are ignored.
+pub fn read_chunks(bytes: &[u8]) -> Result<Vec<Chunk<'_>>> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(MoeqiError::InvalidData("bad magic"));
+    }
+    let mut o = MAGIC.len();
+    let mut chunks = Vec::new();
+    loop {
+        if bytes.len() < o + 8 {
+            return match chunks.last() {
+                Some(_) => Err(corrupt(END, "missing")),
+                None => Err(MoeqiError::Eof),
+            };
+        }
+        let len = u32::from_le_bytes(bytes[o..o + 4].try_into().unwrap()) as usize;
+        let kind: [u8; 4] = bytes[o + 4..o + 8].try_into().unwrap();
+        o += 8;
+        if bytes.len() - o < len.saturating_add(4) {
+            return Err(corrupt(kind, "truncated"));
+        }
+        let data = &bytes[o..o + len];
+        o += len;
+        let crc = u32::from_le_bytes(bytes[o..o + 4].try_into().unwrap());
+        o += 4;
+        if Crc32::default().update(&kind).update(data).finish() != crc {
+            return Err(corrupt(kind, "crc mismatch"));
+        }
+        chunks.push(Chunk { kind, data });
+        if kind == END {
+            return Ok(chunks);
+        }
+    }
+}
+
+pub(crate) fn encode_head(h: &Header) -> Result<Vec<u8>> {
+    let mut d = Vec::with_capacity(14);
+    d.push(h.version);
+    d.extend_from_slice(&h.features.to_le_bytes());
+    d.extend_from_slice(&h.width.to_le_bytes());
+    d.extend_from_slice(&h.height.to_le_bytes());
+    d.push(format_tag(h.format, h.bit_depth)?);
+    Ok(d)
+}
+
+pub(crate) fn decode_head(d: &[u8]) -> Result<Header> {
+    if d.len() < 14 {
+        return Err(corrupt(HEAD, "too short"));
+    }
+    let version = d[0];
+    if version == 0 || version > VERSION {
+        return Err(MoeqiError::Unsupported("MOEQI2 version"));
+    }
+    let features = u32::from_le_bytes(d[1..5].try_into().unwrap());
+    if features & !KNOWN_FEATURES != 0 {
+        return Err(MoeqiError::Unsupported("MOEQI2 feature flags"));
+    }
+    let (format, bit_depth) =
+        format_from_tag(d[13]).map_err(|_| corrupt(HEAD, "bad pixel format"))?;
+    let width = u32::from_le_bytes(d[5..9].try_into().unwrap());
+    let height = u32::from_le_bytes(d[9..13].try_into().unwrap());
+    (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|n| n.checked_mul(format.channels() * format.bytes_per_sample()))
+        .filter(|&n| n <= isize::MAX as usize)
+        .ok_or(corrupt(HEAD, "image too large"))?;
+    Ok(Header {
+        version,
+        features,
+        width,
+        height,
+        format,
+        bit_depth,
+    })
+}
+
+/// Whether `len` coded bytes can hold `rows` rows of a `width`-wide image,
+/// checked before anything is allocated for it. The range coder spends at
+/// least 1/64 bit on every sample (its probabilities stop at 2017/2048) and
+/// varint a byte; in run mode a row takes one length per
+/// [`MAX_RUN`](codec::MAX_RUN) samples and one more where it ends.
+fn payload_fits(width: u32, rows: u32, cfg: &CodecConfig, len: usize) -> bool {
+    let units = match cfg.run_mode {
+        true => rows as u64 + width as u64 * rows as u64 / codec::MAX_RUN as u64,
+        false => width as u64 * rows as u64,
+    };
+    units <= (len as u64).saturating_mul(8 * 64)
+}
+
+pub(crate) fn encode_conf(cfg: &CodecConfig) -> Vec<u8> {
+    let mut d = vec![
+        codec_tag(cfg.codec),
+        cfg.quant_bits,
+        u8::from(cfg.strict_recon),
+        color_transform_tag(cfg.color_transform),
+        predictor_tag(cfg.predictor),
+    ];
+    d.extend_from_slice(&cfg.tile_size.to_le_bytes());
+    d.push(cfg.max_abs_error);
+    d.push(u8::from(cfg.layout == ChannelLayout::Planar));
+    // per plane: codec, predictor, quant_bits; INHERIT keeps the image-wide value
+    for p in &cfg.planes {
+        d.push(p.codec.map_or(INHERIT, codec_tag));
+        d.push(p.predictor.map_or(INHERIT, predictor_tag));
+        d.push(p.quant_bits.unwrap_or(INHERIT));
+    }
+    let a = cfg.alpha;
+    d.push(
+        u8::from(a.separate) | u8::from(a.clear_transparent) << 1 | u8::from(a.premultiplied) << 2,
+    );
+    d.push(u8::from(cfg.run_mode));
+    d.push(u8::from(cfg.palette));
+    d
+}
+
+const INHERIT: u8 = 0xFF;
+
+fn decode_planes(d: &[u8]) -> Result<[PlaneConfig; 4]> {
+    let mut planes = [PlaneConfig::default(); 4];
+    if d.is_empty() {
+        return Ok(planes);
+    }
+    if d.len() < 12 {
+        return Err(corrupt(CONF, "truncated plane parameters"));
+    }
+    let bad = |_| corrupt(CONF, "bad field value");
+    for (p, r) in planes.iter_mut().zip(d.chunks_exact(3)) {
+        let field = |v: u8| (v != INHERIT).then_some(v);
+        p.codec = field(r[0]).map(codec_from_tag).transpose().map_err(bad)?;
+        p.predictor = field(r[1])
+            .map(predictor_from_tag)
+            .transpose()
+            .map_err(bad)?;
+        p.quant_bits = field(r[2]);
+    }
+    Ok(planes)
+}
+
+pub(crate) fn decode_conf(d: &[u8]) -> Result<CodecConfig> {
+    if d.len() < 5 {
+        return Err(corrupt(CONF, "too short"));
+    }
+    let bad = |_| corrupt(CONF, "bad field value");
+    Ok(CodecConfig {
+        codec: codec_from_tag(d[0]).map_err(bad)?,
+        quant_bits: d[1],
+        strict_recon: d[2] != 0,
+        color_transform: color_transform_from_tag(d[3]).map_err(bad)?,
+        predictor: predictor_from_tag(d[4]).map_err(bad)?,
+        tile_size: d
+            .get(5..9)
+            .map_or(0, |b| u32::from_le_bytes(b.try_into().unwrap())),
+        max_abs_error: d.get(9).copied().unwrap_or(0),
+        layout: match d.get(10) {
+            None | Some(0) => ChannelLayout::Interleaved,
+            Some(1) => ChannelLayout::Planar,
+            Some(_) => return Err(corrupt(CONF, "bad field value")),
+        },
+        planes: decode_planes(d.get(11..d.len().min(23)).unwrap_or(&[]))?,
+        alpha: match d.get(23).copied().unwrap_or(0) {
+            a @ 0..=7 => AlphaConfig {
+                separate: a & 1 != 0,
+                clear_transparent: a & 2 != 0,
+                premultiplied: a & 4 != 0,
+            },
+            _ => return Err(corrupt(CONF, "bad field value")),
+        },
+        run_mode: flag(d.get(24))?,
+        palette: flag(d.get(25))?,
+        // carried by the HEAD format byte
+        bit_depth: 0,
+    })
+}
+
+fn flag(b: Option<&u8>) -> Result<bool> {
+    match b {
+        None | Some(0) => Ok(false),
+        Some(1) => Ok(true),
+        Some(_) => Err(corrupt(CONF, "bad field value")),
+    }
+}
+
+/// Entry size, then the entries.
+fn encode_palette(p: &Palette) -> Vec<u8> {
+    let mut d = vec![p.channels as u8];
+    d.extend_from_slice(&p.colours);
+    d
+}
+
+fn decode_palette(d: &[u8], format: PixelFormat) -> Result<Palette> {
+    let channels = match (format, d.first()) {
+        (PixelFormat::Rgb8, Some(3)) => 3,
+        (PixelFormat::Rgba8, Some(4)) => 4,
+        _ => return Err(corrupt(PLTE, "does not match the pixel format")),
+    };
+    let colours = d[1..].to_vec();
+    let n = colours.len() / channels;
+    if n == 0 || n > MAX_COLOURS || n * channels != colours.len() {
+        return Err(corrupt(PLTE, "bad length"));
+    }
+    Ok(Palette { channels, colours })
+}
+
+fn encode_meta(meta: &[(String, String)]) -> Vec<u8> {
+    let mut d = Vec::new();
+    for (k, v) in meta {
+        for s in [k, v] {
+            varint::encode_u32_var(s.len() as u32, &mut d);
+            d.extend_from_slice(s.as_bytes());
+        }
+    }
+    d
+}
+
+pub(crate) fn decode_meta(mut d: &[u8]) -> Result<Metadata> {
+    let read_str = |d: &mut &[u8]| -> Result<String> {
+        let (len, used) = varint::decode_u32_var(d).map_err(|_| corrupt(META, "bad length"))?;
+        let rest = &d[used..];
+        if rest.len() < len as usize {
+            return Err(corrupt(META, "truncated"));
+        }
+        let s = String::from_utf8(rest[..len as usize].to_vec())
+            .map_err(|_| corrupt(META, "not utf-8"))?;
+        *d = &rest[len as usize..];
+        Ok(s)
+    };
+    let mut out = Vec::new();
+    while !d.is_empty() {
+        let k = read_str(&mut d)?;
+        let v = read_str(&mut d)?;
+        out.push((k, v));
+    }
+    Ok(out)
+}
+
+/// The `HEAD` feature flags that mirror fields of `cfg`.
+pub(crate) fn config_features(cfg: &CodecConfig) -> u32 {
+    let mut features = 0;
+    if cfg.max_abs_error != 0 {
+        features |= FEATURE_NEAR_LOSSLESS;
+    }
+    if uses_planes(cfg) {
+        features |= FEATURE_PLANES;
+    }
+    if cfg.alpha.separate {
+        features |= FEATURE_ALPHA;
+    }
+    if cfg.run_mode {
+        features |= FEATURE_RUNS;
+    }
+    features
+}
+
+/// Whether `DATA` depends on the planar fields of `CONF`.
+fn uses_planes(cfg: &CodecConfig) -> bool {
+    cfg.layout == ChannelLayout::Planar || cfg.has_plane_overrides()
+}
+
+pub fn encode(img: &Image, cfg: CodecConfig) -> Result<Vec<u8>> {
+    encode_with_metadata(img, cfg, &[])
+}
+
+/// Like [`encode`], adding a `META` chunk when `meta` is not empty.
+pub fn encode_with_metadata(
+    img: &Image,
+    cfg: CodecConfig,
+    meta: &[(String, String)],
+) -> Result<Vec<u8>> {
+    let mut features = config_features(&cfg);
+    let indexed = match cfg.palette {
+        true => Palette::index(img, cfg)?,
+        false => None,
+    };
+    if indexed.is_some() {
+        features |= FEATURE_PALETTE;
+    }
+    let coded = indexed.as_ref().map_or(img, |(_, indices)| indices);
+    let mut tile_index = Vec::new();
+    let payload = if cfg.tile_size == 0 {
+        codec::encode_payload(coded, cfg)?
+    } else {
+        features |= FEATURE_TILES;
+        let mut payload = Vec::new();
+        for tile in codec::encode_tiles(coded, cfg)? {
+            payload.extend_from_slice(&tile);
+            let end = u32::try_from(payload.len())
+                .map_err(|_| MoeqiError::Unsupported("tiled payload > 4 GiB"))?;
+            tile_index.extend_from_slice(&end.to_le_bytes());
+        }
+        payload
+    };
+    let head = Header {
+        version: VERSION,
+        features,
+        width: img.width,
+        height: img.height,
+        format: img.format,
+        bit_depth: cfg.bit_depth,
+    };
+
+    let mut out = Vec::with_capacity(64 + tile_index.len() + payload.len());
+    out.extend_from_slice(MAGIC);
+    push_chunk(&mut out, HEAD, &encode_head(&head)?)?;
+    push_chunk(&mut out, CONF, &encode_conf(&cfg))?;
+    if let Some((palette, _)) = &indexed {
+        push_chunk(&mut out, PLTE, &encode_palette(palette))?;
+    }
+    if features & FEATURE_TILES != 0 {
+        push_chunk(&mut out, TIDX, &tile_index)?;
+    }
+    push_chunk(&mut out, DATA, &payload)?;
+    if !meta.is_empty() {
+        push_chunk(&mut out, META, &encode_meta(meta))?;
+    }
+    push_chunk(&mut out, END, &[])?;
+    Ok(out)
+}
+
+/// A parsed `MOEQI2` file, payload still coded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container<'a> {
+    pub header: Header,
+    pub config: CodecConfig,
+    /// Present when `data` codes palette indices.
+    pub palette: Option<Palette>,
+    pub metadata: Metadata,
+    /// The `DATA` chunk: one payload, or the tile streams back to back.
+    pub data: &'a [u8],
+    /// End offset of each tile in `data`; empty for untiled files.
+    pub tile_ends: Vec<u32>,
+    /// The `BAND` chunks of a streamed file, top to bottom; `data` is then
+    /// empty.
+    pub bands: Vec<&'a [u8]>,
+}
+
+impl<'a> Container<'a> {
+    pub fn grid(&self) -> TileGrid {
+        TileGrid::new(self.header.width, self.header.height, self.config.tile_size)
+    }
+
+    /// Coded stream of every tile in [`TileGrid`] order; an untiled file is
+    /// a single tile.
+    pub fn tiles(&self) -> Vec<&'a [u8]> {
+        if self.tile_ends.is_empty() {
+            return match self.grid().count() {
+                0 => Vec::new(),
+                _ => vec![self.data],
+            };
+        }
+        let mut start = 0;
+        self.tile_ends
+            .iter()
+            .map(|&end| {
+                let tile = &self.data[start..end as usize];
+                start = end as usize;
+                tile
+            })
+            .collect()
+    }
+}
+
+fn decode_tile_index(d: &[u8], count: usize, data_len: usize) -> Result<Vec<u32>> {
+    if d.len() != count * 4 {
+        return Err(corrupt(TIDX, "tile count mismatch"));
+    }
+    let ends: Vec<u32> = d
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .collect();
+    if ends.windows(2).any(|p| p[0] > p[1]) || ends.last().map_or(0, |&e| e as usize) != data_len {
+        return Err(corrupt(TIDX, "bad tile offsets"));
+    }
+    Ok(ends)
+}
+
+/// Check the `HEAD` feature flags against `CONF` and each other.
+pub(crate) fn check_features(header: &Header, config: &CodecConfig) -> Result<()> {
+    let near = header.features & FEATURE_NEAR_LOSSLESS != 0;
+    if near != (config.max_abs_error != 0) {
+        return Err(corrupt(CONF, "max_abs_error disagrees with HEAD"));
+    }
+    let planes = header.features & FEATURE_PLANES != 0;
+    if planes != uses_planes(config) {
+        return Err(corrupt(CONF, "plane parameters disagree with HEAD"));
+    }
+    if (header.features & FEATURE_ALPHA != 0) != config.alpha.separate {
+        return Err(corrupt(CONF, "alpha coding disagrees with HEAD"));
+    }
+    if (header.features & FEATURE_RUNS != 0) != config.run_mode {
+        return Err(corrupt(CONF, "run mode disagrees with HEAD"));
+    }
+    // bands are coded one by one as they stream in
+    let whole = FEATURE_TILES | FEATURE_PALETTE;
+    if header.features & FEATURE_BANDS != 0 && header.features & whole != 0 {
+        return Err(corrupt(HEAD, "banded files cannot be tiled or indexed"));
+    }
+    Ok(())
+}
+
+/// Rows of a `BAND` chunk and its coded payload.
+pub(crate) fn split_band(d: &[u8]) -> Result<(u32, &[u8])> {
+    match varint::decode_u32_var(d) {
+        Ok((rows, used)) if rows > 0 => Ok((rows, &d[used..])),
+        _ => Err(corrupt(BAND, "bad row count")),
+    }
+}
+
+fn check_bands(bands: &[&[u8]], width: u32, height: u32, cfg: &CodecConfig) -> Result<()> {
+    let mut rows = 0u64;
+    for b in bands {
+        rows += check_band(b, width, cfg)?.0 as u64;
+    }
+    if rows != height as u64 {
+        return Err(corrupt(BAND, "rows do not add up to the height"));
+    }
+    Ok(())
+}
+
+/// [`split_band`], refusing bands too short for their rows.
+fn check_band<'a>(d: &'a [u8], width: u32, cfg: &CodecConfig) -> Result<(u32, &'a [u8])> {
+    let (rows, payload) = split_band(d)?;
+    if !payload_fits(width, rows, cfg, payload.len()) {
+        return Err(corrupt(BAND, "payload too short for its rows"));
+    }
+    Ok((rows, payload))
+}
+
+/// Decode one `BAND` chunk of a `width`-wide image.
+pub(crate) fn decode_band(
+    d: &[u8],
+    width: u32,
+    format: PixelFormat,
+    cfg: CodecConfig,
+) -> Result<Image> {
+    let (rows, payload) = check_band(d, width, &cfg)?;
+    codec::decode_payload(payload, width, rows, format, cfg).map_err(|e| match e {
+        MoeqiError::Eof => corrupt(BAND, "truncated payload"),
+        MoeqiError::InvalidData(reason) => corrupt(BAND, reason),
+        e => e,
+    })
+}
+
+pub fn read_container(bytes: &[u8]) -> Result<Container<'_>> {
+    let chunks = read_chunks(bytes)?;
+    let header = match chunks.first() {
+        Some(c) if c.kind == HEAD => decode_head(c.data)?,
+        _ => return Err(corrupt(HEAD, "missing")),
+    };
+
+    let mut cfg = None;
+    let mut plte = None;
+    let mut tidx = None;
+    let mut data = None;
+    let mut bands = Vec::new();
+    let mut metadata = Vec::new();
+    for c in &chunks[1..] {
+        match c.kind {
+            CONF if cfg.is_none() => cfg = Some(decode_conf(c.data)?),
+            PLTE if plte.is_none() => plte = Some(decode_palette(c.data, header.format)?),
+            TIDX if tidx.is_none() => tidx = Some(c.data),
+            DATA if data.is_none() => data = Some(c.data),
+            BAND => bands.push(c.data),
+            META => metadata.extend(decode_meta(c.data)?),
+            END => {}
+            HEAD | CONF | PLTE | TIDX | DATA => return Err(corrupt(c.kind, "duplicate")),
+            k if k[0].is_ascii_lowercase() => {}
+            _ => return Err(MoeqiError::Unsupported("unknown critical MOEQI2 chunk")),
+        }
+    }
+    let mut config = cfg.ok_or(corrupt(CONF, "missing"))?;
+    config.bit_depth = header.bit_depth;
+    check_features(&header, &config)?;
+    let data = match (header.features & FEATURE_BANDS != 0, data) {
+        (false, Some(d)) => d,
+        (false, None) => return Err(corrupt(DATA, "missing")),
+        (true, None) => {
+            check_bands(&bands, header.width, header.height, &config)?;
+            &[]
+        }
+        (true, Some(_)) => return Err(corrupt(DATA, "unexpected")),
+    };
+    match (header.features & FEATURE_PALETTE != 0, &plte) {
+        (true, None) => return Err(corrupt(PLTE, "missing")),
+        (false, Some(_)) => return Err(corrupt(PLTE, "unexpected")),
+        (true, Some(_)) if !config.palette => {
+            return Err(corrupt(CONF, "palette disagrees with HEAD"))
+        }
+        _ => {}
+    }
+    let banded = header.features & FEATURE_BANDS != 0;
+    if !banded && !payload_fits(header.width, header.height, &config, data.len()) {
+        return Err(corrupt(HEAD, "dimensions too large for the DATA chunk"));
+    }
+    let tiled = header.features & FEATURE_TILES != 0;
+    if tiled != (config.tile_size != 0) {
+        return Err(corrupt(CONF, "tile size disagrees with HEAD"));
+    }
+    let tile_ends = match (tiled, tidx) {
+        (false, None) => Vec::new(),
+        (false, Some(_)) => return Err(corrupt(TIDX, "unexpected")),
+        (true, None) => return Err(corrupt(TIDX, "missing")),
+        (true, Some(d)) => {
+            let count = TileGrid::new(header.width, header.height, config.tile_size).count();
+            decode_tile_index(d, count, data.len())?
+        }
+    };
+
+    Ok(Container {
+        header,
+        config,
+        palette: plte,
+        metadata,
+        data,
+        bands,
+        tile_ends,
+    })
+}
+
+pub fn decode(bytes: &[u8]) -> Result<(Image, CodecConfig)> {
+    let (img, cfg, _) = decode_with_metadata(bytes)?;
+    Ok((img, cfg))
+}
+
+pub fn decode_with_metadata(bytes: &[u8]) -> Result<(Image, CodecConfig, Metadata)> {
+    let c = read_container(bytes)?;
+    let (w, h) = (c.header.width, c.header.height);
+    let img = region(&c, 0, 0, w, h)?;
+    Ok((img, c.config, c.metadata))
+}
+
+/// Decode the `w`×`h` region at `(x, y)`, touching only the tiles that
+/// overlap it. Untiled files are decoded whole and cropped.
+pub fn decode_region(bytes: &[u8], x: u32, y: u32, w: u32, h: u32) -> Result<Image> {
+    region(&read_container(bytes)?, x, y, w, h)
+}
+
+fn region(c: &Container<'_>, x: u32, y: u32, w: u32, h: u32) -> Result<Image> {
+    let Header {
+        width,
+        height,
+        format,
+        ..
+    } = c.header;
+    if c.header.features & FEATURE_BANDS != 0 {
+        return band_region(c, x, y, w, h);
+    }
+    let coded = match c.palette {
+        Some(_) => PixelFormat::Gray8,
+        None => format,
+    };
+    let tiles = c.tiles();
+    codec::decode_region(&tiles, width, height, coded, c.config, x, y, w, h)
+        .and_then(|img| match &c.palette {
+            Some(p) => p.expand(&img),
+            None => Ok(img),
+        })
+        .map_err(|e| match e {
+            MoeqiError::Eof => corrupt(DATA, "truncated payload"),
+            MoeqiError::InvalidData("region outside the image") => e,
+            MoeqiError::InvalidData(reason) => corrupt(DATA, reason),
+            e => e,
+        })
+}
+
+/// [`region`] of a streamed file: decodes only the bands it overlaps.
+fn band_region(c: &Container<'_>, x: u32, y: u32, w: u32, h: u32) -> Result<Image> {
+    let Header {
+        width,
+        height,
+        format,
+        ..
+    } = c.header;
+    if x as u64 + w as u64 > width as u64 || y as u64 + h as u64 > height as u64 {
+        return Err(MoeqiError::InvalidData("region outside the image"));
+    }
+    let bpp = format.channels() * format.bytes_per_sample();
+    let mut data = Vec::with_capacity(w as usize * h as usize * bpp);
+    let mut top = 0;
+    for band in &c.bands {
+        let rows = split_band(band)?.0;
+        let (y0, y1) = (top.max(y), (top + rows).min(y + h));
+        if y0 < y1 {
+            let img = decode_band(band, width, format, c.config)?;
+            let stride = width as usize * bpp;
+            for row in y0..y1 {
+                let start = (row - top) as usize * stride + x as usize * bpp;
+                data.extend_from_slice(&img.data[start..start + w as usize * bpp]);
+            }
+        }
+        top += rows;
+    }
+    Ok(Image {
+        width: w,
+        height: h,
+        format,
+        data,
+    })
+}
+
+/// `bytes` with the `HEAD` size replaced and its CRC fixed up.
+#[cfg(test)]
+pub(crate) fn resize_head(bytes: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut out = bytes.to_vec();
+    let head = MAGIC.len() + 8;
+    out[head + 5..head + 9].copy_from_slice(&width.to_le_bytes());
+    out[head + 9..head + 13].copy_from_slice(&height.to_le_bytes());
+    let crc = Crc32::default()
+        .update(&HEAD)
+        .update(&out[head..head + 14])
+        .finish();
+    out[head + 14..head + 18].copy_from_slice(&crc.to_le_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        ChromaSiting, CodecKind, ColorTransform, Predictor, Subsampling, Upsampling,
+    };
+
+    fn sample() -> (Image, CodecConfig) {
+        let img = Image {
+            width: 4,
+            height: 3,
+            format: PixelFormat::Rgb8,
+            data: (0..36).map(|i| (i * 7) as u8).collect(),
+        };
+        let cfg = CodecConfig {
+            codec: CodecKind::PredictArith,
+            predictor: Predictor::Paeth,
+            color_transform: ColorTransform::None,
+            ..CodecConfig::default()
+        };
+        (img, cfg)
+    }
+
+    /// Offset of the first byte of `kind`'s data.
+    fn data_offset(bytes: &[u8], kind: [u8; 4]) -> usize {
+        bytes.windows(4).position(|w| w == kind).unwrap() + 4
+    }
+
+    #[test]
+    fn roundtrip_with_metadata() {
+        let (img, cfg) = sample();
+        let meta = vec![
+            ("author".to_string(), "qa".to_string()),
+            ("k".into(), "".into()),
+        ];
+        let bytes = encode_with_metadata(&img, cfg, &meta).unwrap();
+        assert_eq!(&bytes[..6], MAGIC);
+        let (out, out_cfg, out_meta) = decode_with_metadata(&bytes).unwrap();
+        assert_eq!((out, out_cfg, out_meta), (img, cfg, meta));
+    }
+
+    #[test]
+    fn ycbcr_config_survives() {
+        let (img, cfg) = sample();
+        let cfg = CodecConfig {
+            color_transform: ColorTransform::YCbCr {
+                subsampling: Subsampling::S422,
+                siting: ChromaSiting::Cosited,
+                upsampling: Upsampling::Nearest,
+            },
+            ..cfg
+        };
+        let (out, out_cfg) = decode(&encode(&img, cfg).unwrap()).unwrap();
+        assert_eq!(out_cfg, cfg);
+        assert_eq!((out.width, out.height, out.format), (4, 3, img.format));
+    }
+
+    #[test]
+    fn plane_parameters_survive() {
+        let (img, cfg) = sample();
+        let cfg = CodecConfig {
+            layout: ChannelLayout::Planar,
+            planes: [
+                PlaneConfig::default(),
+                PlaneConfig {
+                    codec: Some(CodecKind::PredictVarint),
+                    predictor: Some(Predictor::Up),
+                    quant_bits: Some(2),
+                },
+                PlaneConfig::default(),
+                PlaneConfig::default(),
+            ],
+            ..cfg
+        };
+        let bytes = encode(&img, cfg).unwrap();
+        let c = read_container(&bytes).unwrap();
+        assert_eq!(c.header.features, FEATURE_PLANES);
+        assert_eq!(decode(&bytes).unwrap().1, cfg);
+        assert!(crate::format::binary::encode_v1(&img, cfg).is_err());
+    }
+
+    #[test]
+    fn alpha_and_run_options_survive() {
+        let img = Image {
+            width: 3,
+            height: 2,
+            format: PixelFormat::Rgba8,
+            data: (0..24)
+                .map(|i| if i % 4 == 3 { 255 } else { i * 9 })
+                .collect(),
+        };
+        let cfg = CodecConfig {
+            alpha: AlphaConfig {
+                separate: true,
+                clear_transparent: false,
+                premultiplied: true,
+            },
+            ..sample().1
+        };
+        let bytes = encode(&img, cfg).unwrap();
+        assert_eq!(
+            read_container(&bytes).unwrap().header.features,
+            FEATURE_ALPHA
+        );
+        assert_eq!(decode(&bytes).unwrap(), (img.clone(), cfg));
+
+        let cfg = CodecConfig {
+            run_mode: true,
+            ..cfg
+        };
+        let bytes = encode(&img, cfg).unwrap();
+        let features = read_container(&bytes).unwrap().header.features;
+        assert_eq!(features, FEATURE_ALPHA | FEATURE_RUNS);
+        assert_eq!(decode(&bytes).unwrap(), (img, cfg));
+    }
+
+    #[test]
+    fn palette_roundtrip_and_region() {
+        // a chart: a few flat colours in an irregular pattern
+        let (w, h) = (45u32, 33u32);
+        let colours = [
+            [250, 250, 250, 255],
+            [30, 90, 200, 255],
+            [220, 60, 40, 128],
+            [0, 0, 0, 0],
+        ];
+        let data = (0..w * h)
+            .flat_map(|i| colours[((i % w) * (i / w) / 37 % 4) as usize])
+            .collect();
+        let img = Image {
+            width: w,
+            height: h,
+            format: PixelFormat::Rgba8,
+            data,
+        };
+        let cfg = CodecConfig {
+            tile_size: 16,
+            ..sample().1
+        };
+        let indexed = CodecConfig {
+            palette: true,
+            ..cfg
+        };
+        let bytes = encode(&img, indexed).unwrap();
+        let c = read_container(&bytes).unwrap();
+        assert_eq!(c.header.features, FEATURE_TILES | FEATURE_PALETTE);
+        assert_eq!(c.palette.as_ref().map(Palette::len), Some(4));
+        assert!(bytes.len() < encode(&img, cfg).unwrap().len());
+        assert_eq!(decode(&bytes).unwrap(), (img.clone(), indexed));
+
+        let region = decode_region(&bytes, 10, 20, 30, 5).unwrap();
+        assert_eq!(region.format, PixelFormat::Rgba8);
+        assert_eq!(
+            region.data[..4],
+            img.data[(20 * w as usize + 10) * 4..][..4]
+        );
+
+        // too many colours: the flag is kept, the pixels are coded directly
+        let rgb = Image {
+            width: 20,
+            height: 15,
+            format: PixelFormat::Rgb8,
+            data: (0..300u32)
+                .flat_map(|p| [p as u8, (p >> 8) as u8, 7])
+                .collect(),
+        };
+        let bytes = encode(&rgb, indexed).unwrap();
+        assert_eq!(
+            read_container(&bytes).unwrap().header.features,
+            FEATURE_TILES
+        );
+        assert_eq!(decode(&bytes).unwrap(), (rgb, indexed));
+    }
+
+    #[test]
+    fn corruption_names_the_chunk() {
+        let (img, cfg) = sample();
+        let bytes = encode(&img, cfg).unwrap();
+        for kind in [HEAD, CONF, DATA] {
+            let mut bad = bytes.clone();
+            bad[data_offset(&bytes, kind)] ^= 0x40;
+            match decode(&bad) {
+                Err(MoeqiError::CorruptChunk { chunk, reason }) => {
+                    assert_eq!((chunk, reason), (kind, "crc mismatch"))
+                }
+                other => panic!("{other:?}"),
+            }
+        }
+        let cut = &bytes[..data_offset(&bytes, DATA) + 2];
+        assert!(matches!(
+            decode(cut),
+            Err(MoeqiError::CorruptChunk {
+                chunk: DATA,
+                reason: "truncated"
+            })
+        ));
+    }
+
+    #[test]
+    fn oversized_head_is_corrupt() {
+        let (img, cfg) = sample();
+        let bytes = encode(&img, cfg).unwrap();
+        for (w, h) in [(u32::MAX, u32::MAX), (300_000, 368_875), (4, 100_000)] {
+            match decode(&resize_head(&bytes, w, h)) {
+                Err(MoeqiError::CorruptChunk { chunk, .. }) => assert_eq!(chunk, HEAD),
+                other => panic!("{w}x{h}: {other:?}"),
+            }
+        }
+        let wide = Image::from_samples(1, 1, PixelFormat::Rgba16, &[1, 2, 3, 4]);
+        let bytes = encode(&wide, CodecConfig::default()).unwrap();
+        assert!(matches!(
+            decode(&resize_head(&bytes, u32::MAX, u32::MAX)),
+            Err(MoeqiError::CorruptChunk { chunk: HEAD, .. })
+        ));
+    }
+
+    #[test]
+    fn wide_run_mode_head_is_corrupt() {
+        let flat = Image::from_samples(4, 2, PixelFormat::Gray8, &[7; 8]);
+        let cfg = CodecConfig {
+            run_mode: true,
+            ..CodecConfig::default()
+        };
+        let bytes = encode(&flat, cfg).unwrap();
+        assert!(bytes.len() < 200);
+        assert!(matches!(
+            decode(&resize_head(&bytes, 300_000_000, 2)),
+            Err(MoeqiError::CorruptChunk { chunk: HEAD, .. })
+        ));
+    }
+
+    #[test]
+    fn unknown_chunks() {
+        let (img, cfg) = sample();
+        let bytes = encode(&img, cfg).unwrap();
+        let end = bytes.len() - 12;
+        for (kind, ok) in [(*b"tIME", true), (*b"TILE", false)] {
+            let mut out = bytes[..end].to_vec();
+            push_chunk(&mut out, kind, b"x").unwrap();
+            out.extend_from_slice(&bytes[end..]);
+            assert_eq!(decode(&out).is_ok(), ok);
+        }
+    }
+
+    #[test]
+    fn newer_features_are_refused() {
+        let (img, cfg) = sample();
+        let mut bytes = encode(&img, cfg).unwrap();
+        let o = data_offset(&bytes, HEAD);
+        bytes[o + 1] |= 0x80;
+        let crc = Crc32::default()
+            .update(&HEAD)
+            .update(&bytes[o..o + 14])
+            .finish();
+        bytes[o + 14..o + 18].copy_from_slice(&crc.to_le_bytes());
+        assert!(matches!(decode(&bytes), Err(MoeqiError::Unsupported(_))));
+    }
+
+    #[test]
+    fn tiled_roundtrip_and_region() {
+        let img = Image {
+            width: 37,
+            height: 21,
+            format: PixelFormat::Gray8,
+            data: (0..37 * 21).map(|i| (i % 37 * 3 + i / 37) as u8).collect(),
+        };
+        let cfg = CodecConfig {
+            tile_size: 16,
+            ..CodecConfig::default()
+        };
+        let bytes = encode(&img, cfg).unwrap();
+        let c = read_container(&bytes).unwrap();
+        assert_eq!(c.header.features, FEATURE_TILES);
+        assert_eq!(c.tiles().len(), 6);
+        assert_eq!(decode(&bytes).unwrap(), (img.clone(), cfg));
+
+        let part = decode_region(&bytes, 30, 5, 7, 12).unwrap();
+        for y in 0..12 {
+            let row = &img.data[(5 + y) * 37 + 30..][..7];
+            assert_eq!(&part.data[y * 7..][..7], row);
+        }
+        assert!(decode_region(&bytes, 30, 5, 8, 1).is_err());
+        assert!(matches!(
+            crate::format::binary::encode_v1(&img, cfg),
+            Err(MoeqiError::Unsupported("MOEQI1 has no tiles"))
+        ));
+
+        // an untiled file crops the full decode
+        let untiled = encode(&img, CodecConfig::default()).unwrap();
+        assert_eq!(decode_region(&untiled, 30, 5, 7, 12).unwrap(), part);
+    }
+}
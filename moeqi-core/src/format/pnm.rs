@@ -0,0 +1,314 @@
+//! Netpbm images: PGM (`P2`/`P5`), PPM (`P3`/`P6`) and PAM (`P7`).
+//!
+//! Samples are kept as stored, without rescaling: a maxval up to 255 decodes
+//! to the 8-bit formats, anything above to the 16-bit ones. PAM's
+//! `GRAYSCALE_ALPHA` has no [`PixelFormat`] of its own and becomes RGBA.
+
+use crate::error::{MoeqiError, Result};
+use crate::types::{Image, PixelFormat};
+
+/// How [`encode`] writes the samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// ASCII PGM/PPM (`P2`/`P3`).
+    Plain,
+    /// Binary PGM/PPM (`P5`/`P6`).
+    Raw,
+    /// PAM (`P7`), the only kind with alpha.
+    Pam,
+}
+
+/// Parse a PGM, PPM or PAM file.
+pub fn decode(bytes: &[u8]) -> Result<Image> {
+    decode_with_maxval(bytes).map(|(img, _)| img)
+}
+
+/// [`decode`], also returning the file's maxval.
+pub fn decode_with_maxval(bytes: &[u8]) -> Result<(Image, u16)> {
+    let mut t = Tokens { bytes, pos: 0 };
+    let magic = t.token()?;
+    let (width, height, depth, maxval, plain) = match magic {
+        b"P2" | b"P3" | b"P5" | b"P6" => {
+            let depth = if matches!(magic, b"P2" | b"P5") { 1 } else { 3 };
+            let (width, height, maxval) = (t.number()?, t.number()?, t.number()?);
+            (width, height, depth, maxval, matches!(magic, b"P2" | b"P3"))
+        }
+        b"P7" => {
+            let (width, height, depth, maxval) = pam_header(&mut t)?;
+            (width, height, depth, maxval, false)
+        }
+        _ => return Err(MoeqiError::Unsupported("not a PGM, PPM or PAM file")),
+    };
+    if maxval == 0 || maxval > u16::MAX as u32 {
+        return Err(MoeqiError::InvalidData("PNM maxval out of range"));
+    }
+    let count = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|n| n.checked_mul(depth))
+        .ok_or(MoeqiError::InvalidData("PNM image too large"))?;
+
+    let samples: Vec<u32> = if plain {
+        // no reserve: `count` is unchecked until the numbers run out
+        (0..count).map(|_| t.number()).collect::<Result<_>>()?
+    } else {
+        // the header ends with exactly one whitespace byte
+        let start = t.pos + 1;
+        let wide = maxval > 255;
+        let data = count
+            .checked_mul(if wide { 2 } else { 1 })
+            .and_then(|len| bytes.get(start..start.checked_add(len)?))
+            .ok_or(MoeqiError::Eof)?;
+        match wide {
+            false => data.iter().map(|&v| v as u32).collect(),
+            true => data
+                .chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]) as u32)
+                .collect(),
+        }
+    };
+    if samples.iter().any(|&v| v > maxval) {
+        return Err(MoeqiError::InvalidData("PNM sample above maxval"));
+    }
+
+    let samples: Vec<u16> = match depth {
+        // gray + alpha
+        2 => samples
+            .chunks_exact(2)
+            .flat_map(|px| [px[0], px[0], px[0], px[1]])
+            .map(|v| v as u16)
+            .collect(),
+        _ => samples.into_iter().map(|v| v as u16).collect(),
+    };
+    let format = match (depth, maxval > 255) {
+        (1, false) => PixelFormat::Gray8,
+        (3, false) => PixelFormat::Rgb8,
+        (_, false) => PixelFormat::Rgba8,
+        (1, true) => PixelFormat::Gray16,
+        (3, true) => PixelFormat::Rgb16,
+        (_, true) => PixelFormat::Rgba16,
+    };
+    let img = Image::from_samples(width, height, format, &samples);
+    Ok((img, maxval as u16))
+}
+
+/// The `P7` header fields after the magic, up to and including `ENDHDR`.
+fn pam_header(t: &mut Tokens) -> Result<(u32, u32, usize, u32)> {
+    let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+    let mut tupltype: Option<&[u8]> = None;
+    loop {
+        match t.token()? {
+            b"WIDTH" => width = Some(t.number()?),
+            b"HEIGHT" => height = Some(t.number()?),
+            b"DEPTH" => depth = Some(t.number()?),
+            b"MAXVAL" => maxval = Some(t.number()?),
+            b"TUPLTYPE" => tupltype = Some(t.token()?),
+            b"ENDHDR" => break,
+            _ => return Err(MoeqiError::InvalidData("unknown PAM header field")),
+        }
+    }
+    let (Some(width), Some(height), Some(depth), Some(maxval)) = (width, height, depth, maxval)
+    else {
+        return Err(MoeqiError::InvalidData("PAM header field missing"));
+    };
+    let expected = match tupltype {
+        Some(b"BLACKANDWHITE" | b"GRAYSCALE") => Some(1),
+        Some(b"BLACKANDWHITE_ALPHA" | b"GRAYSCALE_ALPHA") => Some(2),
+        Some(b"RGB") => Some(3),
+        Some(b"RGB_ALPHA") => Some(4),
+        _ => None,
+    };
+    if !(1..=4).contains(&depth) || expected.is_some_and(|d| d != depth) {
+        return Err(MoeqiError::Unsupported("PAM tuple type"));
+    }
+    Ok((width, height, depth as usize, maxval))
+}
+
+/// Whitespace-separated header tokens; `#` comments run to the end of the
+/// line.
+struct Tokens<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn token(&mut self) -> Result<&'a [u8]> {
+        loop {
+            match self.bytes.get(self.pos) {
+                Some(b'#') => {
+                    while self.bytes.get(self.pos).is_some_and(|&b| b != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                Some(b) if b.is_ascii_whitespace() => self.pos += 1,
+                Some(_) => break,
+                None => return Err(MoeqiError::Eof),
+            }
+        }
+        let start = self.pos;
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| !b.is_ascii_whitespace() && *b != b'#')
+        {
+            self.pos += 1;
+        }
+        Ok(&self.bytes[start..self.pos])
+    }
+
+    fn number(&mut self) -> Result<u32> {
+        std::str::from_utf8(self.token()?)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or(MoeqiError::InvalidData("PNM number expected"))
+    }
+}
+
+/// Write `img` with a maxval of 255 or 65535, whichever its sample size
+/// allows.
+pub fn encode(img: &Image, kind: Kind) -> Result<Vec<u8>> {
+    let maxval = match img.format.bytes_per_sample() {
+        1 => 255,
+        _ => u16::MAX,
+    };
+    encode_with_maxval(img, kind, maxval)
+}
+
+/// Write `img` with the given maxval: at most 255 for the 8-bit formats,
+/// above for the 16-bit ones, and no sample above it.
+pub fn encode_with_maxval(img: &Image, kind: Kind, maxval: u16) -> Result<Vec<u8>> {
+    if !img.validate() {
+        return Err(MoeqiError::InvalidData("image data length mismatch"));
+    }
+    let wide = img.format.bytes_per_sample() == 2;
+    if maxval == 0 || (maxval > 255) != wide {
+        return Err(MoeqiError::InvalidData(
+            "maxval does not match the sample size",
+        ));
+    }
+    let samples = img.samples();
+    if samples.iter().any(|&v| v > maxval) {
+        return Err(MoeqiError::InvalidData("sample above maxval"));
+    }
+
+    let (w, h, ch) = (img.width, img.height, img.format.channels());
+    let mut out = match (kind, ch) {
+        (Kind::Pam, _) => {
+            let tupltype = match ch {
+                1 => "GRAYSCALE",
+                3 => "RGB",
+                _ => "RGB_ALPHA",
+            };
+            format!(
+                "P7\nWIDTH {w}\nHEIGHT {h}\nDEPTH {ch}\nMAXVAL {maxval}\nTUPLTYPE {tupltype}\nENDHDR\n"
+            )
+        }
+        (_, 4) => {
+            return Err(MoeqiError::Unsupported(
+                "PGM/PPM have no alpha; use PAM",
+            ))
+        }
+        (Kind::Plain, _) => format!("{}\n{w} {h}\n{maxval}\n", if ch == 1 { "P2" } else { "P3" }),
+        (Kind::Raw, _) => format!("{}\n{w} {h}\n{maxval}\n", if ch == 1 { "P5" } else { "P6" }),
+    }
+    .into_bytes();
+
+    match kind {
+        Kind::Plain => {
+            // Netpbm keeps plain lines within 70 characters
+            let mut line = 0;
+            for v in samples {
+                let text = v.to_string();
+                if line > 0 && line + 1 + text.len() > 70 {
+                    out.push(b'\n');
+                    line = 0;
+                } else if line > 0 {
+                    out.push(b' ');
+                    line += 1;
+                }
+                out.extend_from_slice(text.as_bytes());
+                line += text.len();
+            }
+            out.push(b'\n');
+        }
+        Kind::Raw | Kind::Pam if wide => out.extend(samples.iter().flat_map(|v| v.to_be_bytes())),
+        Kind::Raw | Kind::Pam => out.extend_from_slice(&img.data),
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(format: PixelFormat, max: u16) -> Image {
+        let samples: Vec<u16> = (0..5 * 3 * format.channels() as u32)
+            .map(|i| (i * 7919 % (max as u32 + 1)) as u16)
+            .collect();
+        Image::from_samples(5, 3, format, &samples)
+    }
+
+    #[test]
+    fn roundtrip_every_kind() {
+        for format in [
+            PixelFormat::Gray8,
+            PixelFormat::Rgb8,
+            PixelFormat::Rgba8,
+            PixelFormat::Gray16,
+            PixelFormat::Rgb16,
+            PixelFormat::Rgba16,
+        ] {
+            for kind in [Kind::Plain, Kind::Raw, Kind::Pam] {
+                let maxval = match format.bytes_per_sample() {
+                    1 => 200,
+                    _ => 1023,
+                };
+                let img = image(format, maxval);
+                let bytes = encode_with_maxval(&img, kind, maxval);
+                if format.channels() == 4 && kind != Kind::Pam {
+                    assert!(matches!(bytes, Err(MoeqiError::Unsupported(_))));
+                    continue;
+                }
+                let back = decode_with_maxval(&bytes.unwrap()).unwrap();
+                assert_eq!(back, (img.clone(), maxval), "{format:?} {kind:?}");
+                assert_eq!(decode(&encode(&img, kind).unwrap()).unwrap(), img);
+            }
+        }
+    }
+
+    #[test]
+    fn parses_comments_and_gray_alpha() {
+        let plain = b"P2 # two by one\n2 1\n# maxval\n15\n3\n 15 ";
+        assert_eq!(decode(plain).unwrap().data, [3, 15]);
+
+        let pam = b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 2\nMAXVAL 255\n\
+                    TUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n\x10\xff\x20\x00";
+        let img = decode(pam).unwrap();
+        assert_eq!(img.format, PixelFormat::Rgba8);
+        assert_eq!(img.data, [16, 16, 16, 255, 32, 32, 32, 0]);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        assert!(matches!(decode(b"P5 2 1 255\n\x01"), Err(MoeqiError::Eof)));
+        assert!(decode(b"P2 2 1 9\n3 10").is_err());
+        assert!(matches!(
+            decode(b"P5 100000 100000 255\n\x00"),
+            Err(MoeqiError::Eof)
+        ));
+        assert!(decode(b"P2 100000 100000 255\n1 2 3").is_err());
+        assert!(decode(b"P5 2 1 0\n\x00\x00").is_err());
+        assert!(decode(b"P5 2 1 65536\n\x00\x00").is_err());
+        assert!(matches!(
+            decode(b"P4 1 1\n\x00"),
+            Err(MoeqiError::Unsupported(_))
+        ));
+        let mismatch =
+            b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 3\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n...";
+        assert!(decode(mismatch).is_err());
+        assert!(decode(b"P7\nWIDTH 1\nENDHDR\n").is_err());
+
+        let img = image(PixelFormat::Gray8, 255);
+        assert!(encode_with_maxval(&img, Kind::Raw, 100).is_err());
+        assert!(encode_with_maxval(&img, Kind::Raw, 1000).is_err());
+    }
+}
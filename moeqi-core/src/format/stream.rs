@@ -0,0 +1,418 @@
+//! Row-by-row `MOEQI2` coding over [`Read`] / [`Write`].
+//!
+//! The image is cut into bands of [`Encoder::with_band_rows`] rows, each
+//! coded on its own into a `BAND` chunk (`varint rows | payload`) as soon as
+//! its rows are in. Memory stays proportional to the width times the band
+//! height on both ends. Tiled and palette configs need the whole image and
+//! are refused. The files are ordinary `MOEQI2` files flagged with
+//! [`FEATURE_BANDS`]; [`moeqi2::decode`] and [`moeqi2::decode_region`] read
+//! them too.
+
+use std::io::{Read, Write};
+
+use crate::codec::{self, varint};
+use crate::error::{MoeqiError, Result};
+use crate::format::crc32::Crc32;
+use crate::format::moeqi2::{
+    self, check_features, corrupt, decode_band, Header, Metadata, BAND, CONF, END, FEATURE_BANDS,
+    HEAD, MAGIC, META, VERSION,
+};
+use crate::types::{CodecConfig, Image, PixelFormat};
+
+/// Rows per band unless [`Encoder::with_band_rows`] says otherwise.
+pub const DEFAULT_BAND_ROWS: u32 = 16;
+
+/// Writes an image given a few rows at a time.
+pub struct Encoder<W: Write> {
+    out: W,
+    header: Header,
+    config: CodecConfig,
+    band_rows: u32,
+    /// Rows handed to [`Encoder::write_rows`] so far.
+    rows: u32,
+    /// Rows of the band being filled.
+    band: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Write the file header for a `width`×`height` image; rows follow with
+    /// [`Encoder::write_rows`].
+    pub fn new(
+        mut out: W,
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+        cfg: CodecConfig,
+    ) -> Result<Self> {
+        if cfg.tile_size != 0 {
+            return Err(MoeqiError::Unsupported(
+                "streamed files are banded, not tiled",
+            ));
+        }
+        if cfg.palette {
+            return Err(MoeqiError::Unsupported("a palette needs the whole image"));
+        }
+        let header = Header {
+            version: VERSION,
+            features: moeqi2::config_features(&cfg) | FEATURE_BANDS,
+            width,
+            height,
+            format,
+            bit_depth: cfg.bit_depth,
+        };
+        let mut head = MAGIC.to_vec();
+        moeqi2::push_chunk(&mut head, HEAD, &moeqi2::encode_head(&header)?)?;
+        moeqi2::push_chunk(&mut head, CONF, &moeqi2::encode_conf(&cfg))?;
+        out.write_all(&head)?;
+        Ok(Self {
+            out,
+            header,
+            config: cfg,
+            band_rows: DEFAULT_BAND_ROWS,
+            rows: 0,
+            band: Vec::new(),
+        })
+    }
+
+    /// Rows per band (at least 1). Taller bands code slightly better and
+    /// hold more memory.
+    pub fn with_band_rows(mut self, rows: u32) -> Self {
+        self.band_rows = rows.max(1);
+        self
+    }
+
+    /// Bytes per row of the source image.
+    pub fn row_bytes(&self) -> usize {
+        let f = self.header.format;
+        self.header.width as usize * f.channels() * f.bytes_per_sample()
+    }
+
+    /// Append whole rows, tightly packed as in [`Image::data`].
+    pub fn write_rows(&mut self, mut data: &[u8]) -> Result<()> {
+        let row_bytes = self.row_bytes();
+        if row_bytes == 0 || !data.len().is_multiple_of(row_bytes) {
+            return match data.is_empty() {
+                true => Ok(()),
+                false => Err(MoeqiError::InvalidData("partial row")),
+            };
+        }
+        if (data.len() / row_bytes) as u64 > (self.header.height - self.rows) as u64 {
+            return Err(MoeqiError::InvalidData("more rows than the image height"));
+        }
+        while !data.is_empty() {
+            let want = self.band_rows as usize * row_bytes - self.band.len();
+            let take = want.min(data.len());
+            self.band.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            self.rows += (take / row_bytes) as u32;
+            if self.band.len() == self.band_rows as usize * row_bytes {
+                self.flush_band()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_band(&mut self) -> Result<()> {
+        let rows = (self.band.len() / self.row_bytes()) as u32;
+        let img = Image {
+            width: self.header.width,
+            height: rows,
+            format: self.header.format,
+            data: std::mem::take(&mut self.band),
+        };
+        let mut d = Vec::new();
+        varint::encode_u32_var(rows, &mut d);
+        d.extend(codec::encode_payload(&img, self.config)?);
+        let mut chunk = Vec::with_capacity(d.len() + 12);
+        moeqi2::push_chunk(&mut chunk, BAND, &d)?;
+        self.out.write_all(&chunk)?;
+        self.band = img.data;
+        self.band.clear();
+        Ok(())
+    }
+
+    /// Code the last band and close the file; every row must be written.
+    pub fn finish(mut self) -> Result<W> {
+        if !self.band.is_empty() {
+            self.flush_band()?;
+        }
+        if self.rows != self.header.height {
+            return Err(MoeqiError::InvalidData("image incomplete"));
+        }
+        let mut end = Vec::with_capacity(12);
+        moeqi2::push_chunk(&mut end, END, &[])?;
+        self.out.write_all(&end)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Reads a streamed image a few rows at a time.
+pub struct Decoder<R: Read> {
+    input: R,
+    header: Header,
+    config: CodecConfig,
+    /// Rows handed out so far.
+    rows: u32,
+    /// Rows decoded so far, including the current band.
+    decoded: u32,
+    /// Decoded band and how much of it is handed out.
+    band: Vec<u8>,
+    pos: usize,
+    metadata: Metadata,
+    ended: bool,
+}
+
+/// Read one chunk, checking its CRC.
+fn read_chunk<R: Read>(r: &mut R) -> Result<([u8; 4], Vec<u8>)> {
+    let mut head = [0u8; 8];
+    r.read_exact(&mut head).map_err(eof)?;
+    let len = u32::from_le_bytes(head[..4].try_into().unwrap());
+    let kind: [u8; 4] = head[4..].try_into().unwrap();
+    let mut data = Vec::new();
+    r.take(len as u64).read_to_end(&mut data).map_err(eof)?;
+    let mut crc = [0u8; 4];
+    if data.len() != len as usize || r.read_exact(&mut crc).is_err() {
+        return Err(corrupt(kind, "truncated"));
+    }
+    if Crc32::default().update(&kind).update(&data).finish() != u32::from_le_bytes(crc) {
+        return Err(corrupt(kind, "crc mismatch"));
+    }
+    Ok((kind, data))
+}
+
+fn eof(e: std::io::Error) -> MoeqiError {
+    match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => MoeqiError::Eof,
+        _ => MoeqiError::Io(e),
+    }
+}
+
+impl<R: Read> Decoder<R> {
+    /// Read the file header of a streamed `MOEQI2` file.
+    pub fn new(mut input: R) -> Result<Self> {
+        let mut magic = [0u8; 6];
+        input.read_exact(&mut magic).map_err(eof)?;
+        if &magic != MAGIC {
+            return Err(MoeqiError::InvalidData("bad magic"));
+        }
+        let header = match read_chunk(&mut input)? {
+            (HEAD, d) => moeqi2::decode_head(&d)?,
+            _ => return Err(corrupt(HEAD, "missing")),
+        };
+        let mut config = match read_chunk(&mut input)? {
+            (CONF, d) => moeqi2::decode_conf(&d)?,
+            _ => return Err(corrupt(CONF, "missing")),
+        };
+        config.bit_depth = header.bit_depth;
+        check_features(&header, &config)?;
+        if header.features & FEATURE_BANDS == 0 {
+            return Err(MoeqiError::Unsupported("not a streamed MOEQI2 file"));
+        }
+        Ok(Self {
+            input,
+            header,
+            config,
+            rows: 0,
+            decoded: 0,
+            band: Vec::new(),
+            pos: 0,
+            metadata: Vec::new(),
+            ended: false,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn config(&self) -> CodecConfig {
+        self.config
+    }
+
+    /// Bytes per row of the decoded image.
+    pub fn row_bytes(&self) -> usize {
+        let f = self.header.format;
+        self.header.width as usize * f.channels() * f.bytes_per_sample()
+    }
+
+    /// Fill `buf` with whole rows, tightly packed as in [`Image::data`].
+    /// Returns the number of rows read: fewer than fit only at the end of
+    /// the image, 0 once every row is out.
+    pub fn read_rows(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let row_bytes = self.row_bytes();
+        if row_bytes == 0 {
+            return Ok(0);
+        }
+        let mut filled = 0;
+        while buf.len() - filled >= row_bytes {
+            if self.pos == self.band.len() && !self.next_band()? {
+                break;
+            }
+            let rows =
+                ((buf.len() - filled) / row_bytes).min((self.band.len() - self.pos) / row_bytes);
+            let n = rows * row_bytes;
+            buf[filled..filled + n].copy_from_slice(&self.band[self.pos..self.pos + n]);
+            self.pos += n;
+            filled += n;
+        }
+        self.rows += (filled / row_bytes) as u32;
+        Ok(filled / row_bytes)
+    }
+
+    /// Decode the next band; false once the image is complete.
+    fn next_band(&mut self) -> Result<bool> {
+        if self.decoded == self.header.height {
+            return Ok(false);
+        }
+        loop {
+            match read_chunk(&mut self.input)? {
+                (BAND, d) => {
+                    let (w, format) = (self.header.width, self.header.format);
+                    let img = decode_band(&d, w, format, self.config)?;
+                    if img.height > self.header.height - self.decoded {
+                        return Err(corrupt(BAND, "rows do not add up to the height"));
+                    }
+                    self.decoded += img.height;
+                    self.band = img.data;
+                    self.pos = 0;
+                    return Ok(true);
+                }
+                (END, _) => return Err(corrupt(BAND, "rows do not add up to the height")),
+                (k, _) if k[0].is_ascii_lowercase() => {}
+                _ => return Err(MoeqiError::Unsupported("unknown critical MOEQI2 chunk")),
+            }
+        }
+    }
+
+    /// Read the chunks after the last band through `END `, returning any
+    /// `META` pairs. Every row must have been read.
+    pub fn finish(mut self) -> Result<Metadata> {
+        if self.rows != self.header.height {
+            return Err(MoeqiError::InvalidData("rows left unread"));
+        }
+        while !self.ended {
+            match read_chunk(&mut self.input)? {
+                (META, d) => self.metadata.extend(moeqi2::decode_meta(&d)?),
+                (END, _) => self.ended = true,
+                (k, _) if k[0].is_ascii_lowercase() => {}
+                (BAND, _) => return Err(corrupt(BAND, "rows do not add up to the height")),
+                _ => return Err(MoeqiError::Unsupported("unknown critical MOEQI2 chunk")),
+            }
+        }
+        Ok(self.metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{CodecKind, ColorTransform, Predictor, Subsampling};
+
+    fn gradient(w: u32, h: u32, format: PixelFormat) -> Image {
+        let ch = format.channels() as u32;
+        let data = (0..w * h * ch)
+            .map(|i| ((i / ch % w) * 3 + (i / ch / w) * 5 + i % ch * 40) as u8)
+            .collect();
+        Image {
+            width: w,
+            height: h,
+            format,
+            data,
+        }
+    }
+
+    #[test]
+    fn rows_in_rows_out() {
+        let img = gradient(53, 41, PixelFormat::Rgb8);
+        let cfg = CodecConfig {
+            codec: CodecKind::PredictArith,
+            predictor: Predictor::Med,
+            run_mode: true,
+            ..CodecConfig::default()
+        };
+        let row = img.data.len() / 41;
+        let mut enc = Encoder::new(Vec::new(), 53, 41, img.format, cfg)
+            .unwrap()
+            .with_band_rows(8);
+        // uneven writes across band boundaries
+        for part in img.data.chunks(row * 3) {
+            enc.write_rows(part).unwrap();
+        }
+        let bytes = enc.finish().unwrap();
+        assert_eq!(moeqi2::decode(&bytes).unwrap(), (img.clone(), cfg));
+        let region = moeqi2::decode_region(&bytes, 5, 6, 10, 20).unwrap();
+        assert_eq!(region.data[..3], img.data[(6 * 53 + 5) * 3..][..3]);
+
+        let mut dec = Decoder::new(bytes.as_slice()).unwrap();
+        assert_eq!(dec.config(), cfg);
+        let mut out = Vec::new();
+        let mut buf = vec![0u8; row * 5];
+        loop {
+            let n = dec.read_rows(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n * row]);
+        }
+        assert_eq!(out, img.data);
+        assert!(dec.finish().unwrap().is_empty());
+    }
+
+    #[test]
+    fn refuses_whole_image_configs_and_short_input() {
+        let cfg = CodecConfig {
+            color_transform: ColorTransform::YCbCr {
+                subsampling: Subsampling::S420,
+                siting: Default::default(),
+                upsampling: Default::default(),
+            },
+            ..CodecConfig::default()
+        };
+        let img = gradient(20, 9, PixelFormat::Rgb8);
+        let mut enc = Encoder::new(Vec::new(), 20, 9, img.format, cfg).unwrap();
+        enc.write_rows(&img.data[..60 * 4]).unwrap();
+        assert!(enc.write_rows(&img.data[..61]).is_err());
+        assert!(enc.finish().is_err());
+
+        let tiled = CodecConfig {
+            tile_size: 8,
+            ..CodecConfig::default()
+        };
+        assert!(Encoder::new(Vec::new(), 20, 9, img.format, tiled).is_err());
+
+        let mut enc = Encoder::new(Vec::new(), 20, 9, img.format, cfg).unwrap();
+        enc.write_rows(&img.data).unwrap();
+        let bytes = enc.finish().unwrap();
+        let mut dec = Decoder::new(&bytes[..bytes.len() - 30]).unwrap();
+        let mut buf = vec![0u8; img.data.len()];
+        assert!(dec.read_rows(&mut buf).is_err());
+    }
+
+    #[test]
+    fn oversized_head_is_corrupt() {
+        let img = gradient(20, 9, PixelFormat::Rgb8);
+        let mut enc = Encoder::new(Vec::new(), 20, 9, img.format, CodecConfig::default()).unwrap();
+        enc.write_rows(&img.data).unwrap();
+        let bytes = enc.finish().unwrap();
+
+        let huge = moeqi2::resize_head(&bytes, u32::MAX, u32::MAX);
+        assert!(matches!(
+            Decoder::new(huge.as_slice()),
+            Err(MoeqiError::CorruptChunk { chunk: HEAD, .. })
+        ));
+        let wide = moeqi2::resize_head(&bytes, 1 << 30, 9);
+        let too_short = |r: Result<_>| {
+            matches!(
+                r,
+                Err(MoeqiError::CorruptChunk {
+                    chunk: BAND,
+                    reason: "payload too short for its rows"
+                })
+            )
+        };
+        assert!(too_short(moeqi2::decode(&wide).map(|_| ())));
+        let mut dec = Decoder::new(wide.as_slice()).unwrap();
+        assert!(too_short(dec.next_band().map(|_| ())));
+    }
+}
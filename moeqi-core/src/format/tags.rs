@@ -0,0 +1,134 @@
+//! Byte tags shared by the `MOEQI1` and `MOEQI2` containers.
+
+use crate::error::{MoeqiError, Result};
+use crate::types::{
+    ChromaSiting, CodecKind, ColorTransform, PixelFormat, Predictor, Subsampling, Upsampling,
+};
+
+// Low nibble: channel count. High nibble: 0 for the 8-bit formats, `depth - 1`
+// (8..=15) for the 16-bit ones, so the 9..=16 bit depth rides along.
+
+/// Tag for `f` holding `bit_depth`-bit samples (a [`CodecConfig::bit_depth`]).
+///
+/// [`CodecConfig::bit_depth`]: crate::types::CodecConfig::bit_depth
+pub(crate) fn format_tag(f: PixelFormat, bit_depth: u8) -> Result<u8> {
+    let depth = f.bit_depth(bit_depth).ok_or(MoeqiError::InvalidData(
+        "bit depth does not fit the pixel format",
+    ))?;
+    let channels = f.channels() as u8;
+    Ok(match f.bytes_per_sample() {
+        1 => channels,
+        _ => ((depth - 1) << 4) | channels,
+    })
+}
+
+/// Pixel format and [`CodecConfig::bit_depth`] (0 for the full depth) of a tag.
+///
+/// [`CodecConfig::bit_depth`]: crate::types::CodecConfig::bit_depth
+pub(crate) fn format_from_tag(tag: u8) -> Result<(PixelFormat, u8)> {
+    let wide = tag >> 4 != 0;
+    let f = match (tag & 0xF, wide) {
+        (1, false) => PixelFormat::Gray8,
+        (3, false) => PixelFormat::Rgb8,
+        (4, false) => PixelFormat::Rgba8,
+        (1, true) => PixelFormat::Gray16,
+        (3, true) => PixelFormat::Rgb16,
+        (4, true) => PixelFormat::Rgba16,
+        _ => return Err(MoeqiError::InvalidData("bad pixel format")),
+    };
+    let depth = match tag >> 4 {
+        0 | 15 => 0,
+        h @ 8..=14 => h + 1,
+        _ => return Err(MoeqiError::InvalidData("bad pixel format")),
+    };
+    Ok((f, depth))
+}
+
+// YCbCr: 0x10 | subsampling (bits 0..=1) | siting (bit 2) | upsampling (bit 3).
+
+pub(crate) fn color_transform_tag(t: ColorTransform) -> u8 {
+    match t {
+        ColorTransform::None => 0,
+        ColorTransform::YCoCgR => 1,
+        ColorTransform::YCbCr {
+            subsampling,
+            siting,
+            upsampling,
+        } => {
+            let s = match subsampling {
+                Subsampling::S444 => 0,
+                Subsampling::S422 => 1,
+                Subsampling::S420 => 2,
+            };
+            let siting = match siting {
+                ChromaSiting::Centered => 0,
+                ChromaSiting::Cosited => 1,
+            };
+            let up = match upsampling {
+                Upsampling::Nearest => 0,
+                Upsampling::Bilinear => 1,
+            };
+            0x10 | s | (siting << 2) | (up << 3)
+        }
+    }
+}
+
+pub(crate) fn color_transform_from_tag(tag: u8) -> Result<ColorTransform> {
+    Ok(match tag {
+        0 => ColorTransform::None,
+        1 => ColorTransform::YCoCgR,
+        0x10..=0x1F => ColorTransform::YCbCr {
+            subsampling: match tag & 3 {
+                0 => Subsampling::S444,
+                1 => Subsampling::S422,
+                2 => Subsampling::S420,
+                _ => return Err(MoeqiError::InvalidData("bad color transform")),
+            },
+            siting: match tag & 4 {
+                0 => ChromaSiting::Centered,
+                _ => ChromaSiting::Cosited,
+            },
+            upsampling: match tag & 8 {
+                0 => Upsampling::Nearest,
+                _ => Upsampling::Bilinear,
+            },
+        },
+        _ => return Err(MoeqiError::InvalidData("bad color transform")),
+    })
+}
+
+pub(crate) fn predictor_tag(p: Predictor) -> u8 {
+    match p {
+        Predictor::Left => 0,
+        Predictor::Up => 1,
+        Predictor::Average => 2,
+        Predictor::Paeth => 3,
+        Predictor::Med => 4,
+    }
+}
+
+pub(crate) fn predictor_from_tag(tag: u8) -> Result<Predictor> {
+    Ok(match tag {
+        0 => Predictor::Left,
+        1 => Predictor::Up,
+        2 => Predictor::Average,
+        3 => Predictor::Paeth,
+        4 => Predictor::Med,
+        _ => return Err(MoeqiError::InvalidData("bad predictor")),
+    })
+}
+
+pub(crate) fn codec_tag(c: CodecKind) -> u8 {
+    match c {
+        CodecKind::PredictVarint => 0,
+        CodecKind::PredictArith => 1,
+    }
+}
+
+pub(crate) fn codec_from_tag(tag: u8) -> Result<CodecKind> {
+    Ok(match tag {
+        0 => CodecKind::PredictVarint,
+        1 => CodecKind::PredictArith,
+        _ => return Err(MoeqiError::InvalidData("bad codec")),
+    })
+}
@@ -1,10 +1,19 @@
 #![doc = include_str!("../README.md")]
 
 pub mod codec;
+pub mod color;
+#[cfg(feature = "image")]
+mod dynamic;
 pub mod error;
 pub mod format;
+pub mod metrics;
+pub mod moe;
+pub mod parallel;
 pub mod train;
 pub mod types;
 
 pub use error::{MoeqiError, Result};
-pub use types::{CodecConfig, CodecKind, ColorTransform, Image, PixelFormat};
+pub use types::{
+    AlphaConfig, ChannelLayout, ChromaSiting, CodecConfig, CodecKind, ColorTransform, Image,
+    PixelFormat, PlaneConfig, Predictor, Subsampling, Upsampling,
+};
@@ -1,11 +1,14 @@
-use crate::model::Model;
+use crate::moe::model::Model;
 
+/// Residual entropy coder of a `MOEQIBIN` file.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Codec {
     Varint,
     Huff,
 }
 
+/// In-memory form of a `MOEQIBIN` v2 file.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Bitstream {
     pub w: u16,
     pub h: u16,
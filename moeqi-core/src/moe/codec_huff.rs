@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+use crate::error::{MoeqiError, Result};
+use crate::moe::huff_canonical::{build_lengths, build_tree, canonical_codes, Node, MAX_CODE_LEN};
+use crate::moe::huff_lut::{HuffLut, MAX_LUT_LEN};
+
+/// Huffman-code `vals` with a table fitted to their histogram.
+///
+/// Returns `(payload, huff_symbols, huff_lengths)` as stored in `MOEQIBIN`; the
+/// payload is LSB-first, each code MSB first, as [`decode_huff_i16`] reads it.
+/// An empty input gives an empty payload and table.
+pub fn encode_huff_i16(vals: &[i16]) -> Result<(Vec<u8>, Vec<i16>, Vec<u8>)> {
+    if vals.is_empty() {
+        return Ok((Vec::new(), Vec::new(), Vec::new()));
+    }
+
+    let mut hist: BTreeMap<i16, u64> = BTreeMap::new();
+    for &v in vals {
+        *hist.entry(v).or_insert(0) += 1;
+    }
+    let hist: Vec<(i16, u64)> = hist.into_iter().collect();
+    let (symbols, lengths) = build_lengths(&hist, MAX_CODE_LEN)?;
+
+    // (code, len) by symbol + 32768
+    let mut table = vec![(0u32, 0u8); 1 << 16];
+    for (sym, code, len) in canonical_codes(&symbols, &lengths)? {
+        table[(sym as i32 + 32768) as usize] = (code, len);
+    }
+
+    let mut out = Vec::with_capacity(vals.len() / 2);
+    let mut acc = 0u64;
+    let mut nbits = 0u32;
+    for &v in vals {
+        let (code, len) = table[(v as i32 + 32768) as usize];
+        for i in (0..len as u32).rev() {
+            acc |= (((code >> i) & 1) as u64) << nbits;
+            nbits += 1;
+        }
+        while nbits >= 8 {
+            out.push(acc as u8);
+            acc >>= 8;
+            nbits -= 8;
+        }
+    }
+    if nbits > 0 {
+        out.push(acc as u8);
+    }
+
+    Ok((out, symbols, lengths))
+}
+
+/// Decode exactly `count` residuals coded with the canonical table `symbols`/`lengths`.
+///
+/// Uses the table-driven [`HuffLut`]; tables with codes longer than
+/// [`MAX_LUT_LEN`] fall back to [`decode_huff_i16_tree`].
+pub fn decode_huff_i16(
+    payload: &[u8],
+    count: usize,
+    symbols: &[i16],
+    lengths: &[u8],
+) -> Result<Vec<i16>> {
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    if lengths.iter().any(|&l| l > MAX_LUT_LEN) {
+        return decode_huff_i16_tree(payload, count, symbols, lengths);
+    }
+    HuffLut::new(symbols, lengths)?.decode(payload, count)
+}
+
+/// Reference decoder walking the code tree one bit at a time.
+pub fn decode_huff_i16_tree(
+    payload: &[u8],
+    count: usize,
+    symbols: &[i16],
+    lengths: &[u8],
+) -> Result<Vec<i16>> {
+    let mut out = Vec::with_capacity(count);
+    if count == 0 {
+        return Ok(out);
+    }
+    let root = build_tree(symbols, lengths)?;
+
+    let mut node: &Node = &root;
+
+    for &byte in payload {
+        let mut b = byte;
+        // LSB-first bits (matches your JS encoder assumption)
+        for _ in 0..8 {
+            let bit = (b & 1) != 0;
+            b >>= 1;
+
+            node = if bit {
+                node.right.as_deref()
+            } else {
+                node.left.as_deref()
+            }
+            .ok_or(MoeqiError::InvalidData("huff code not in table"))?;
+
+            if let Some(sym) = node.sym {
+                out.push(sym);
+                if out.len() == count {
+                    return Ok(out);
+                }
+                node = &root;
+            }
+        }
+    }
+    Err(MoeqiError::Eof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_skewed_residuals() {
+        let mut seed = 7u32;
+        let vals: Vec<i16> = (0..5000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let r = (seed >> 16) as i16 % 64;
+                if r.abs() < 40 { r / 8 } else { r * 37 }
+            })
+            .collect();
+        let (payload, syms, lens) = encode_huff_i16(&vals).unwrap();
+        assert!(payload.len() < vals.len());
+        assert_eq!(decode_huff_i16(&payload, vals.len(), &syms, &lens).unwrap(), vals);
+    }
+
+    #[test]
+    fn single_symbol() {
+        let vals = [3i16; 20];
+        let (payload, syms, lens) = encode_huff_i16(&vals).unwrap();
+        assert_eq!((syms.as_slice(), lens.as_slice()), (&[3i16][..], &[1u8][..]));
+        assert_eq!(decode_huff_i16(&payload, 20, &syms, &lens).unwrap(), vals);
+    }
+
+    #[test]
+    fn lut_matches_tree_with_long_codes() {
+        // Fibonacci-ish frequencies push the rare symbols into the secondary tables
+        let mut vals = Vec::new();
+        let (mut a, mut b) = (1usize, 2usize);
+        for s in 0..20i16 {
+            vals.extend(std::iter::repeat_n(s * 3 - 30, a));
+            (a, b) = (b, a + b);
+        }
+        let mut seed = 99u32;
+        for i in (1..vals.len()).rev() {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            vals.swap(i, (seed >> 8) as usize % (i + 1));
+        }
+
+        let (payload, syms, lens) = encode_huff_i16(&vals).unwrap();
+        assert!(lens.iter().any(|&l| l as u32 > crate::moe::huff_lut::PRIMARY_BITS));
+        let lut = decode_huff_i16(&payload, vals.len(), &syms, &lens).unwrap();
+        let tree = decode_huff_i16_tree(&payload, vals.len(), &syms, &lens).unwrap();
+        assert_eq!(lut, vals);
+        assert_eq!(tree, vals);
+
+        assert!(matches!(
+            decode_huff_i16(&payload[..payload.len() - 2], vals.len(), &syms, &lens),
+            Err(MoeqiError::Eof)
+        ));
+    }
+}
@@ -1,18 +1,20 @@
-use crate::{MoeqiError};
-use crate::bitstream::{Bitstream, Codec};
-use crate::model::{FEAT, router_argmax, dot7};
+use crate::error::{MoeqiError, Result};
+use crate::moe::bitstream::{Bitstream, Codec};
+use crate::moe::model::{FEAT, Model, router_argmax, dot7};
 
-use crate::codec_varint::decode_varint_i16;
-use crate::codec_huff::decode_huff_i16;
-use crate::codec_varint::encode_varint_i16;
+use crate::moe::codec_varint::decode_varint_i16;
+use crate::moe::codec_huff::decode_huff_i16;
 
 #[inline]
-fn clamp_u8(x: i32) -> u8 {
+pub(crate) fn clamp_u8(x: i32) -> u8 {
     if x < 0 { 0 } else if x > 255 { 255 } else { x as u8 }
 }
 
+/// Router/expert features of pixel `(x, y)`: a bias term, its left, up and
+/// up-left neighbours scaled to `0..=1`, and their pairwise differences.
+/// Missing neighbours on the borders fall back to the pixel itself.
 #[inline]
-fn feat_at(x: usize, y: usize, w: usize, luma: &[u8]) -> [f32; FEAT] {
+pub fn feat_at(x: usize, y: usize, w: usize, luma: &[u8]) -> [f32; FEAT] {
     let idx = y*w + x;
     let cur = luma[idx] as i32;
     let l  = if x>0 { luma[idx-1] as i32 } else { cur };
@@ -34,10 +36,23 @@ fn feat_at(x: usize, y: usize, w: usize, luma: &[u8]) -> [f32; FEAT] {
     ]
 }
 
-pub fn decode_luma(bs: &Bitstream) -> Result<Vec<u8>, MoeqiError> {
+/// Prediction of the expert chosen by the router for features `f`, in pixel units.
+#[inline]
+pub fn predict(model: &Model, f: &[f32; FEAT]) -> i32 {
+    let k = router_argmax(model, f);
+    let mu = dot7(model.we_row(k), f);
+    // explicit rounding point (important for consistency)
+    (mu * 255.0).round() as i32
+}
+
+/// Reconstruct the Gray8 plane of `bs`, row-major, `w * h` bytes.
+pub fn decode_luma(bs: &Bitstream) -> Result<Vec<u8>> {
     let w = bs.w as usize;
     let h = bs.h as usize;
 
+    if w == 0 || h == 0 { return Err(MoeqiError::InvalidData("zero-sized image")); }
+    if !bs.model.validate() { return Err(MoeqiError::InvalidData("model shape")); }
+
     if bs.first_row.len() != w { return Err(MoeqiError::Format("first_row length")); }
     if bs.first_col.len() != h { return Err(MoeqiError::Format("first_col length")); }
 
@@ -48,14 +63,13 @@ pub fn decode_luma(bs: &Bitstream) -> Result<Vec<u8>, MoeqiError> {
 
     // Decode residuals to i16 qi
     let qi: Vec<i16> = match bs.codec {
-        Codec::Varint => decode_varint_i16(&bs.payload, bs.residuals_count as usize)
-            .map_err(|_| MoeqiError::Decode("varint"))?,
+        Codec::Varint => decode_varint_i16(&bs.payload, bs.residuals_count as usize)?,
         Codec::Huff => decode_huff_i16(
             &bs.payload,
             bs.residuals_count as usize,
             &bs.huff_symbols,
             &bs.huff_lengths,
-        ).map_err(|_| MoeqiError::Decode("huff"))?,
+        )?,
     };
 
     let mut recon = vec![0u8; w*h];
@@ -72,11 +86,7 @@ pub fn decode_luma(bs: &Bitstream) -> Result<Vec<u8>, MoeqiError> {
     for y in 1..h {
         for x in 1..w {
             let f = feat_at(x, y, w, &recon);
-            let k = router_argmax(&bs.model, &f);
-
-            let mu = dot7(bs.model.we_row(k), &f);
-            // explicit rounding point (important for consistency)
-            let pred = (mu * 255.0).round() as i32;
+            let pred = predict(&bs.model, &f);
 
             let q = qi[ri] as i32;
             ri += 1;
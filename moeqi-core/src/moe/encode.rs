@@ -0,0 +1,125 @@
+use crate::error::{MoeqiError, Result};
+use crate::moe::bitstream::{Bitstream, Codec};
+use crate::moe::codec_huff::encode_huff_i16;
+use crate::moe::codec_varint::encode_varint_i16;
+use crate::moe::decode::{clamp_u8, feat_at, predict};
+use crate::moe::model::Model;
+use crate::types::{Image, PixelFormat};
+
+/// Quantize `r` to the nearest multiple of `qstep` (ties away from zero).
+#[inline]
+fn quantize(r: i64, qstep: i64) -> i16 {
+    let q = (r + (qstep / 2) * r.signum()) / qstep;
+    q.clamp(i16::MIN as i64, i16::MAX as i64) as i16
+}
+
+/// Quantize the MoE prediction residuals of a Gray8 image into a [`Bitstream`]
+/// whose residuals are entropy-coded with `codec`.
+///
+/// Predictions are taken from reconstructed neighbours exactly as
+/// [`decode_luma`](crate::moe::decode_luma) sees them, so lossy `qstep`s don't drift.
+/// `qstep == 1` is lossless.
+pub fn encode_luma(img: &Image, model: &Model, qstep: u16, codec: Codec) -> Result<Bitstream> {
+    if img.format != PixelFormat::Gray8 { return Err(MoeqiError::Unsupported("MOEQIBIN is Gray8 only")); }
+    if !img.validate() { return Err(MoeqiError::InvalidData("image data length mismatch")); }
+    if !model.validate() { return Err(MoeqiError::InvalidData("model shape")); }
+    if qstep == 0 { return Err(MoeqiError::InvalidData("qstep must be >= 1")); }
+
+    let w16 = u16::try_from(img.width).map_err(|_| MoeqiError::Unsupported("width > 65535"))?;
+    let h16 = u16::try_from(img.height).map_err(|_| MoeqiError::Unsupported("height > 65535"))?;
+    if w16 == 0 || h16 == 0 { return Err(MoeqiError::InvalidData("zero-sized image")); }
+
+    let w = w16 as usize;
+    let h = h16 as usize;
+    let src = &img.data;
+
+    let first_row = src[0..w].to_vec();
+    let first_col: Vec<u8> = (0..h).map(|y| src[y*w]).collect();
+
+    let mut recon = vec![0u8; w*h];
+    recon[0..w].copy_from_slice(&first_row);
+    for y in 0..h {
+        recon[y*w] = first_col[y];
+    }
+
+    let qs = qstep as i32;
+    let mut qi = Vec::with_capacity((w - 1) * (h - 1));
+
+    for y in 1..h {
+        for x in 1..w {
+            let f = feat_at(x, y, w, &recon);
+            let pred = predict(model, &f);
+
+            let q = quantize(src[y*w + x] as i64 - pred as i64, qs as i64);
+            qi.push(q);
+            // same expression as the decoder
+            recon[y*w + x] = clamp_u8(pred + q as i32 * qs);
+        }
+    }
+
+    let (payload, huff_symbols, huff_lengths) = match codec {
+        Codec::Varint => (encode_varint_i16(&qi), Vec::new(), Vec::new()),
+        Codec::Huff => encode_huff_i16(&qi)?,
+    };
+
+    Ok(Bitstream {
+        w: w16,
+        h: h16,
+        qstep,
+        codec,
+        first_row,
+        first_col,
+        residuals_count: qi.len() as u32,
+        payload,
+        huff_symbols,
+        huff_lengths,
+        model: model.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moe::{decode_luma, pack_mqb, parse_mqb};
+
+    fn gradient_with_noise(w: u32, h: u32) -> Image {
+        let mut seed = 0x1234_5678u32;
+        let data = (0..w * h)
+            .map(|i| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let (x, y) = (i % w, i / w);
+                ((x * 3 + y * 2) as i32 + (seed >> 29) as i32 - 4).clamp(0, 255) as u8
+            })
+            .collect();
+        Image { width: w, height: h, format: PixelFormat::Gray8, data }
+    }
+
+    fn two_experts() -> Model {
+        Model {
+            e: 2,
+            wr: vec![0.0, 1.0, -1.0, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+            we: vec![0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, -1.0, 0.0, 0.0, 0.0],
+        }
+    }
+
+    #[test]
+    fn lossless_roundtrip() {
+        let img = gradient_with_noise(37, 23);
+        for codec in [Codec::Varint, Codec::Huff] {
+            let bs = encode_luma(&img, &two_experts(), 1, codec).unwrap();
+            let parsed = parse_mqb(&pack_mqb(&bs).unwrap()).unwrap();
+            assert_eq!(parsed, bs);
+            assert_eq!(decode_luma(&parsed).unwrap(), img.data);
+        }
+    }
+
+    #[test]
+    fn lossy_error_is_bounded_by_half_step() {
+        let img = gradient_with_noise(40, 30);
+        let bytes = crate::moe::encode(&img, &two_experts(), 6, Codec::Huff).unwrap();
+        let out = crate::moe::decode(&bytes).unwrap();
+        for (&a, &b) in img.data.iter().zip(out.data.iter()) {
+            assert!((a as i32 - b as i32).abs() <= 3);
+        }
+    }
+}
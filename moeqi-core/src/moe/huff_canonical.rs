@@ -0,0 +1,192 @@
+use crate::error::{MoeqiError, Result};
+
+/// Longest code [`build_lengths`] emits by default.
+pub const MAX_CODE_LEN: u8 = 15;
+
+#[derive(Default)]
+pub struct Node {
+    pub sym: Option<i16>,
+    pub left: Option<Box<Node>>,
+    pub right: Option<Box<Node>>,
+}
+
+/// Canonical `(symbol, code, length)` triples in (length, symbol) order.
+/// Codes are read MSB first, i.e. bit `len - 1` is the first bit on the wire.
+pub fn canonical_codes(symbols: &[i16], lengths: &[u8]) -> Result<Vec<(i16, u32, u8)>> {
+    if symbols.len() != lengths.len() { return Err(MoeqiError::InvalidData("huff table length mismatch")); }
+    let mut pairs: Vec<(u8, i16)> = symbols.iter().copied().zip(lengths.iter().copied())
+        .map(|(s,l)| (l,s)).collect();
+    pairs.sort_by_key(|(l,s)| (*l, *s));
+
+    if pairs.is_empty() { return Err(MoeqiError::InvalidData("empty huff table")); }
+    if pairs.iter().any(|&(l, _)| l == 0 || l > 32) {
+        return Err(MoeqiError::InvalidData("huff code length"));
+    }
+
+    let mut out = Vec::with_capacity(pairs.len());
+    // u64: with 32-bit codes, `code + 1` and the shift can pass u32::MAX
+    let mut code: u64 = 0;
+    let mut prev_len = pairs[0].0 as u32;
+
+    for (idx, (len, sym)) in pairs.into_iter().enumerate() {
+        let len = len as u32;
+        if idx == 0 {
+            code = 0;
+            prev_len = len;
+        } else {
+            code = (code + 1) << (len - prev_len);
+            prev_len = len;
+        }
+        if code >> len != 0 {
+            return Err(MoeqiError::InvalidData("huff table oversubscribed"));
+        }
+        out.push((sym, code as u32, len as u8));
+    }
+
+    Ok(out)
+}
+
+/// Build the decoding tree of a canonical Huffman code from per-symbol code lengths.
+pub fn build_tree(symbols: &[i16], lengths: &[u8]) -> Result<Node> {
+    let mut root = Node::default();
+
+    for (sym, code, len) in canonical_codes(symbols, lengths)? {
+        // insert bits MSB->LSB
+        let mut node = &mut root;
+        for i in (0..len).rev() {
+            let bit = (code >> i) & 1;
+            if bit == 0 {
+                if node.left.is_none() { node.left = Some(Box::new(Node::default())); }
+                node = node.left.as_deref_mut().unwrap();
+            } else {
+                if node.right.is_none() { node.right = Some(Box::new(Node::default())); }
+                node = node.right.as_deref_mut().unwrap();
+            }
+        }
+        node.sym = Some(sym);
+    }
+
+    Ok(root)
+}
+
+enum Item {
+    Leaf(usize),
+    Package(usize, usize),
+}
+
+/// Length-limited Huffman code lengths for a `(symbol, count)` histogram (package-merge).
+///
+/// Symbols with a zero count are dropped. Returns `huff_symbols`/`huff_lengths`
+/// in canonical (length, symbol) order, ready for a `MOEQIBIN` table. A lone
+/// symbol gets a 1-bit code.
+pub fn build_lengths(hist: &[(i16, u64)], max_len: u8) -> Result<(Vec<i16>, Vec<u8>)> {
+    let mut leaves: Vec<(u64, i16)> = hist.iter().filter(|&&(_, n)| n > 0).map(|&(s, n)| (n, s)).collect();
+    leaves.sort_unstable();
+    if leaves.windows(2).any(|p| p[0].1 == p[1].1) {
+        return Err(MoeqiError::InvalidData("duplicate huff symbol"));
+    }
+    let n = leaves.len();
+    if n == 0 { return Err(MoeqiError::InvalidData("empty huff table")); }
+    if max_len == 0 || max_len > 32 || (max_len < 32 && n > 1usize << max_len) {
+        return Err(MoeqiError::InvalidData("too many huff symbols for length limit"));
+    }
+
+    let mut lengths = vec![0u8; n];
+    if n == 1 {
+        lengths[0] = 1;
+    } else {
+        // levels[j] holds the (weight, item) list after j package+merge rounds
+        let mut levels: Vec<Vec<(u64, Item)>> = Vec::with_capacity(max_len as usize);
+        levels.push(leaves.iter().enumerate().map(|(i, &(w, _))| (w, Item::Leaf(i))).collect());
+        for _ in 1..max_len {
+            let prev = levels.last().unwrap();
+            let mut merged = Vec::with_capacity(n + prev.len() / 2);
+            let mut li = 0;
+            let mut pi = 0;
+            while li < n || pi + 1 < prev.len() {
+                let pkg_w = if pi + 1 < prev.len() { Some(prev[pi].0 + prev[pi + 1].0) } else { None };
+                match pkg_w {
+                    Some(pw) if li >= n || pw < leaves[li].0 => {
+                        merged.push((pw, Item::Package(pi, pi + 1)));
+                        pi += 2;
+                    }
+                    _ => {
+                        merged.push((leaves[li].0, Item::Leaf(li)));
+                        li += 1;
+                    }
+                }
+            }
+            levels.push(merged);
+        }
+
+        // every leaf inside the 2n-2 cheapest items of the last level adds one bit
+        let mut stack: Vec<(usize, usize)> = (0..2 * n - 2).map(|i| (levels.len() - 1, i)).collect();
+        while let Some((lvl, i)) = stack.pop() {
+            match levels[lvl][i].1 {
+                Item::Leaf(s) => lengths[s] += 1,
+                Item::Package(a, b) => {
+                    stack.push((lvl - 1, a));
+                    stack.push((lvl - 1, b));
+                }
+            }
+        }
+    }
+
+    let syms: Vec<i16> = leaves.iter().map(|&(_, s)| s).collect();
+    let codes = canonical_codes(&syms, &lengths)?;
+    Ok(codes.iter().map(|&(s, _, l)| (s, l)).unzip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kraft(lengths: &[u8]) -> f64 {
+        lengths.iter().map(|&l| 0.5f64.powi(l as i32)).sum()
+    }
+
+    #[test]
+    fn package_merge_respects_limit() {
+        // Fibonacci weights want a 24-deep tree without the limit
+        let mut hist = Vec::new();
+        let (mut a, mut b) = (1u64, 1u64);
+        for s in 0..25i16 {
+            hist.push((s - 12, a));
+            (a, b) = (b, a + b);
+        }
+        let (syms, lens) = build_lengths(&hist, MAX_CODE_LEN).unwrap();
+        assert_eq!(syms.len(), 25);
+        assert!(lens.iter().all(|&l| (1..=MAX_CODE_LEN).contains(&l)));
+        assert_eq!(kraft(&lens), 1.0);
+        // heavier symbols never get longer codes
+        let len_of = |s: i16| lens[syms.iter().position(|&x| x == s).unwrap()];
+        assert!((-12..12).all(|s| len_of(s) >= len_of(s + 1)));
+    }
+
+    #[test]
+    fn unconstrained_matches_huffman_cost() {
+        let hist = [(0i16, 45u64), (1, 13), (-1, 12), (2, 16), (-2, 9), (3, 5)];
+        let (syms, lens) = build_lengths(&hist, MAX_CODE_LEN).unwrap();
+        let cost: u64 = syms
+            .iter()
+            .zip(lens.iter())
+            .map(|(s, &l)| hist.iter().find(|h| h.0 == *s).unwrap().1 * l as u64)
+            .sum();
+        assert_eq!(cost, 224);
+    }
+
+    #[test]
+    fn long_codes_are_checked_too() {
+        let syms: Vec<i16> = (0..33).collect();
+        let mut lens: Vec<u8> = (1..=32).collect();
+        lens.push(32);
+        let codes = canonical_codes(&syms, &lens).unwrap();
+        assert_eq!(codes[32], (32, u32::MAX, 32));
+
+        // one 32-bit code too many
+        let syms: Vec<i16> = (0..34).collect();
+        lens.push(32);
+        assert!(canonical_codes(&syms, &lens).is_err());
+        assert!(canonical_codes(&[0, 1, 2], &[1, 1, 32]).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::error::{MoeqiError, Result};
+use crate::moe::huff_canonical::canonical_codes;
+
+/// Bits resolved by the primary table; longer codes continue into a secondary table.
+pub const PRIMARY_BITS: u32 = 10;
+
+/// Longest code the tables accept. Secondary tables grow as `2^(len - PRIMARY_BITS)`,
+/// so tables with longer codes are left to the tree decoder.
+pub const MAX_LUT_LEN: u8 = 24;
+
+#[derive(Clone, Copy, Default)]
+struct Entry {
+    /// Symbol (as `i16` bits), or the first secondary index when `sub > 0`.
+    val: u32,
+    /// Full code length; 0 marks a bit pattern that no code starts with.
+    len: u8,
+    /// Index bits of the secondary table this entry links to.
+    sub: u8,
+}
+
+/// Two-level lookup tables for a canonical Huffman code read LSB-first.
+///
+/// The next [`PRIMARY_BITS`] stream bits index `primary` directly. Since the
+/// stream is LSB-first and codes are MSB-first, every code is stored bit-reversed
+/// and replicated across all indices that share it as a prefix.
+pub struct HuffLut {
+    primary: Vec<Entry>,
+    secondary: Vec<Entry>,
+    max_len: u32,
+}
+
+#[inline]
+fn reverse(code: u32, len: u32) -> u32 {
+    code.reverse_bits() >> (32 - len)
+}
+
+impl HuffLut {
+    pub fn new(symbols: &[i16], lengths: &[u8]) -> Result<Self> {
+        let codes = canonical_codes(symbols, lengths)?;
+        let max_len = codes.iter().map(|c| c.2).max().unwrap_or(0);
+        if max_len > MAX_LUT_LEN {
+            return Err(MoeqiError::Unsupported("huff code too long for lookup table"));
+        }
+        let max_len = max_len as u32;
+        let mask = (1u32 << PRIMARY_BITS) - 1;
+        let mut primary = vec![Entry::default(); 1 << PRIMARY_BITS];
+
+        // secondary sizing: longest code behind each primary prefix
+        for &(_, code, len) in &codes {
+            let len = len as u32;
+            if len > PRIMARY_BITS {
+                let e = &mut primary[(reverse(code, len) & mask) as usize];
+                e.sub = e.sub.max((len - PRIMARY_BITS) as u8);
+            }
+        }
+        let mut secondary_len = 0u32;
+        for e in primary.iter_mut().filter(|e| e.sub > 0) {
+            e.val = secondary_len;
+            secondary_len += 1 << e.sub;
+        }
+        let mut secondary = vec![Entry::default(); secondary_len as usize];
+
+        for &(sym, code, len) in &codes {
+            let len32 = len as u32;
+            let rev = reverse(code, len32);
+            let leaf = Entry { val: sym as u16 as u32, len, sub: 0 };
+            if len32 <= PRIMARY_BITS {
+                for i in 0..1u32 << (PRIMARY_BITS - len32) {
+                    primary[(rev | (i << len32)) as usize] = leaf;
+                }
+            } else {
+                let link = primary[(rev & mask) as usize];
+                let rest_len = len32 - PRIMARY_BITS;
+                let rest = rev >> PRIMARY_BITS;
+                for i in 0..1u32 << (link.sub as u32 - rest_len) {
+                    secondary[(link.val + (rest | (i << rest_len))) as usize] = leaf;
+                }
+            }
+        }
+
+        Ok(Self { primary, secondary, max_len })
+    }
+
+    /// Decode exactly `count` symbols from an LSB-first `payload`.
+    pub fn decode(&self, payload: &[u8], count: usize) -> Result<Vec<i16>> {
+        let mut out = Vec::with_capacity(count);
+        let mask = (1u64 << PRIMARY_BITS) - 1;
+
+        let mut acc = 0u64;
+        let mut nbits = 0u32;
+        let mut pos = 0usize;
+
+        while out.len() < count {
+            while nbits <= 56 && pos < payload.len() {
+                acc |= (payload[pos] as u64) << nbits;
+                pos += 1;
+                nbits += 8;
+            }
+
+            let mut e = self.primary[(acc & mask) as usize];
+            if e.sub > 0 {
+                let idx = (acc >> PRIMARY_BITS) & ((1u64 << e.sub) - 1);
+                e = self.secondary[e.val as usize + idx as usize];
+            }
+
+            if e.len == 0 {
+                return Err(if nbits < self.max_len {
+                    MoeqiError::Eof
+                } else {
+                    MoeqiError::InvalidData("huff code not in table")
+                });
+            }
+            if e.len as u32 > nbits {
+                return Err(MoeqiError::Eof);
+            }
+
+            out.push(e.val as u16 as i16);
+            acc >>= e.len;
+            nbits -= e.len as u32;
+        }
+
+        Ok(out)
+    }
+}
@@ -0,0 +1,101 @@
+//! Mixture-of-experts luma pipeline (`MOEQIBIN` v2 files).
+//!
+//! A file carries the first row/column of a Gray8 image verbatim, a quantized
+//! residual stream (varint or canonical Huffman) and a small linear model: a
+//! router `wr` that picks one of `e` experts per pixel and the experts' `we`
+//! predictors, both over the seven causal features of [`decode::feat_at`].
+
+pub mod bitstream;
+pub mod codec_huff;
+pub mod codec_varint;
+pub mod decode;
+pub mod encode;
+pub mod huff_canonical;
+pub mod huff_lut;
+pub mod model;
+pub mod pack_mqb;
+
+pub use bitstream::{Bitstream, Codec};
+pub use decode::decode_luma;
+pub use encode::encode_luma;
+pub use model::Model;
+pub use pack_mqb::{pack_mqb, parse_mqb};
+
+use crate::error::Result;
+use crate::types::{Image, PixelFormat};
+
+/// Encode a Gray8 [`Image`] with `model` into a `MOEQIBIN` file.
+pub fn encode(img: &Image, model: &Model, qstep: u16, codec: Codec) -> Result<Vec<u8>> {
+    pack_mqb(&encode_luma(img, model, qstep, codec)?)
+}
+
+/// Parse a `MOEQIBIN` file and decode it into a Gray8 [`Image`].
+pub fn decode(bytes: &[u8]) -> Result<Image> {
+    let bs = parse_mqb(bytes)?;
+    let data = decode_luma(&bs)?;
+    Ok(Image {
+        width: bs.w as u32,
+        height: bs.h as u32,
+        format: PixelFormat::Gray8,
+        data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moe::model::FEAT;
+
+    /// 3x2 image, qstep 2, one expert predicting the left neighbour.
+    fn sample_file(codec: u8, payload: &[u8], huff: &[(i16, u8)]) -> Vec<u8> {
+        let mut b = b"MOEQIBIN".to_vec();
+        b.extend_from_slice(&[2, 0, codec, 0, 0]);
+        for v in [3u16, 2, 2, 1] {
+            b.extend_from_slice(&v.to_le_bytes());
+        }
+        b.extend_from_slice(&2u32.to_le_bytes());
+        b.extend_from_slice(&[10, 20, 30]);
+        b.extend_from_slice(&[10, 40]);
+        b.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        b.extend_from_slice(payload);
+        if codec == 1 {
+            b.extend_from_slice(&(huff.len() as u16).to_le_bytes());
+            for &(sym, len) in huff {
+                b.extend_from_slice(&sym.to_le_bytes());
+                b.push(len);
+            }
+        }
+        let wr = [0f32; FEAT];
+        let we = [0f32, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        for v in wr.iter().chain(we.iter()) {
+            b.extend_from_slice(&v.to_le_bytes());
+        }
+        b
+    }
+
+    #[test]
+    fn decodes_varint_file() {
+        // residuals +3, -1 (zigzag 6, 1) times qstep 2
+        let img = decode(&sample_file(0, &[6, 1], &[])).unwrap();
+        assert_eq!(img.format, PixelFormat::Gray8);
+        assert_eq!((img.width, img.height), (3, 2));
+        assert_eq!(img.data, vec![10, 20, 30, 40, 46, 44]);
+    }
+
+    #[test]
+    fn decodes_huff_file() {
+        // canonical codes: 0 -> "0", -1 -> "10", 3 -> "11"; LSB-first bits 1,1 then 1,0
+        let table = [(0i16, 1u8), (3, 2), (-1, 2)];
+        let img = decode(&sample_file(1, &[0b0111], &table)).unwrap();
+        assert_eq!(img.data, vec![10, 20, 30, 40, 46, 44]);
+    }
+
+    #[test]
+    fn truncated_file_is_eof() {
+        let bytes = sample_file(0, &[6, 1], &[]);
+        assert!(matches!(
+            parse_mqb(&bytes[..bytes.len() - 1]),
+            Err(crate::MoeqiError::Eof)
+        ));
+    }
+}
@@ -1,5 +1,7 @@
 pub const FEAT: usize = 7;
 
+/// Router and expert weights, `e` rows of [`FEAT`] values each.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Model {
     pub e: u16,
     // Wr always f32 for stability (router)
@@ -9,6 +11,12 @@ pub struct Model {
 }
 
 impl Model {
+    /// At least one expert and `e * FEAT` weights in both `wr` and `we`.
+    pub fn validate(&self) -> bool {
+        let n = self.e as usize * FEAT;
+        self.e > 0 && self.wr.len() == n && self.we.len() == n
+    }
+
     #[inline]
     pub fn wr_row(&self, k: usize) -> &[f32] {
         let e = self.e as usize;
@@ -1,6 +1,6 @@
 use crate::error::MoeqiError;
-use crate::bitstream::{Bitstream, Codec};
-use crate::model::{Model, FEAT};
+use crate::moe::bitstream::{Bitstream, Codec};
+use crate::moe::model::{Model, FEAT};
 
 const MAGIC: &[u8; 8] = b"MOEQIBIN";
 const VERSION: u8 = 2;
@@ -9,23 +9,23 @@ const VERSION: u8 = 2;
 enum Quant { Fp32=0, Fp16=1, Int8=2 }
 
 fn rd_u8(data: &[u8], o: &mut usize) -> Result<u8, MoeqiError> {
-    if *o+1 > data.len() { return Err(MoeqiError::Format("eof")); }
+    if *o+1 > data.len() { return Err(MoeqiError::Eof); }
     let v = data[*o]; *o += 1; Ok(v)
 }
 fn rd_u16(data: &[u8], o: &mut usize) -> Result<u16, MoeqiError> {
-    if *o+2 > data.len() { return Err(MoeqiError::Format("eof")); }
+    if *o+2 > data.len() { return Err(MoeqiError::Eof); }
     let v = u16::from_le_bytes([data[*o], data[*o+1]]); *o += 2; Ok(v)
 }
 fn rd_u32(data: &[u8], o: &mut usize) -> Result<u32, MoeqiError> {
-    if *o+4 > data.len() { return Err(MoeqiError::Format("eof")); }
+    if *o+4 > data.len() { return Err(MoeqiError::Eof); }
     let v = u32::from_le_bytes([data[*o], data[*o+1], data[*o+2], data[*o+3]]); *o += 4; Ok(v)
 }
 fn rd_f32(data: &[u8], o: &mut usize) -> Result<f32, MoeqiError> {
-    if *o+4 > data.len() { return Err(MoeqiError::Format("eof")); }
+    if *o+4 > data.len() { return Err(MoeqiError::Eof); }
     let v = f32::from_le_bytes([data[*o], data[*o+1], data[*o+2], data[*o+3]]); *o += 4; Ok(v)
 }
 fn rd_bytes(data: &[u8], o: &mut usize, n: usize) -> Result<Vec<u8>, MoeqiError> {
-    if *o+n > data.len() { return Err(MoeqiError::Format("eof")); }
+    if *o+n > data.len() { return Err(MoeqiError::Eof); }
     let v = data[*o..*o+n].to_vec(); *o += n; Ok(v)
 }
 fn rd_f32_vec(data: &[u8], o: &mut usize, n: usize) -> Result<Vec<f32>, MoeqiError> {
@@ -36,6 +36,7 @@ fn rd_f32_vec(data: &[u8], o: &mut usize, n: usize) -> Result<Vec<f32>, MoeqiErr
     Ok(out)
 }
 
+/// Parse a `MOEQIBIN` v2 file. Residuals stay entropy-coded; see [`crate::moe::decode_luma`].
 pub fn parse_mqb(bytes: &[u8]) -> Result<Bitstream, MoeqiError> {
     if bytes.len() < 10 { return Err(MoeqiError::Format("too small")); }
     if &bytes[0..8] != MAGIC { return Err(MoeqiError::Format("bad magic")); }
@@ -149,7 +150,50 @@ fn half_to_f32(h: u16) -> f32 {
     f32::from_bits(f)
 }
 
-// pack_mqb can be added later (mirror your format)
-pub fn pack_mqb(_bs: &Bitstream) -> Result<Vec<u8>, MoeqiError> {
-    Err(MoeqiError::Unsupported("packer not implemented yet"))
+/// Serialize `bs` as a `MOEQIBIN` v2 file, the inverse of [`parse_mqb`].
+/// Both `wr` and `we` are written as fp32 so the model survives bit-exactly.
+pub fn pack_mqb(bs: &Bitstream) -> Result<Vec<u8>, MoeqiError> {
+    if bs.first_row.len() != bs.w as usize { return Err(MoeqiError::Format("first_row length")); }
+    if bs.first_col.len() != bs.h as usize { return Err(MoeqiError::Format("first_col length")); }
+    if !bs.model.validate() { return Err(MoeqiError::InvalidData("model shape")); }
+    let payload_len = u32::try_from(bs.payload.len()).map_err(|_| MoeqiError::Unsupported("payload > 4 GiB"))?;
+
+    let nvals = (bs.model.e as usize) * FEAT;
+    let mut out = Vec::with_capacity(32 + bs.first_row.len() + bs.first_col.len() + bs.payload.len() + nvals * 8);
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.push(0); // flags
+    out.push(match bs.codec { Codec::Varint => 0, Codec::Huff => 1 });
+    out.push(Quant::Fp32 as u8); // quant_wr
+    out.push(Quant::Fp32 as u8); // quant_we
+
+    out.extend_from_slice(&bs.w.to_le_bytes());
+    out.extend_from_slice(&bs.h.to_le_bytes());
+    out.extend_from_slice(&bs.qstep.to_le_bytes());
+    out.extend_from_slice(&bs.model.e.to_le_bytes());
+    out.extend_from_slice(&bs.residuals_count.to_le_bytes());
+
+    out.extend_from_slice(&bs.first_row);
+    out.extend_from_slice(&bs.first_col);
+
+    out.extend_from_slice(&payload_len.to_le_bytes());
+    out.extend_from_slice(&bs.payload);
+
+    if bs.codec == Codec::Huff {
+        if bs.huff_symbols.len() != bs.huff_lengths.len() {
+            return Err(MoeqiError::InvalidData("huff table length mismatch"));
+        }
+        let nsym = u16::try_from(bs.huff_symbols.len()).map_err(|_| MoeqiError::Unsupported("huff table > 65535 symbols"))?;
+        out.extend_from_slice(&nsym.to_le_bytes());
+        for (&sym, &ln) in bs.huff_symbols.iter().zip(bs.huff_lengths.iter()) {
+            out.extend_from_slice(&sym.to_le_bytes());
+            out.push(ln);
+        }
+    }
+
+    for v in bs.model.wr.iter().chain(bs.model.we.iter()) {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+
+    Ok(out)
 }
@@ -0,0 +1,108 @@
+//! Tile-level parallelism behind the `parallel` feature.
+//!
+//! Tiles are coded independently and collected in order, so the output is
+//! byte-identical to the serial loop. An untiled image is a single tile and
+//! always runs on the calling thread.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::Result;
+
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Worker threads used for tile coding: 0 = one per core (the default),
+/// 1 = serial. Process-wide; no effect without the `parallel` feature.
+///
+/// Only tiles are coded concurrently, so an untiled config
+/// ([`CodecConfig::tile_size`](crate::types::CodecConfig::tile_size) 0,
+/// the default) runs on one thread whatever this is set to.
+pub fn set_threads(n: usize) {
+    THREADS.store(n, Ordering::Relaxed);
+}
+
+/// Current [`set_threads`] value.
+pub fn threads() -> usize {
+    THREADS.load(Ordering::Relaxed)
+}
+
+/// `(0..count).map(f)`, fanned out over the configured threads.
+#[cfg(not(feature = "parallel"))]
+pub(crate) fn map_tiles<T, F>(count: usize, f: F) -> Result<Vec<T>>
+where
+    T: Send,
+    F: Fn(usize) -> Result<T> + Sync + Send,
+{
+    (0..count).map(f).collect()
+}
+
+/// `(0..count).map(f)`, fanned out over the configured threads.
+#[cfg(feature = "parallel")]
+pub(crate) fn map_tiles<T, F>(count: usize, f: F) -> Result<Vec<T>>
+where
+    T: Send,
+    F: Fn(usize) -> Result<T> + Sync + Send,
+{
+    use rayon::prelude::*;
+
+    let run = || (0..count).into_par_iter().map(&f).collect();
+    match threads() {
+        _ if count < 2 => (0..count).map(&f).collect(),
+        1 => (0..count).map(&f).collect(),
+        0 => run(),
+        n => pool(n)?.install(run),
+    }
+}
+
+/// Pool for an explicit thread count, rebuilt only when the count changes.
+#[cfg(feature = "parallel")]
+fn pool(n: usize) -> Result<std::sync::Arc<rayon::ThreadPool>> {
+    use std::sync::{Arc, Mutex};
+
+    static POOL: Mutex<Option<(usize, Arc<rayon::ThreadPool>)>> = Mutex::new(None);
+
+    let mut slot = POOL.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((size, pool)) = slot.as_ref() {
+        if *size == n {
+            return Ok(pool.clone());
+        }
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(n)
+        .build()
+        .map_err(|_| crate::error::MoeqiError::Unsupported("cannot start thread pool"))?;
+    let pool = Arc::new(pool);
+    *slot = Some((n, pool.clone()));
+    Ok(pool)
+}
+
+#[cfg(all(test, feature = "parallel"))]
+mod tests {
+    use crate::codec::{decode_region, encode_tiles};
+    use crate::types::{CodecConfig, CodecKind, Image, PixelFormat};
+
+    #[test]
+    fn thread_count_does_not_change_output() {
+        let (w, h) = (150u32, 90u32);
+        let img = Image {
+            width: w,
+            height: h,
+            format: PixelFormat::Rgb8,
+            data: (0..w * h * 3).map(|i| (i * 31 % 253) as u8).collect(),
+        };
+        let cfg = CodecConfig {
+            codec: CodecKind::PredictArith,
+            tile_size: 32,
+            ..CodecConfig::default()
+        };
+        let run = |n| {
+            super::set_threads(n);
+            let tiles = encode_tiles(&img, cfg).unwrap();
+            let refs: Vec<&[u8]> = tiles.iter().map(Vec::as_slice).collect();
+            let out = decode_region(&refs, w, h, img.format, cfg, 0, 0, w, h).unwrap();
+            (tiles, out)
+        };
+        let serial = run(1);
+        assert_eq!(run(4), serial);
+        assert_eq!(run(0), serial);
+    }
+}
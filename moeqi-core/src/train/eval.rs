@@ -5,12 +5,13 @@ pub fn mse(a: &Image, b: &Image) -> Result<f64> {
     if a.width != b.width || a.height != b.height || a.format != b.format {
         return Err(MoeqiError::InvalidData("image mismatch"));
     }
+    let (sa, sb) = (a.samples(), b.samples());
     let mut acc = 0f64;
-    for (&x, &y) in a.data.iter().zip(b.data.iter()) {
+    for (&x, &y) in sa.iter().zip(sb.iter()) {
         let d = x as f64 - y as f64;
         acc += d * d;
     }
-    Ok(acc / (a.data.len() as f64))
+    Ok(acc / (sa.len() as f64))
 }
 
 pub fn psnr(a: &Image, b: &Image) -> Result<f64> {
@@ -18,7 +19,10 @@ pub fn psnr(a: &Image, b: &Image) -> Result<f64> {
     if m == 0.0 {
         return Ok(99.0);
     }
-    let max = 255.0;
+    let max = match a.format.bytes_per_sample() {
+        1 => 255.0,
+        _ => 65535.0,
+    };
     Ok(20.0 * (max / m.sqrt()).log10())
 }
 
@@ -28,5 +32,5 @@ pub fn bpp(encoded_bytes: usize, img: &Image) -> f64 {
 }
 
 pub fn is_color(fmt: PixelFormat) -> bool {
-    matches!(fmt, PixelFormat::Rgb8 | PixelFormat::Rgba8)
+    fmt.channels() >= 3
 }
@@ -1,2 +1,4 @@
 pub mod eval;
 pub mod fit;
+pub mod moe;
+pub mod rate;
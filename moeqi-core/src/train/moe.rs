@@ -0,0 +1,363 @@
+use crate::error::{MoeqiError, Result};
+use crate::moe::decode::feat_at;
+use crate::moe::model::{dot7, router_argmax, Model, FEAT};
+use crate::types::{Image, PixelFormat};
+
+/// Settings for [`fit_model`]. The same images, config and seed always give the same [`Model`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MoeFitConfig {
+    /// Number of experts `E`.
+    pub experts: u16,
+    pub seed: u64,
+    /// Lloyd iterations of the k-means initialisation.
+    pub kmeans_iters: u32,
+    /// Rounds of "assign each sample to its lowest-error expert, refit experts".
+    pub refine_iters: u32,
+    /// Full-batch gradient steps of the softmax router.
+    pub router_epochs: u32,
+    pub router_lr: f32,
+    /// Per-sample Tikhonov term added to the expert normal equations.
+    pub ridge: f64,
+    /// Interior pixels are subsampled with a fixed stride above this count.
+    pub max_samples: usize,
+}
+
+impl Default for MoeFitConfig {
+    fn default() -> Self {
+        Self {
+            experts: 4,
+            seed: 0x5eed,
+            kmeans_iters: 10,
+            refine_iters: 4,
+            router_epochs: 100,
+            router_lr: 2.0,
+            ridge: 1e-6,
+            max_samples: 65_536,
+        }
+    }
+}
+
+/// Train router (`wr`) and expert (`we`) weights on a corpus of Gray8 images.
+///
+/// Experts start from k-means clusters of the [`feat_at`] features, are fitted by
+/// least squares and refined on their lowest-error samples. The router is then
+/// a softmax classifier of those assignments, and the experts are refitted one
+/// last time on the partition the router actually produces, which is what
+/// [`decode_luma`](crate::moe::decode_luma) sees.
+pub fn fit_model(images: &[Image], cfg: MoeFitConfig) -> Result<Model> {
+    if cfg.experts == 0 {
+        return Err(MoeqiError::InvalidData("experts must be >= 1"));
+    }
+    let (feats, targets) = collect_samples(images, cfg.max_samples)?;
+    let e = cfg.experts as usize;
+    let mut rng = SplitMix64(cfg.seed);
+
+    let global = fit_expert(&feats, &targets, |_| true, cfg.ridge).unwrap_or([0.0; FEAT]);
+
+    let mut assign = kmeans(&feats, e, cfg.kmeans_iters, &mut rng);
+    let mut we = fit_experts(&feats, &targets, &assign, e, cfg.ridge, &global);
+
+    for _ in 0..cfg.refine_iters {
+        let next: Vec<usize> = feats
+            .iter()
+            .zip(targets.iter())
+            .map(|(f, &t)| best_expert(&we, f, t))
+            .collect();
+        if next == assign {
+            break;
+        }
+        assign = next;
+        we = fit_experts(&feats, &targets, &assign, e, cfg.ridge, &global);
+    }
+
+    let mut model = Model {
+        e: cfg.experts,
+        wr: fit_router(&feats, &assign, e, cfg.router_epochs, cfg.router_lr),
+        we: Vec::new(),
+    };
+
+    let routed: Vec<usize> = feats.iter().map(|f| router_argmax(&model, f)).collect();
+    model.we = fit_experts(&feats, &targets, &routed, e, cfg.ridge, &global)
+        .iter()
+        .flat_map(|w| w.iter().map(|&v| v as f32))
+        .collect();
+
+    Ok(model)
+}
+
+/// Features and `0..=1` targets of every interior pixel (`x, y >= 1`).
+fn collect_samples(images: &[Image], max_samples: usize) -> Result<(Vec<[f32; FEAT]>, Vec<f32>)> {
+    let mut total = 0usize;
+    for img in images {
+        if img.format != PixelFormat::Gray8 {
+            return Err(MoeqiError::Unsupported("MoE training is Gray8 only"));
+        }
+        if !img.validate() {
+            return Err(MoeqiError::InvalidData("image data length mismatch"));
+        }
+        let (w, h) = (img.width as usize, img.height as usize);
+        total += w.saturating_sub(1) * h.saturating_sub(1);
+    }
+    if total == 0 {
+        return Err(MoeqiError::InvalidData("no training samples"));
+    }
+    let stride = total.div_ceil(max_samples.max(1));
+
+    let mut feats = Vec::with_capacity(total / stride + 1);
+    let mut targets = Vec::with_capacity(total / stride + 1);
+    let mut n = 0usize;
+    for img in images {
+        let w = img.width as usize;
+        for y in 1..img.height as usize {
+            for x in 1..w {
+                if n.is_multiple_of(stride) {
+                    feats.push(feat_at(x, y, w, &img.data));
+                    targets.push(img.data[y * w + x] as f32 / 255.0);
+                }
+                n += 1;
+            }
+        }
+    }
+    Ok((feats, targets))
+}
+
+fn dist2(a: &[f32; FEAT], b: &[f32; FEAT]) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+fn nearest(centers: &[[f32; FEAT]], f: &[f32; FEAT]) -> (usize, f32) {
+    let mut best = (0, f32::INFINITY);
+    for (k, c) in centers.iter().enumerate() {
+        let d = dist2(c, f);
+        if d < best.1 {
+            best = (k, d);
+        }
+    }
+    best
+}
+
+/// k-means++ seeding followed by Lloyd iterations; returns the cluster of each sample.
+fn kmeans(feats: &[[f32; FEAT]], e: usize, iters: u32, rng: &mut SplitMix64) -> Vec<usize> {
+    let n = feats.len();
+    let mut centers = vec![feats[rng.below(n)]];
+    let mut d2: Vec<f32> = feats.iter().map(|f| dist2(&centers[0], f)).collect();
+    while centers.len() < e {
+        let sum: f64 = d2.iter().map(|&d| d as f64).sum();
+        let pick = if sum > 0.0 {
+            let mut r = rng.unit() * sum;
+            let mut i = 0;
+            while i + 1 < n && r >= d2[i] as f64 {
+                r -= d2[i] as f64;
+                i += 1;
+            }
+            i
+        } else {
+            rng.below(n)
+        };
+        centers.push(feats[pick]);
+        for (d, f) in d2.iter_mut().zip(feats.iter()) {
+            *d = d.min(dist2(&feats[pick], f));
+        }
+    }
+
+    let mut assign: Vec<usize> = feats.iter().map(|f| nearest(&centers, f).0).collect();
+    for _ in 0..iters {
+        let mut sum = vec![[0f64; FEAT]; e];
+        let mut cnt = vec![0usize; e];
+        for (f, &k) in feats.iter().zip(assign.iter()) {
+            for (s, &v) in sum[k].iter_mut().zip(f.iter()) {
+                *s += v as f64;
+            }
+            cnt[k] += 1;
+        }
+        for k in 0..e {
+            if cnt[k] > 0 {
+                for (c, s) in centers[k].iter_mut().zip(sum[k].iter()) {
+                    *c = (s / cnt[k] as f64) as f32;
+                }
+            }
+        }
+        let next: Vec<usize> = feats.iter().map(|f| nearest(&centers, f).0).collect();
+        if next == assign {
+            break;
+        }
+        assign = next;
+    }
+    assign
+}
+
+fn best_expert(we: &[[f64; FEAT]], f: &[f32; FEAT], t: f32) -> usize {
+    let mut best = (0, f64::INFINITY);
+    for (k, w) in we.iter().enumerate() {
+        let mu: f64 = w.iter().zip(f.iter()).map(|(&a, &b)| a * b as f64).sum();
+        let err = (mu - t as f64).abs();
+        if err < best.1 {
+            best = (k, err);
+        }
+    }
+    best.0
+}
+
+fn fit_experts(
+    feats: &[[f32; FEAT]],
+    targets: &[f32],
+    assign: &[usize],
+    e: usize,
+    ridge: f64,
+    fallback: &[f64; FEAT],
+) -> Vec<[f64; FEAT]> {
+    (0..e)
+        .map(|k| fit_expert(feats, targets, |i| assign[i] == k, ridge).unwrap_or(*fallback))
+        .collect()
+}
+
+/// Least-squares weights over the samples selected by `pick`; `None` if there are none.
+fn fit_expert(
+    feats: &[[f32; FEAT]],
+    targets: &[f32],
+    pick: impl Fn(usize) -> bool,
+    ridge: f64,
+) -> Option<[f64; FEAT]> {
+    let mut a = [[0f64; FEAT]; FEAT];
+    let mut b = [0f64; FEAT];
+    let mut n = 0usize;
+    for (i, (f, &t)) in feats.iter().zip(targets.iter()).enumerate() {
+        if !pick(i) {
+            continue;
+        }
+        for r in 0..FEAT {
+            for c in 0..FEAT {
+                a[r][c] += f[r] as f64 * f[c] as f64;
+            }
+            b[r] += f[r] as f64 * t as f64;
+        }
+        n += 1;
+    }
+    if n == 0 {
+        return None;
+    }
+    // scale-free regularisation so rank-deficient features (e.g. lf - uf) stay solvable
+    for (r, row) in a.iter_mut().enumerate() {
+        row[r] += ridge * n as f64;
+    }
+    solve(a, b)
+}
+
+/// Gaussian elimination with partial pivoting.
+fn solve(mut a: [[f64; FEAT]; FEAT], mut b: [f64; FEAT]) -> Option<[f64; FEAT]> {
+    for col in 0..FEAT {
+        let piv = (col..FEAT).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[piv][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, piv);
+        b.swap(col, piv);
+        let pivot = a[col];
+        for r in col + 1..FEAT {
+            let m = a[r][col] / pivot[col];
+            for (x, &p) in a[r][col..].iter_mut().zip(pivot[col..].iter()) {
+                *x -= m * p;
+            }
+            b[r] -= m * b[col];
+        }
+    }
+    let mut x = [0f64; FEAT];
+    for r in (0..FEAT).rev() {
+        let s: f64 = (r + 1..FEAT).map(|c| a[r][c] * x[c]).sum();
+        x[r] = (b[r] - s) / a[r][r];
+    }
+    Some(x)
+}
+
+/// Multinomial logistic regression of `labels`, trained by full-batch gradient descent.
+fn fit_router(feats: &[[f32; FEAT]], labels: &[usize], e: usize, epochs: u32, lr: f32) -> Vec<f32> {
+    let mut wr = vec![0f32; e * FEAT];
+    if e == 1 {
+        return wr;
+    }
+    let n = feats.len() as f32;
+    let mut grad = vec![0f64; e * FEAT];
+    let mut p = vec![0f32; e];
+    for _ in 0..epochs {
+        grad.iter_mut().for_each(|g| *g = 0.0);
+        for (f, &label) in feats.iter().zip(labels.iter()) {
+            let mut zmax = f32::NEG_INFINITY;
+            for (k, pk) in p.iter_mut().enumerate() {
+                *pk = dot7(&wr[k * FEAT..(k + 1) * FEAT], f);
+                zmax = zmax.max(*pk);
+            }
+            let mut sum = 0f32;
+            for pk in p.iter_mut() {
+                *pk = (*pk - zmax).exp();
+                sum += *pk;
+            }
+            for (k, &pk) in p.iter().enumerate() {
+                let d = pk / sum - if k == label { 1.0 } else { 0.0 };
+                for (g, &v) in grad[k * FEAT..(k + 1) * FEAT].iter_mut().zip(f.iter()) {
+                    *g += (d * v) as f64;
+                }
+            }
+        }
+        for (w, &g) in wr.iter_mut().zip(grad.iter()) {
+            *w -= lr * (g as f32) / n;
+        }
+    }
+    wr
+}
+
+/// Small self-contained PRNG so training is reproducible without extra deps.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Smooth ramps with a hard vertical edge, so left- and up-prediction both fail somewhere.
+    fn sample(w: u32, h: u32, phase: u32) -> Image {
+        let data = (0..w * h)
+            .map(|i| {
+                let (x, y) = (i % w, i / w);
+                let base = if x < w / 2 { 40 } else { 180 };
+                (base + (y * 2 + x + phase) % 50) as u8
+            })
+            .collect();
+        Image { width: w, height: h, format: PixelFormat::Gray8, data }
+    }
+
+    #[test]
+    fn deterministic_and_better_than_left_prediction() {
+        let images = [sample(48, 32, 0), sample(40, 40, 7)];
+        let cfg = MoeFitConfig { experts: 3, ..Default::default() };
+        let a = fit_model(&images, cfg).unwrap();
+        let b = fit_model(&images, cfg).unwrap();
+        assert_eq!(a, b);
+        assert!(a.validate());
+
+        let mut left = Model { e: 1, wr: vec![0.0; FEAT], we: vec![0.0; FEAT] };
+        left.we[1] = 1.0;
+        for img in &images {
+            let trained = crate::moe::encode(img, &a, 1, crate::moe::Codec::Varint).unwrap();
+            assert_eq!(crate::moe::decode(&trained).unwrap(), *img);
+            let baseline = crate::moe::encode(img, &left, 1, crate::moe::Codec::Varint).unwrap();
+            assert!(trained.len() - a.wr.len() * 8 <= baseline.len() - left.wr.len() * 8);
+        }
+    }
+}
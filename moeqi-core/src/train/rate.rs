@@ -0,0 +1,203 @@
+use crate::codec::quant::SignedUniformQuant;
+use crate::error::{MoeqiError, Result};
+use crate::format::binary;
+use crate::train::eval;
+use crate::types::{CodecConfig, Image};
+
+/// Budget for [`encode_with_target`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetRate {
+    /// At most this many bits per pixel, container included.
+    Bpp(f64),
+    /// At most this many container bytes.
+    Bytes(usize),
+    /// At least this PSNR in dB, at the smallest size found.
+    Psnr(f64),
+}
+
+/// What [`encode_with_target`] settled on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateReport {
+    pub config: CodecConfig,
+    pub bytes: usize,
+    pub bpp: f64,
+    /// [`eval::psnr`] of the decoded file; 99 for lossless.
+    pub psnr: f64,
+}
+
+impl TargetRate {
+    fn met(&self, r: &RateReport) -> bool {
+        match *self {
+            TargetRate::Bpp(bpp) => r.bpp <= bpp,
+            TargetRate::Bytes(n) => r.bytes <= n,
+            TargetRate::Psnr(db) => r.psnr >= db,
+        }
+    }
+}
+
+fn probe(img: &Image, cfg: CodecConfig, quant_bits: u8) -> Result<(Vec<u8>, RateReport)> {
+    let mut config = CodecConfig {
+        quant_bits,
+        strict_recon: true,
+        max_abs_error: 0,
+        ..cfg
+    };
+    for p in &mut config.planes {
+        p.quant_bits = None;
+    }
+    let bytes = binary::encode(img, config)?;
+    let (recon, _) = binary::decode(&bytes)?;
+    let report = RateReport {
+        config,
+        bytes: bytes.len(),
+        bpp: eval::bpp(bytes.len(), img),
+        psnr: eval::psnr(img, &recon)?,
+    };
+    Ok((bytes, report))
+}
+
+/// [`CodecConfig::quant_bits`] from finest to coarsest: lossless, then each
+/// value giving a coarser step at `depth` bits than the one before. Values
+/// sharing a step (at 8 bits, 15 down to 9 all give step 1) would only
+/// repeat an encode, so they get one rung.
+fn ladder(depth: u8) -> Vec<u8> {
+    let mut rungs = vec![0];
+    let mut last = 1;
+    for bits in (1..=15).rev() {
+        let step = SignedUniformQuant::with_depth(bits, depth).step();
+        if step > last {
+            rungs.push(bits);
+            last = step;
+        }
+    }
+    rungs
+}
+
+/// Encode `img` into the container of [`binary::encode`], picking the
+/// residual quantizer ([`CodecConfig::quant_bits`]) that meets `target`:
+/// the finest one for a size budget, the coarsest one for a PSNR floor.
+/// The other fields of `cfg` are kept, colour transform included, except
+/// that `strict_recon` is set and `max_abs_error` and the per-plane
+/// `quant_bits` are cleared; [`RateReport::config`] is what was written.
+///
+/// The quantizer is image-wide. Bisects over the ladder, assuming size and
+/// PSNR fall as the step grows; five or six encodes per call. Fails with
+/// `Unsupported` when even the coarsest step misses a size budget.
+pub fn encode_with_target(
+    img: &Image,
+    cfg: CodecConfig,
+    target: TargetRate,
+) -> Result<(Vec<u8>, RateReport)> {
+    let depth = img
+        .format
+        .bit_depth(cfg.bit_depth)
+        .ok_or(MoeqiError::InvalidData(
+            "bit depth does not fit the pixel format",
+        ))?;
+    let ladder = ladder(depth);
+    // rung `good` meets the target, `bad` does not; close the gap
+    let last = ladder.len() - 1;
+    let (mut good, mut bad) = match target {
+        TargetRate::Psnr(_) => (0, last),
+        _ => (last, 0),
+    };
+    let mut best = probe(img, cfg, ladder[good])?;
+    if !target.met(&best.1) {
+        return Err(MoeqiError::Unsupported("rate target unreachable"));
+    }
+    let far = probe(img, cfg, ladder[bad])?;
+    if target.met(&far.1) {
+        return Ok(far);
+    }
+
+    while good.abs_diff(bad) > 1 {
+        let mid = (good + bad) / 2;
+        let r = probe(img, cfg, ladder[mid])?;
+        if target.met(&r.1) {
+            (good, best) = (mid, r);
+        } else {
+            bad = mid;
+        }
+    }
+    Ok(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{CodecKind, ColorTransform, PixelFormat, Predictor};
+
+    /// Diagonal ramps per channel plus 5 bits of noise, scaled to the
+    /// format's sample size.
+    fn noisy_ramp(w: u32, h: u32, format: PixelFormat) -> Image {
+        let ch = format.channels() as u32;
+        let scale = match format.bytes_per_sample() {
+            2 => 257,
+            _ => 1,
+        };
+        let mut seed = 7u32;
+        let samples: Vec<u16> = (0..w * h * ch)
+            .map(|i| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let (p, c) = (i / ch, i % ch);
+                let v = (p % w) * (c + 3) + (p / w) * 2 + (seed >> 27);
+                (v % 256 * scale) as u16
+            })
+            .collect();
+        Image::from_samples(w, h, format, &samples)
+    }
+
+    #[test]
+    fn ladder_rungs_have_distinct_steps() {
+        let steps = |depth| -> Vec<i32> {
+            ladder(depth)[1..]
+                .iter()
+                .map(|&b| SignedUniformQuant::with_depth(b, depth).step())
+                .collect()
+        };
+        assert_eq!(steps(8), [3, 5, 9, 17, 37, 85, 255]);
+        let deep = steps(16);
+        assert!(deep.len() == 14 && deep[0] == 5 && deep.windows(2).all(|s| s[0] < s[1]));
+    }
+
+    #[test]
+    fn meets_size_and_quality_targets() {
+        let img = noisy_ramp(64, 48, PixelFormat::Gray8);
+        let cfg = CodecConfig {
+            codec: CodecKind::PredictArith,
+            predictor: Predictor::Med,
+            ..CodecConfig::default()
+        };
+        let lossless = binary::encode(&img, cfg).unwrap().len();
+
+        let budget = lossless / 3;
+        let (bytes, r) = encode_with_target(&img, cfg, TargetRate::Bytes(budget)).unwrap();
+        assert!(bytes.len() <= budget && r.bytes == bytes.len());
+        assert!(r.config.quant_bits > 0);
+        assert_eq!(binary::decode(&bytes).unwrap().1, r.config);
+
+        let (_, r) = encode_with_target(&img, cfg, TargetRate::Bpp(2.0)).unwrap();
+        assert!(r.bpp <= 2.0);
+
+        let (_, r) = encode_with_target(&img, cfg, TargetRate::Psnr(40.0)).unwrap();
+        assert!(r.psnr >= 40.0 && r.config.quant_bits > 0);
+
+        assert!(encode_with_target(&img, cfg, TargetRate::Bytes(8)).is_err());
+    }
+
+    #[test]
+    fn keeps_the_colour_transform_and_reaches_16_bit_budgets() {
+        let img = noisy_ramp(40, 30, PixelFormat::Rgb16);
+        let cfg = CodecConfig {
+            codec: CodecKind::PredictArith,
+            color_transform: ColorTransform::YCoCgR,
+            ..CodecConfig::default()
+        };
+        let lossless = binary::encode(&img, cfg).unwrap().len();
+        let budget = lossless / 4;
+        let (bytes, r) = encode_with_target(&img, cfg, TargetRate::Bytes(budget)).unwrap();
+        assert!(bytes.len() <= budget);
+        assert_eq!(r.config.color_transform, ColorTransform::YCoCgR);
+        assert_eq!(binary::decode(&bytes).unwrap().1, r.config);
+    }
+}
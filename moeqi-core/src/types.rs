@@ -5,13 +5,33 @@ pub enum PixelFormat {
     Gray8,
     Rgb8,
     Rgba8,
+    /// Little-endian `u16` samples, like the other 16-bit formats.
+    Gray16,
+    Rgb16,
+    Rgba16,
 }
 impl PixelFormat {
     pub fn channels(self) -> usize {
         match self {
-            PixelFormat::Gray8 => 1,
-            PixelFormat::Rgb8 => 3,
-            PixelFormat::Rgba8 => 4,
+            PixelFormat::Gray8 | PixelFormat::Gray16 => 1,
+            PixelFormat::Rgb8 | PixelFormat::Rgb16 => 3,
+            PixelFormat::Rgba8 | PixelFormat::Rgba16 => 4,
+        }
+    }
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            PixelFormat::Gray8 | PixelFormat::Rgb8 | PixelFormat::Rgba8 => 1,
+            _ => 2,
+        }
+    }
+    /// Significant bits per sample for a [`CodecConfig::bit_depth`]: 0 means
+    /// the full 8 or 16 bits. `None` if the depth doesn't fit the format.
+    pub fn bit_depth(self, depth: u8) -> Option<u8> {
+        match (self.bytes_per_sample(), depth) {
+            (1, 0 | 8) => Some(8),
+            (2, 0) => Some(16),
+            (2, 9..=16) => Some(depth),
+            _ => None,
         }
     }
 }
@@ -26,16 +46,45 @@ pub struct Image {
 }
 impl Image {
     pub fn expected_len(&self) -> usize {
-        self.width as usize * self.height as usize * self.format.channels()
+        self.width as usize
+            * self.height as usize
+            * self.format.channels()
+            * self.format.bytes_per_sample()
     }
     pub fn validate(&self) -> bool {
         self.data.len() == self.expected_len()
     }
+    /// Samples in `data` order, widened to `u16`.
+    pub fn samples(&self) -> Vec<u16> {
+        match self.format.bytes_per_sample() {
+            1 => self.data.iter().map(|&v| v as u16).collect(),
+            _ => self
+                .data
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect(),
+        }
+    }
+    /// Inverse of [`Image::samples`]; 8-bit formats keep the low byte.
+    pub fn from_samples(width: u32, height: u32, format: PixelFormat, samples: &[u16]) -> Self {
+        let data = match format.bytes_per_sample() {
+            1 => samples.iter().map(|&v| v as u8).collect(),
+            _ => samples.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        };
+        Self {
+            width,
+            height,
+            format,
+            data,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CodecKind {
     PredictVarint,
+    /// Adaptive binary range coding of the residuals, contexts from local gradients.
+    PredictArith,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -43,6 +92,113 @@ pub enum ColorTransform {
     None,
     /// Reversible integer transform (better residuals for RGB/RGBA)
     YCoCgR,
+    /// Lossy BT.601 YCbCr, 8-bit RGB/RGBA only and not with
+    /// `max_abs_error`. Y, Cb, Cr (and alpha) are coded as separate planes,
+    /// chroma at the subsampled resolution.
+    YCbCr {
+        subsampling: Subsampling,
+        #[serde(default)]
+        siting: ChromaSiting,
+        #[serde(default)]
+        upsampling: Upsampling,
+    },
+}
+
+/// Chroma resolution relative to luma.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Subsampling {
+    S444,
+    /// Half horizontal resolution.
+    S422,
+    /// Half horizontal and vertical resolution.
+    S420,
+}
+impl Subsampling {
+    /// Horizontal and vertical decimation factors.
+    pub fn factors(self) -> (usize, usize) {
+        match self {
+            Subsampling::S444 => (1, 1),
+            Subsampling::S422 => (2, 1),
+            Subsampling::S420 => (2, 2),
+        }
+    }
+}
+
+/// Where subsampled chroma sits on the luma grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ChromaSiting {
+    /// Between the luma samples it covers (JPEG); box-filtered.
+    #[default]
+    Centered,
+    /// On the first luma sample it covers (H.264 type 2); [1, 2, 1] filtered.
+    Cosited,
+}
+
+/// How the decoder brings chroma back to full resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Upsampling {
+    Nearest,
+    /// Linear interpolation between the nearest chroma samples, edges clamped.
+    #[default]
+    Bilinear,
+}
+
+/// Spatial predictor for each sample, from its left (`a`), up (`b`) and up-left (`c`)
+/// neighbours in the same channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Predictor {
+    /// `a`; every row restarts from 0.
+    #[default]
+    Left,
+    /// `b`
+    Up,
+    /// `(a + b) / 2`
+    Average,
+    /// PNG Paeth: whichever of `a`, `b`, `c` is closest to `a + b - c`.
+    Paeth,
+    /// JPEG-LS / LOCO-I median edge detector.
+    Med,
+}
+
+/// How the channels of an image are arranged in the coded stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ChannelLayout {
+    /// Channels alternate within each row and share one set of parameters.
+    #[default]
+    Interleaved,
+    /// Each channel, after the colour transform, is a separate stream coded
+    /// with its own [`PlaneConfig`].
+    Planar,
+}
+
+/// Overrides of the image-wide [`CodecConfig`] fields for one plane; `None`
+/// keeps the image-wide value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct PlaneConfig {
+    #[serde(default)]
+    pub codec: Option<CodecKind>,
+    #[serde(default)]
+    pub predictor: Option<Predictor>,
+    #[serde(default)]
+    pub quant_bits: Option<u8>,
+}
+
+/// Treatment of the alpha channel of the RGBA formats; ignored otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct AlphaConfig {
+    /// Code alpha apart from colour, per tile: a single value when constant,
+    /// a run-length mask when only 0 and the maximum occur, else its own
+    /// plane (with the parameters of plane 3).
+    #[serde(default)]
+    pub separate: bool,
+    /// Zero the colour of fully transparent pixels before coding. Lossy, but
+    /// invisible once composited.
+    #[serde(default)]
+    pub clear_transparent: bool,
+    /// Colour is premultiplied by alpha, in the input and in decoded images.
+    /// The encoder rejects colour above alpha; the decoder clamps to it.
+    #[serde(default)]
+    pub premultiplied: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -53,6 +209,60 @@ pub struct CodecConfig {
     /// CRITICAL: keep predictor in reconstructed domain to avoid drift/artifacts.
     pub strict_recon: bool,
     pub color_transform: ColorTransform,
+    #[serde(default)]
+    pub predictor: Predictor,
+    /// Side of the independently coded square tiles; 0 = one tile for the whole image.
+    /// Tiles are also what the `parallel` feature spreads across threads.
+    #[serde(default)]
+    pub tile_size: u32,
+    /// Significant bits of 16-bit formats (9..=16); 0 = the format's full depth.
+    #[serde(default)]
+    pub bit_depth: u8,
+    /// Near-lossless (JPEG-LS `NEAR`): every decoded sample is within this
+    /// distance of the source. Non-zero overrides `quant_bits` with a step of
+    /// `2 * max_abs_error + 1`, implies `strict_recon` and skips the colour
+    /// transform, as the bound must hold per channel of the source.
+    #[serde(default)]
+    pub max_abs_error: u8,
+    #[serde(default)]
+    pub layout: ChannelLayout,
+    /// Per-plane overrides, used wherever planes are coded separately:
+    /// [`ChannelLayout::Planar`] and [`ColorTransform::YCbCr`]. Plane `i` is
+    /// channel `i` after the colour transform (Y, Co, Cg, A for YCoCg-R;
+    /// Y, Cb, Cr, A for YCbCr).
+    #[serde(default)]
+    pub planes: [PlaneConfig; 4],
+    #[serde(default)]
+    pub alpha: AlphaConfig,
+    /// Code runs of samples equal to their left neighbour as one length,
+    /// entered wherever the causal neighbourhood is flat (JPEG-LS run mode).
+    /// The encoder keeps it per plane only where it is smaller, at twice
+    /// the encode time. Pays off on screenshots and flat graphics.
+    #[serde(default)]
+    pub run_mode: bool,
+    /// Store `Rgb8`/`Rgba8` images with at most 256 colours as a palette and
+    /// an index image, when the rest of the config is lossless. Decoding
+    /// still yields the original pixels.
+    #[serde(default)]
+    pub palette: bool,
+}
+
+impl CodecConfig {
+    /// The config plane `i` is coded with.
+    pub fn plane(&self, i: usize) -> CodecConfig {
+        let p = self.planes[i];
+        CodecConfig {
+            codec: p.codec.unwrap_or(self.codec),
+            predictor: p.predictor.unwrap_or(self.predictor),
+            quant_bits: p.quant_bits.unwrap_or(self.quant_bits),
+            ..*self
+        }
+    }
+
+    /// Whether any plane overrides the image-wide parameters.
+    pub fn has_plane_overrides(&self) -> bool {
+        self.planes != [PlaneConfig::default(); 4]
+    }
 }
 
 impl Default for CodecConfig {
@@ -62,6 +272,15 @@ impl Default for CodecConfig {
             quant_bits: 0,
             strict_recon: true,
             color_transform: ColorTransform::YCoCgR,
+            predictor: Predictor::Left,
+            tile_size: 0,
+            bit_depth: 0,
+            max_abs_error: 0,
+            layout: ChannelLayout::Interleaved,
+            planes: [PlaneConfig::default(); 4],
+            alpha: AlphaConfig::default(),
+            run_mode: false,
+            palette: false,
         }
     }
 }
@@ -0,0 +1,112 @@
+#ifndef MOEQI_H
+#define MOEQI_H
+
+/* Generated by cbindgen from moeqi-ffi; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Outcome of a call; one value per `MoeqiError` variant, plus `Ok` and
+// `NullArgument`. `moeqi_last_error_message` has the details.
+typedef enum MoeqiStatus {
+  MOEQI_STATUS_OK = 0,
+  MOEQI_STATUS_INVALID_DATA = 1,
+  MOEQI_STATUS_FORMAT = 2,
+  MOEQI_STATUS_UNSUPPORTED = 3,
+  MOEQI_STATUS_EOF = 4,
+  MOEQI_STATUS_CORRUPT_CHUNK = 5,
+  MOEQI_STATUS_JSON = 6,
+  MOEQI_STATUS_IO = 7,
+  // A required pointer argument was null.
+  MOEQI_STATUS_NULL_ARGUMENT = 8,
+} MoeqiStatus;
+
+typedef struct MoeqiBuf {
+  uint8_t *ptr;
+  size_t len;
+  size_t cap;
+} MoeqiBuf;
+
+// A decoded image. Release `pixels` with `moeqi_free_buf`.
+typedef struct MoeqiImage {
+  uint32_t width;
+  uint32_t height;
+  // As in `moeqi_encode`.
+  uint8_t format_tag;
+  // Row-major, tightly packed; 16-bit samples little-endian.
+  struct MoeqiBuf pixels;
+} MoeqiImage;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Message of the last failed call on this thread, or null if none failed.
+// Valid until the next failing call on the same thread; don't free it.
+const char *moeqi_last_error_message(void);
+
+// Crate version as a static NUL-terminated string, e.g. `"0.1.0"`.
+const char *moeqi_version(void);
+
+// Release a buffer from `moeqi_encode[2]` or `moeqi_decode`; null is a no-op.
+void moeqi_free_buf(struct MoeqiBuf b);
+
+// Worker threads for coding tiled images: 0 = one per core, 1 = serial.
+// Process-wide; only takes effect when built with the `parallel` feature,
+// and only for a nonzero `tile_size` in cfg_json: untiled images (the
+// default) are coded on one thread.
+void moeqi_set_threads(uint32_t n);
+
+// Encode raw pixels (Gray8/RGB8/RGBA8 = 1/3/4, little-endian Gray16/RGB16/RGBA16
+// = 0xF1/0xF3/0xF4) into MOEQI2 container bytes. 9..15-bit data goes in the
+// 16-bit formats with `bit_depth` set in cfg_json.
+// cfg_json: UTF-8 JSON of CodecConfig, or null for default.
+// Returns an empty buffer on failure; see `moeqi_last_error_message`, or
+// call `moeqi_encode2` for a status.
+//
+// # Safety
+//
+// `pixels` must point to `pixels_len` readable bytes, and `cfg_json`, unless
+// null, to `cfg_json_len`.
+struct MoeqiBuf moeqi_encode(const uint8_t *pixels,
+                             size_t pixels_len,
+                             uint32_t width,
+                             uint32_t height,
+                             uint8_t format_tag,
+                             const uint8_t *cfg_json,
+                             size_t cfg_json_len);
+
+// `moeqi_encode`, writing the buffer to `*out` and returning a status. On
+// failure `*out` is left untouched; a null `pixels` or `out` gives
+// `NullArgument`.
+//
+// # Safety
+//
+// As for `moeqi_encode`, and `out` must point to a writable `MoeqiBuf`.
+enum MoeqiStatus moeqi_encode2(const uint8_t *pixels,
+                               size_t pixels_len,
+                               uint32_t width,
+                               uint32_t height,
+                               uint8_t format_tag,
+                               const uint8_t *cfg_json,
+                               size_t cfg_json_len,
+                               struct MoeqiBuf *out);
+
+// Decode a MOEQI2, MOEQI1 or MOEQIBIN file into `*out`. On failure `*out`
+// is left untouched and the status says why, `moeqi_last_error_message`
+// in more detail.
+//
+// # Safety
+//
+// `bytes` must point to `len` readable bytes and `out` to a writable
+// `MoeqiImage`.
+enum MoeqiStatus moeqi_decode(const uint8_t *bytes, size_t len, struct MoeqiImage *out);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* MOEQI_H */
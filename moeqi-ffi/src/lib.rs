@@ -1,7 +1,96 @@
 #![doc = include_str!("../README.md")]
 
+use std::cell::RefCell;
+use std::ffi::{c_char, CString};
+
 use moeqi_core::types::{CodecConfig, Image, PixelFormat};
-use moeqi_core::{format, Result};
+use moeqi_core::{format, moe, MoeqiError, Result};
+
+/// Outcome of a call; one value per `MoeqiError` variant, plus `Ok` and
+/// `NullArgument`. `moeqi_last_error_message` has the details.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoeqiStatus {
+    Ok = 0,
+    InvalidData = 1,
+    Format = 2,
+    Unsupported = 3,
+    Eof = 4,
+    CorruptChunk = 5,
+    Json = 6,
+    Io = 7,
+    /// A required pointer argument was null.
+    NullArgument = 8,
+}
+
+impl From<&MoeqiError> for MoeqiStatus {
+    fn from(e: &MoeqiError) -> Self {
+        match e {
+            MoeqiError::InvalidData(_) => MoeqiStatus::InvalidData,
+            MoeqiError::Format(_) => MoeqiStatus::Format,
+            MoeqiError::Unsupported(_) => MoeqiStatus::Unsupported,
+            MoeqiError::Eof => MoeqiStatus::Eof,
+            MoeqiError::CorruptChunk { .. } => MoeqiStatus::CorruptChunk,
+            MoeqiError::Json(_) => MoeqiStatus::Json,
+            MoeqiError::Io(_) => MoeqiStatus::Io,
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// Record `e` as this thread's last error and return its status.
+fn fail(e: MoeqiError) -> MoeqiStatus {
+    set_last_error(e.to_string());
+    MoeqiStatus::from(&e)
+}
+
+/// Message of the last failed call on this thread, or null if none failed.
+/// Valid until the next failing call on the same thread; don't free it.
+#[no_mangle]
+pub extern "C" fn moeqi_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|e| {
+        e.borrow()
+            .as_ref()
+            .map_or(core::ptr::null(), |m| m.as_ptr())
+    })
+}
+
+/// Crate version as a static NUL-terminated string, e.g. `"0.1.0"`.
+#[no_mangle]
+pub extern "C" fn moeqi_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+fn format_from_tag(tag: u8) -> Result<PixelFormat> {
+    Ok(match tag {
+        1 => PixelFormat::Gray8,
+        3 => PixelFormat::Rgb8,
+        4 => PixelFormat::Rgba8,
+        0xF1 => PixelFormat::Gray16,
+        0xF3 => PixelFormat::Rgb16,
+        0xF4 => PixelFormat::Rgba16,
+        _ => return Err(MoeqiError::InvalidData("bad format_tag")),
+    })
+}
+
+fn format_to_tag(format: PixelFormat) -> u8 {
+    match format {
+        PixelFormat::Gray8 => 1,
+        PixelFormat::Rgb8 => 3,
+        PixelFormat::Rgba8 => 4,
+        PixelFormat::Gray16 => 0xF1,
+        PixelFormat::Rgb16 => 0xF3,
+        PixelFormat::Rgba16 => 0xF4,
+    }
+}
 
 #[repr(C)]
 pub struct MoeqiBuf {
@@ -20,6 +109,7 @@ fn vec_to_buf(mut v: Vec<u8>) -> MoeqiBuf {
     out
 }
 
+/// Release a buffer from `moeqi_encode[2]` or `moeqi_decode`; null is a no-op.
 #[no_mangle]
 pub extern "C" fn moeqi_free_buf(b: MoeqiBuf) {
     if b.ptr.is_null() || b.cap == 0 {
@@ -28,10 +118,28 @@ pub extern "C" fn moeqi_free_buf(b: MoeqiBuf) {
     unsafe { drop(Vec::from_raw_parts(b.ptr, b.len, b.cap)) }
 }
 
-/// Encode raw pixels (Gray8/RGB8/RGBA8) into MOEQI1 container bytes.
+/// Worker threads for coding tiled images: 0 = one per core, 1 = serial.
+/// Process-wide; only takes effect when built with the `parallel` feature,
+/// and only for a nonzero `tile_size` in cfg_json: untiled images (the
+/// default) are coded on one thread.
+#[no_mangle]
+pub extern "C" fn moeqi_set_threads(n: u32) {
+    moeqi_core::parallel::set_threads(n as usize)
+}
+
+/// Encode raw pixels (Gray8/RGB8/RGBA8 = 1/3/4, little-endian Gray16/RGB16/RGBA16
+/// = 0xF1/0xF3/0xF4) into MOEQI2 container bytes. 9..15-bit data goes in the
+/// 16-bit formats with `bit_depth` set in cfg_json.
 /// cfg_json: UTF-8 JSON of CodecConfig, or null for default.
+/// Returns an empty buffer on failure; see `moeqi_last_error_message`, or
+/// call `moeqi_encode2` for a status.
+///
+/// # Safety
+///
+/// `pixels` must point to `pixels_len` readable bytes, and `cfg_json`, unless
+/// null, to `cfg_json_len`.
 #[no_mangle]
-pub extern "C" fn moeqi_encode(
+pub unsafe extern "C" fn moeqi_encode(
     pixels: *const u8,
     pixels_len: usize,
     width: u32,
@@ -40,34 +148,129 @@ pub extern "C" fn moeqi_encode(
     cfg_json: *const u8,
     cfg_json_len: usize,
 ) -> MoeqiBuf {
-    let r = (|| -> Result<Vec<u8>> {
-        let format = match format_tag {
-            1 => PixelFormat::Gray8,
-            3 => PixelFormat::Rgb8,
-            4 => PixelFormat::Rgba8,
-            _ => return Err(moeqi_core::MoeqiError::InvalidData("bad format_tag")),
-        };
+    let mut out = MoeqiBuf {
+        ptr: core::ptr::null_mut(),
+        len: 0,
+        cap: 0,
+    };
+    unsafe {
+        moeqi_encode2(
+            pixels,
+            pixels_len,
+            width,
+            height,
+            format_tag,
+            cfg_json,
+            cfg_json_len,
+            &mut out,
+        )
+    };
+    out
+}
 
+/// `moeqi_encode`, writing the buffer to `*out` and returning a status. On
+/// failure `*out` is left untouched; a null `pixels` or `out` gives
+/// `NullArgument`.
+///
+/// # Safety
+///
+/// As for `moeqi_encode`, and `out` must point to a writable `MoeqiBuf`.
+#[no_mangle]
+pub unsafe extern "C" fn moeqi_encode2(
+    pixels: *const u8,
+    pixels_len: usize,
+    width: u32,
+    height: u32,
+    format_tag: u8,
+    cfg_json: *const u8,
+    cfg_json_len: usize,
+    out: *mut MoeqiBuf,
+) -> MoeqiStatus {
+    if pixels.is_null() || out.is_null() {
+        set_last_error("pixels or out is null".into());
+        return MoeqiStatus::NullArgument;
+    }
+    let r = (|| -> Result<Vec<u8>> {
+        let format = format_from_tag(format_tag)?;
         let data = unsafe { core::slice::from_raw_parts(pixels, pixels_len) }.to_vec();
-        let img = Image { width, height, format, data };
+        let img = Image {
+            width,
+            height,
+            format,
+            data,
+        };
 
         let cfg = if cfg_json.is_null() || cfg_json_len == 0 {
             CodecConfig::default()
         } else {
-            let s = core::str::from_utf8(unsafe { core::slice::from_raw_parts(cfg_json, cfg_json_len) })
-                .map_err(|_| moeqi_core::MoeqiError::InvalidData("cfg_json not utf8"))?;
-            serde_json::from_str::<CodecConfig>(s).map_err(|_| moeqi_core::MoeqiError::InvalidData("cfg_json parse"))?
+            let s = core::str::from_utf8(unsafe {
+                core::slice::from_raw_parts(cfg_json, cfg_json_len)
+            })
+            .map_err(|_| MoeqiError::InvalidData("cfg_json not utf8"))?;
+            serde_json::from_str::<CodecConfig>(s)?
         };
 
         format::binary::encode(&img, cfg)
     })();
 
     match r {
-        Ok(v) => vec_to_buf(v),
-        Err(_) => MoeqiBuf { ptr: core::ptr::null_mut(), len: 0, cap: 0 },
+        Ok(v) => {
+            unsafe { out.write(vec_to_buf(v)) };
+            MoeqiStatus::Ok
+        }
+        Err(e) => fail(e),
     }
 }
 
+/// A decoded image. Release `pixels` with `moeqi_free_buf`.
+#[repr(C)]
+pub struct MoeqiImage {
+    pub width: u32,
+    pub height: u32,
+    /// As in `moeqi_encode`.
+    pub format_tag: u8,
+    /// Row-major, tightly packed; 16-bit samples little-endian.
+    pub pixels: MoeqiBuf,
+}
+
+/// Decode a MOEQI2, MOEQI1 or MOEQIBIN file into `*out`. On failure `*out`
+/// is left untouched and the status says why, `moeqi_last_error_message`
+/// in more detail.
+///
+/// # Safety
+///
+/// `bytes` must point to `len` readable bytes and `out` to a writable
+/// `MoeqiImage`.
+#[no_mangle]
+pub unsafe extern "C" fn moeqi_decode(
+    bytes: *const u8,
+    len: usize,
+    out: *mut MoeqiImage,
+) -> MoeqiStatus {
+    if bytes.is_null() || out.is_null() {
+        set_last_error("bytes or out is null".into());
+        return MoeqiStatus::NullArgument;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(bytes, len) };
+    let decoded = if bytes.starts_with(b"MOEQIBIN") {
+        moe::decode(bytes)
+    } else {
+        format::binary::decode(bytes).map(|(img, _)| img)
+    };
+    match decoded {
+        Ok(img) => {
+            let image = MoeqiImage {
+                width: img.width,
+                height: img.height,
+                format_tag: format_to_tag(img.format),
+                pixels: vec_to_buf(img.data),
+            };
+            unsafe { out.write(image) };
+            MoeqiStatus::Ok
+        }
+        Err(e) => fail(e),
+    }
+}
 
 #[cfg(feature = "wasm")]
 mod wasm_api {
@@ -75,7 +278,13 @@ mod wasm_api {
     use wasm_bindgen::prelude::*;
 
     #[wasm_bindgen]
-    pub fn encode_moeqi(pixels: &[u8], width: u32, height: u32, format_tag: u8, cfg_json: Option<String>) -> Vec<u8> {
+    pub fn encode_moeqi(
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        format_tag: u8,
+        cfg_json: Option<String>,
+    ) -> Vec<u8> {
         let cfg = cfg_json
             .and_then(|s| serde_json::from_str::<CodecConfig>(&s).ok())
             .unwrap_or_default();
@@ -87,7 +296,83 @@ mod wasm_api {
             _ => PixelFormat::Rgba8,
         };
 
-        let img = Image { width, height, format, data: pixels.to_vec() };
+        let img = Image {
+            width,
+            height,
+            format,
+            data: pixels.to_vec(),
+        };
         format::binary::encode(&img, cfg).unwrap_or_default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn decode_and_error_status() {
+        let pixels: Vec<u8> = (0..5 * 4 * 3).map(|i| (i * 11) as u8).collect();
+        let encoded =
+            unsafe { moeqi_encode(pixels.as_ptr(), pixels.len(), 5, 4, 3, core::ptr::null(), 0) };
+        let bytes = unsafe { core::slice::from_raw_parts(encoded.ptr, encoded.len) };
+
+        let mut out = core::mem::MaybeUninit::<MoeqiImage>::uninit();
+        assert_eq!(
+            unsafe { moeqi_decode(bytes.as_ptr(), bytes.len(), out.as_mut_ptr()) },
+            MoeqiStatus::Ok
+        );
+        let img = unsafe { out.assume_init() };
+        assert_eq!((img.width, img.height, img.format_tag), (5, 4, 3));
+        assert_eq!(
+            unsafe { core::slice::from_raw_parts(img.pixels.ptr, img.pixels.len) },
+            &pixels[..]
+        );
+        moeqi_free_buf(img.pixels);
+
+        let mut out = core::mem::MaybeUninit::<MoeqiImage>::uninit();
+        assert_eq!(
+            unsafe { moeqi_decode(bytes.as_ptr(), 8, out.as_mut_ptr()) },
+            MoeqiStatus::Eof
+        );
+        let message = unsafe { CStr::from_ptr(moeqi_last_error_message()) };
+        assert_eq!(message.to_str().unwrap(), "unexpected EOF");
+        assert_eq!(
+            unsafe { moeqi_decode(core::ptr::null(), 0, out.as_mut_ptr()) },
+            MoeqiStatus::NullArgument
+        );
+
+        let mut buf = MoeqiBuf {
+            ptr: core::ptr::null_mut(),
+            len: 0,
+            cap: 0,
+        };
+        let encode2 = |pixels: *const u8, format_tag, out| unsafe {
+            moeqi_encode2(pixels, 60, 5, 4, format_tag, core::ptr::null(), 0, out)
+        };
+        assert_eq!(
+            encode2(core::ptr::null(), 3, &mut buf),
+            MoeqiStatus::NullArgument
+        );
+        assert_eq!(
+            encode2(pixels.as_ptr(), 3, core::ptr::null_mut()),
+            MoeqiStatus::NullArgument
+        );
+        assert_eq!(
+            encode2(pixels.as_ptr(), 2, &mut buf),
+            MoeqiStatus::InvalidData
+        );
+        assert!(buf.ptr.is_null());
+        assert_eq!(encode2(pixels.as_ptr(), 3, &mut buf), MoeqiStatus::Ok);
+        assert_eq!(
+            unsafe { core::slice::from_raw_parts(buf.ptr, buf.len) },
+            bytes
+        );
+        moeqi_free_buf(buf);
+        moeqi_free_buf(encoded);
+
+        let version = unsafe { CStr::from_ptr(moeqi_version()) };
+        assert_eq!(version.to_str().unwrap(), env!("CARGO_PKG_VERSION"));
+    }
+}
@@ -0,0 +1,71 @@
+/* Round-trips an image through the C API.
+ *
+ * From moeqi-ffi/:
+ *
+ *   cargo build --release
+ *   cc tests/roundtrip.c -Iinclude -Ltarget/release -lmoeqi_ffi -o target/roundtrip
+ *   LD_LIBRARY_PATH=target/release ./target/roundtrip
+ */
+#include <stdio.h>
+#include <string.h>
+
+#include "moeqi.h"
+
+#define W 37
+#define H 23
+
+static int fail(const char *what) {
+    const char *message = moeqi_last_error_message();
+    fprintf(stderr, "%s: %s\n", what, message ? message : "(no message)");
+    return 1;
+}
+
+int main(void) {
+    uint8_t pixels[W * H * 3];
+    for (size_t i = 0; i < sizeof pixels; i++)
+        pixels[i] = (uint8_t)(i * 7 + i / (W * 3));
+
+    const char *cfg = "{\"codec\":\"PredictArith\",\"quant_bits\":0,\"strict_recon\":true,"
+                      "\"color_transform\":\"YCoCgR\",\"predictor\":\"Med\"}";
+    MoeqiBuf encoded = moeqi_encode(pixels, sizeof pixels, W, H, 3,
+                                    (const uint8_t *)cfg, strlen(cfg));
+    if (!encoded.ptr)
+        return fail("moeqi_encode");
+
+    MoeqiImage image;
+    MoeqiStatus status = moeqi_decode(encoded.ptr, encoded.len, &image);
+    if (status != MOEQI_STATUS_OK)
+        return fail("moeqi_decode");
+    if (image.width != W || image.height != H || image.format_tag != 3 ||
+        image.pixels.len != sizeof pixels || memcmp(image.pixels.ptr, pixels, sizeof pixels)) {
+        fprintf(stderr, "decoded image differs\n");
+        return 1;
+    }
+
+    /* errors come back as a status and a message */
+    status = moeqi_decode(encoded.ptr, 10, &image);
+    const char *message = moeqi_last_error_message();
+    if (status == MOEQI_STATUS_OK || !message || !*message) {
+        fprintf(stderr, "truncated file was accepted\n");
+        return 1;
+    }
+    /* the message only lives until the next failing call */
+    char truncated[256];
+    snprintf(truncated, sizeof truncated, "%s", message);
+    MoeqiBuf bad = {0};
+    if (moeqi_encode2(pixels, sizeof pixels, W, H, 2, NULL, 0, &bad) != MOEQI_STATUS_INVALID_DATA ||
+        bad.ptr || !strstr(moeqi_last_error_message(), "format_tag")) {
+        fprintf(stderr, "bad format tag was accepted\n");
+        return 1;
+    }
+    if (moeqi_encode2(NULL, 0, W, H, 3, NULL, 0, &bad) != MOEQI_STATUS_NULL_ARGUMENT) {
+        fprintf(stderr, "null pixels were not reported\n");
+        return 1;
+    }
+
+    printf("moeqi %s: %zu -> %zu bytes, round trip ok (truncated: status %d, \"%s\")\n",
+           moeqi_version(), sizeof pixels, encoded.len, (int)status, truncated);
+    moeqi_free_buf(image.pixels);
+    moeqi_free_buf(encoded);
+    return 0;
+}
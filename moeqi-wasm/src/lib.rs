@@ -1,77 +1,319 @@
 #![doc = include_str!("../README.md")]
 
+use js_sys::{Uint8Array, Uint8ClampedArray};
+use moeqi_core::format::binary;
+use moeqi_core::moe::{self, Bitstream, Codec};
 use moeqi_core::types::{CodecConfig, Image, PixelFormat};
+use moeqi_core::{MoeqiError, Result};
 use wasm_bindgen::prelude::*;
 
+/// A decoded image, pixels kept in wasm memory.
 #[wasm_bindgen]
 pub struct Decoded {
-    w: u32,
-    h: u32,
-    data: Vec<u8>,
+    image: Image,
+    /// Significant bits per sample, from the file's `bit_depth`.
+    depth: u8,
 }
 
-/*#[wasm_bindgen]
+#[wasm_bindgen]
 impl Decoded {
     #[wasm_bindgen(getter)]
-    pub fn w(&self) -> u32 { self.w }
+    pub fn width(&self) -> u32 {
+        self.image.width
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.image.height
+    }
+
+    /// `"Gray8"`, `"Rgb8"`, `"Rgba8"`, `"Gray16"`, `"Rgb16"` or `"Rgba16"`.
+    #[wasm_bindgen(getter)]
+    pub fn format(&self) -> String {
+        format_name(self.image.format).into()
+    }
+
+    /// Significant bits per sample: 8, or 9..16 for the 16-bit formats.
+    #[wasm_bindgen(getter, js_name = bitDepth)]
+    pub fn bit_depth(&self) -> u8 {
+        self.depth
+    }
+
+    /// View of the pixels (row-major, 16-bit samples little-endian) without
+    /// a copy. Only valid until this `Decoded` is freed or wasm memory grows;
+    /// copy it with `slice()` to keep it.
+    #[wasm_bindgen(getter)]
+    pub fn pixels(&self) -> Uint8Array {
+        // SAFETY: the view borrows `self.image.data`, as documented above.
+        unsafe { Uint8Array::view(&self.image.data) }
+    }
+
+    /// [`Decoded::pixels`] as a `Uint8ClampedArray`, for `new ImageData(..)`.
+    /// Only `Rgba8` images; see [`Decoded::to_rgba`].
+    #[wasm_bindgen(getter, js_name = clampedPixels)]
+    pub fn clamped_pixels(&self) -> std::result::Result<Uint8ClampedArray, JsError> {
+        if self.image.format != PixelFormat::Rgba8 {
+            return Err(JsError::new(
+                "clampedPixels needs an Rgba8 image; call toRgba()",
+            ));
+        }
+        // SAFETY: as for `pixels`
+        Ok(unsafe { Uint8ClampedArray::view(&self.image.data) })
+    }
+
+    /// A copy as `Rgba8`, ready for canvas: gray is replicated, alpha is
+    /// opaque where missing and 16-bit samples keep their top 8 significant
+    /// bits ([`Decoded::bit_depth`]).
+    #[wasm_bindgen(js_name = toRgba)]
+    pub fn to_rgba(&self) -> Decoded {
+        Decoded {
+            image: to_rgba(&self.image, self.depth),
+            depth: 8,
+        }
+    }
+}
+
+/// Decode a `MOEQI2` or legacy `MOEQI1` file.
+#[wasm_bindgen]
+pub fn decode(bytes: &[u8]) -> std::result::Result<Decoded, JsError> {
+    let (image, cfg) = binary::decode(bytes)?;
+    // the decoder has checked `bit_depth` against the format
+    let depth = image.format.bit_depth(cfg.bit_depth).unwrap_or(16);
+    Ok(Decoded { image, depth })
+}
+
+/// Encode raw pixels into a `MOEQI2` file. `format` is one of the
+/// [`Decoded::format`] names; `cfg_json` a JSON `CodecConfig`, or
+/// `undefined` for the default.
+#[wasm_bindgen]
+pub fn encode(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    format: &str,
+    cfg_json: Option<String>,
+) -> std::result::Result<Vec<u8>, JsError> {
+    Ok(encode_image(
+        pixels,
+        width,
+        height,
+        format,
+        cfg_json.as_deref(),
+    )?)
+}
+
+/// Header fields of a `MOEQIBIN` file, for debugging.
+#[wasm_bindgen]
+pub struct MqbHeader {
+    width: u16,
+    height: u16,
+    qstep: u16,
+    codec: Codec,
+    experts: u16,
+    payload_bytes: usize,
+}
+
+#[wasm_bindgen]
+impl MqbHeader {
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
     #[wasm_bindgen(getter)]
-    pub fn h(&self) -> u32 { self.h }
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Residual quantization step; 1 is lossless.
     #[wasm_bindgen(getter)]
-    pub fn data(&self) -> Vec<u8> { self.data.clone() } // simplest; can optimize with Uint8Array views
-}*/
-
-/*#[wasm_bindgen]
-pub fn parse_mqb(bytes: &[u8]) -> Result<(Image, crate::types::CodecConfig)> {
-    // Old name, new container.
-    format::binary::decode(bytes)
-}*/
-
-/*pub fn decode_luma(bytes: &[u8]) -> Result<(u32, u32, Vec<u8>)> {
-    let (img, _cfg) = format::binary::decode(bytes)?;
-    let (width, height) = (img.width, img.height);
-
-    fn rgb_to_luma(r: u8, g: u8, b: u8) -> u8 {
-        let (r, g, b) = (r as u16, g as u16, b as u16);
-        ((77 * r + 150 * g + 29 * b + 128) >> 8) as u8
-    }
-
-    let luma = match img.format {
-        PixelFormat::Gray8 => img.data,
-        PixelFormat::Rgb8 => img
-            .data
-            .chunks_exact(3)
-            .map(|p| rgb_to_luma(p[0], p[1], p[2]))
-            .collect(),
-        PixelFormat::Rgba8 => img
-            .data
-            .chunks_exact(4)
-            .map(|p| rgb_to_luma(p[0], p[1], p[2]))
-            .collect(),
+    pub fn qstep(&self) -> u16 {
+        self.qstep
+    }
+
+    /// Residual coder: `"Varint"` or `"Huff"`.
+    #[wasm_bindgen(getter)]
+    pub fn codec(&self) -> String {
+        format!("{:?}", self.codec)
+    }
+
+    /// Number of experts `E` in the model.
+    #[wasm_bindgen(getter)]
+    pub fn experts(&self) -> u16 {
+        self.experts
+    }
+
+    /// Size of the coded residuals.
+    #[wasm_bindgen(getter, js_name = payloadBytes)]
+    pub fn payload_bytes(&self) -> usize {
+        self.payload_bytes
+    }
+}
+
+impl From<&Bitstream> for MqbHeader {
+    fn from(bs: &Bitstream) -> Self {
+        MqbHeader {
+            width: bs.w,
+            height: bs.h,
+            qstep: bs.qstep,
+            codec: bs.codec,
+            experts: bs.model.e,
+            payload_bytes: bs.payload.len(),
+        }
+    }
+}
+
+/// Parse the header of a `MOEQIBIN` file without decoding it.
+#[wasm_bindgen(js_name = parseMqb)]
+pub fn parse_mqb(bytes: &[u8]) -> std::result::Result<MqbHeader, JsError> {
+    Ok(MqbHeader::from(&moe::parse_mqb(bytes)?))
+}
+
+/// Decode a `MOEQIBIN` file: `Gray8`, or `Rgba8` for canvas when `rgba` is
+/// true.
+#[wasm_bindgen(js_name = decodeMqb)]
+pub fn decode_mqb(bytes: &[u8], rgba: Option<bool>) -> std::result::Result<Decoded, JsError> {
+    let image = moe::decode(bytes)?;
+    Ok(Decoded {
+        image: match rgba {
+            Some(true) => to_rgba(&image, 8),
+            _ => image,
+        },
+        depth: 8,
+    })
+}
+
+fn encode_image(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    format: &str,
+    cfg_json: Option<&str>,
+) -> Result<Vec<u8>> {
+    let img = Image {
+        width,
+        height,
+        format: parse_format(format)?,
+        data: pixels.to_vec(),
+    };
+    if !img.validate() {
+        return Err(MoeqiError::InvalidData(
+            "pixels length doesn't match width, height and format",
+        ));
+    }
+    let cfg = match cfg_json {
+        Some(json) => serde_json::from_str(json)?,
+        None => CodecConfig::default(),
     };
+    binary::encode(&img, cfg)
+}
 
-    if (width as usize) * (height as usize) != luma.len() {
-        return Err(MoeqiError::InvalidData("luma length mismatch"));
-    }
-
-    Ok((width, height, luma))
-}*/
-
-/*#[wasm_bindgen]
-fn to_luma_bytes(img: &Image) -> Vec<u8> {
-    match img.format {
-        PixelFormat::Gray8 => img.data.clone(),
-        PixelFormat::Rgb8 => img.data.chunks_exact(3).map(|p| {
-            // integer luma approx: (0.299,0.587,0.114)
-            let r = p[0] as u16;
-            let g = p[1] as u16;
-            let b = p[2] as u16;
-            ((77*r + 150*g + 29*b + 128) >> 8) as u8
-        }).collect(),
-        PixelFormat::Rgba8 => img.data.chunks_exact(4).map(|p| {
-            let r = p[0] as u16;
-            let g = p[1] as u16;
-            let b = p[2] as u16;
-            ((77*r + 150*g + 29*b + 128) >> 8) as u8
-        }).collect(),
-    }
-}*/
+fn format_name(format: PixelFormat) -> &'static str {
+    match format {
+        PixelFormat::Gray8 => "Gray8",
+        PixelFormat::Rgb8 => "Rgb8",
+        PixelFormat::Rgba8 => "Rgba8",
+        PixelFormat::Gray16 => "Gray16",
+        PixelFormat::Rgb16 => "Rgb16",
+        PixelFormat::Rgba16 => "Rgba16",
+    }
+}
+
+fn parse_format(name: &str) -> Result<PixelFormat> {
+    Ok(match name {
+        "Gray8" => PixelFormat::Gray8,
+        "Rgb8" => PixelFormat::Rgb8,
+        "Rgba8" => PixelFormat::Rgba8,
+        "Gray16" => PixelFormat::Gray16,
+        "Rgb16" => PixelFormat::Rgb16,
+        "Rgba16" => PixelFormat::Rgba16,
+        _ => return Err(MoeqiError::InvalidData("unknown pixel format")),
+    })
+}
+
+/// `img` as `Rgba8`, keeping the top 8 of `depth` significant bits.
+fn to_rgba(img: &Image, depth: u8) -> Image {
+    let ch = img.format.channels();
+    let bytes = img.format.bytes_per_sample();
+    let shift = depth.saturating_sub(8);
+    // little-endian 16-bit samples
+    let sample = |px: &[u8], c: usize| match bytes {
+        1 => px[c],
+        _ => (u16::from_le_bytes([px[2 * c], px[2 * c + 1]]) >> shift).min(255) as u8,
+    };
+    let data = img
+        .data
+        .chunks_exact(ch * bytes)
+        .flat_map(|px| match ch {
+            1 => [sample(px, 0), sample(px, 0), sample(px, 0), 255],
+            3 => [sample(px, 0), sample(px, 1), sample(px, 2), 255],
+            _ => [sample(px, 0), sample(px, 1), sample(px, 2), sample(px, 3)],
+        })
+        .collect();
+    Image {
+        width: img.width,
+        height: img.height,
+        format: PixelFormat::Rgba8,
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_and_rgba() {
+        let pixels: Vec<u8> = (0..4 * 3 * 3).map(|i| (i * 13) as u8).collect();
+        let cfg = r#"{"codec":"PredictArith","quant_bits":0,"strict_recon":true,
+                      "color_transform":"None"}"#;
+        let bytes = encode_image(&pixels, 4, 3, "Rgb8", Some(cfg)).unwrap();
+        let (img, cfg) = binary::decode(&bytes).unwrap();
+        assert_eq!(img.data, pixels);
+        assert_eq!(format_name(img.format), "Rgb8");
+        assert_eq!(cfg.codec, moeqi_core::CodecKind::PredictArith);
+
+        let rgba = to_rgba(&img, 8);
+        assert_eq!(rgba.format, PixelFormat::Rgba8);
+        assert_eq!(rgba.data[..8], [0, 13, 26, 255, 39, 52, 65, 255]);
+        let wide = Image::from_samples(1, 1, PixelFormat::Gray16, &[0x1234]);
+        assert_eq!(to_rgba(&wide, 16).data, [0x12, 0x12, 0x12, 255]);
+        let ten_bit = Image::from_samples(1, 1, PixelFormat::Gray16, &[0x3ff]);
+        let cfg = CodecConfig {
+            bit_depth: 10,
+            ..CodecConfig::default()
+        };
+        let decoded = decode(&binary::encode(&ten_bit, cfg).unwrap())
+            .ok()
+            .unwrap();
+        assert_eq!(decoded.bit_depth(), 10);
+        assert_eq!(decoded.to_rgba().image.data, [255, 255, 255, 255]);
+
+        assert!(encode_image(&pixels, 4, 3, "Cmyk8", None).is_err());
+        assert!(encode_image(&pixels, 5, 3, "Rgb8", None).is_err());
+        assert!(matches!(
+            encode_image(&pixels, 4, 3, "Rgb8", Some("{")),
+            Err(MoeqiError::Json(_))
+        ));
+    }
+
+    #[test]
+    fn mqb_header() {
+        let img = Image {
+            width: 6,
+            height: 5,
+            format: PixelFormat::Gray8,
+            data: (0..30).map(|i| (i * 7) as u8).collect(),
+        };
+        let fit = moeqi_core::train::moe::MoeFitConfig {
+            experts: 2,
+            ..Default::default()
+        };
+        let model = moeqi_core::train::moe::fit_model(std::slice::from_ref(&img), fit).unwrap();
+        let bytes = moe::encode(&img, &model, 2, Codec::Huff).unwrap();
+        let header = MqbHeader::from(&moe::parse_mqb(&bytes).unwrap());
+        assert_eq!((header.width(), header.height(), header.qstep()), (6, 5, 2));
+        assert_eq!((header.codec().as_str(), header.experts()), ("Huff", 2));
+        assert!(header.payload_bytes() > 0);
+    }
+}
@@ -0,0 +1,60 @@
+//! Runs in Node: `wasm-pack test --node moeqi-wasm`.
+#![cfg(target_arch = "wasm32")]
+
+use moeqi_core::moe::{self, Codec};
+use moeqi_core::train::moe::{fit_model, MoeFitConfig};
+use moeqi_core::types::{Image, PixelFormat};
+use moeqi_wasm::{decode, decode_mqb, encode, parse_mqb};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+fn gray() -> Image {
+    Image {
+        width: 9,
+        height: 7,
+        format: PixelFormat::Gray8,
+        data: (0..63).map(|i| (i * 5 + i / 9 * 11) as u8).collect(),
+    }
+}
+
+#[wasm_bindgen_test]
+fn mqb_header_and_pixels() {
+    let img = gray();
+    let fit = MoeFitConfig {
+        experts: 3,
+        ..MoeFitConfig::default()
+    };
+    let model = fit_model(std::slice::from_ref(&img), fit).unwrap();
+    let bytes = moe::encode(&img, &model, 1, Codec::Varint).unwrap();
+
+    let header = parse_mqb(&bytes).map_err(JsValue::from).unwrap();
+    assert_eq!((header.width(), header.height()), (9, 7));
+    assert_eq!((header.qstep(), header.codec()), (1, "Varint".to_string()));
+    assert_eq!(header.experts(), 3);
+
+    let luma = decode_mqb(&bytes, None).map_err(JsValue::from).unwrap();
+    assert_eq!(luma.format(), "Gray8");
+    assert_eq!(luma.pixels().to_vec(), img.data);
+
+    let rgba = decode_mqb(&bytes, Some(true))
+        .map_err(JsValue::from)
+        .unwrap();
+    assert_eq!(rgba.format(), "Rgba8");
+    let clamped = rgba.clamped_pixels().map_err(JsValue::from).unwrap();
+    assert_eq!(clamped.length() as usize, 9 * 7 * 4);
+    assert_eq!(clamped.to_vec()[..8], [0, 0, 0, 255, 5, 5, 5, 255]);
+}
+
+#[wasm_bindgen_test]
+fn errors_carry_the_message() {
+    let err = decode_mqb(b"MOEQIBIN", None).err().unwrap();
+    let message = js_sys::Error::from(JsValue::from(err)).message();
+    assert!(!String::from(message).is_empty());
+
+    let bytes = encode(&gray().data, 9, 7, "Gray8", None)
+        .map_err(JsValue::from)
+        .unwrap();
+    let decoded = decode(&bytes).map_err(JsValue::from).unwrap();
+    assert_eq!(decoded.pixels().to_vec(), gray().data);
+    assert!(decoded.clamped_pixels().is_err());
+}
@@ -0,0 +1,195 @@
+//! [`image`] crate integration, with the `image` feature.
+
+use std::io::{Read, Write};
+
+use image::error::{
+    DecodingError, EncodingError, ImageFormatHint, UnsupportedError, UnsupportedErrorKind,
+};
+use image::{ColorType, ExtendedColorType, ImageDecoder, ImageEncoder, ImageError, ImageResult};
+
+use crate::{CodecConfig, Image, MoeqiError, PixelFormat};
+
+fn hint() -> ImageFormatHint {
+    ImageFormatHint::Name("MOEQI".into())
+}
+
+fn to_image_error(e: MoeqiError, decoding: bool) -> ImageError {
+    match e {
+        MoeqiError::Io(e) => ImageError::IoError(e),
+        MoeqiError::Unsupported(what) => {
+            ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+                hint(),
+                UnsupportedErrorKind::GenericFeature(what.into()),
+            ))
+        }
+        e if decoding => ImageError::Decoding(DecodingError::new(hint(), e)),
+        e => ImageError::Encoding(EncodingError::new(hint(), e)),
+    }
+}
+
+/// Reads `MOEQI2`, legacy `MOEQI1` and Gray8 `MOEQIBIN` files. The image is
+/// decoded up front by [`MoeqiDecoder::new`].
+pub struct MoeqiDecoder {
+    image: Image,
+}
+
+impl MoeqiDecoder {
+    pub fn new<R: Read>(mut input: R) -> ImageResult<Self> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let decoded = match bytes.starts_with(b"MOEQIBIN") {
+            true => moeqi_core::moe::decode(&bytes),
+            false => crate::decode(&bytes).map(|(img, _)| img),
+        };
+        let image = decoded.map_err(|e| to_image_error(e, true))?;
+        Ok(Self { image })
+    }
+}
+
+impl ImageDecoder for MoeqiDecoder {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.image.width, self.image.height)
+    }
+
+    fn color_type(&self) -> ColorType {
+        match self.image.format {
+            PixelFormat::Gray8 => ColorType::L8,
+            PixelFormat::Rgb8 => ColorType::Rgb8,
+            PixelFormat::Rgba8 => ColorType::Rgba8,
+            PixelFormat::Gray16 => ColorType::L16,
+            PixelFormat::Rgb16 => ColorType::Rgb16,
+            PixelFormat::Rgba16 => ColorType::Rgba16,
+        }
+    }
+
+    fn read_image(self, buf: &mut [u8]) -> ImageResult<()> {
+        let data = &self.image.data;
+        match self.image.format.bytes_per_sample() {
+            1 => buf.copy_from_slice(data),
+            // `image` wants native-endian samples
+            _ => {
+                for (out, s) in buf.chunks_exact_mut(2).zip(data.chunks_exact(2)) {
+                    out.copy_from_slice(&u16::from_le_bytes([s[0], s[1]]).to_ne_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
+
+/// Writes `MOEQI2` files with a given [`CodecConfig`].
+pub struct MoeqiEncoder<W: Write> {
+    out: W,
+    config: CodecConfig,
+}
+
+impl<W: Write> MoeqiEncoder<W> {
+    /// Lossless, with the default config.
+    pub fn new(out: W) -> Self {
+        Self::with_config(out, CodecConfig::default())
+    }
+
+    pub fn with_config(out: W, config: CodecConfig) -> Self {
+        Self { out, config }
+    }
+}
+
+impl<W: Write> ImageEncoder for MoeqiEncoder<W> {
+    fn write_image(
+        mut self,
+        buf: &[u8],
+        width: u32,
+        height: u32,
+        color_type: ExtendedColorType,
+    ) -> ImageResult<()> {
+        let format = match color_type {
+            ExtendedColorType::L8 => PixelFormat::Gray8,
+            ExtendedColorType::Rgb8 => PixelFormat::Rgb8,
+            ExtendedColorType::Rgba8 => PixelFormat::Rgba8,
+            ExtendedColorType::L16 => PixelFormat::Gray16,
+            ExtendedColorType::Rgb16 => PixelFormat::Rgb16,
+            ExtendedColorType::Rgba16 => PixelFormat::Rgba16,
+            other => {
+                return Err(ImageError::Unsupported(
+                    UnsupportedError::from_format_and_kind(
+                        hint(),
+                        UnsupportedErrorKind::Color(other),
+                    ),
+                ))
+            }
+        };
+        let data = match format.bytes_per_sample() {
+            1 => buf.to_vec(),
+            _ => buf
+                .chunks_exact(2)
+                .flat_map(|s| u16::from_ne_bytes([s[0], s[1]]).to_le_bytes())
+                .collect(),
+        };
+        let img = Image {
+            width,
+            height,
+            format,
+            data,
+        };
+        let bytes = crate::encode(&img, self.config).map_err(|e| to_image_error(e, false))?;
+        self.out.write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+/// Teach [`image::open`] and [`image::ImageReader`] the `.moeqi` extension
+/// and the MOEQI magic. Returns `false` if a `.moeqi` hook was already
+/// registered.
+pub fn register_image_hooks() -> bool {
+    let registered = image::hooks::register_decoding_hook(
+        "moeqi".into(),
+        Box::new(|r| Ok(Box::new(MoeqiDecoder::new(r)?))),
+    );
+    if registered {
+        image::hooks::register_format_detection_hook("moeqi".into(), b"MOEQI", None);
+    }
+    registered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::DynamicImage;
+
+    #[test]
+    fn image_crate_roundtrip() {
+        let img = Image {
+            width: 4,
+            height: 3,
+            format: PixelFormat::Rgb16,
+            data: (0..4 * 3 * 6).map(|i| (i * 29) as u8).collect(),
+        };
+        let dynamic = DynamicImage::try_from(img.clone()).unwrap();
+
+        let mut bytes = Vec::new();
+        dynamic
+            .write_with_encoder(MoeqiEncoder::new(&mut bytes))
+            .unwrap();
+        let decoded = DynamicImage::from_decoder(MoeqiDecoder::new(&bytes[..]).unwrap()).unwrap();
+        assert_eq!(decoded, dynamic);
+        assert_eq!(Image::try_from(decoded).unwrap(), img);
+
+        let path = std::env::temp_dir().join(format!("moeqi-{}.moeqi", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        register_image_hooks();
+        let opened = image::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(opened.unwrap(), dynamic);
+
+        assert!(MoeqiDecoder::new(&b"MOEQI2 truncated"[..]).is_err());
+        let gray_alpha = DynamicImage::new_luma_a8(2, 2);
+        assert!(matches!(
+            gray_alpha.write_with_encoder(MoeqiEncoder::new(Vec::new())),
+            Err(ImageError::Unsupported(_))
+        ));
+    }
+}
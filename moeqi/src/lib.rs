@@ -1,13 +1,51 @@
 #![doc = include_str!("../README.md")]
 
-pub use moeqi_core::{CodecConfig, CodecKind, ColorTransform, Image, MoeqiError, PixelFormat, Result};
+pub use moeqi_core::{
+    AlphaConfig, ChannelLayout, ChromaSiting, CodecConfig, CodecKind, ColorTransform, Image,
+    MoeqiError, PixelFormat, PlaneConfig, Predictor, Result, Subsampling, Upsampling,
+};
 
-/// Encode an [`Image`] into the `MOEQI1` binary container format.
+#[cfg(feature = "image")]
+mod image_io;
+#[cfg(feature = "image")]
+pub use image_io::{register_image_hooks, MoeqiDecoder, MoeqiEncoder};
+
+/// Encode an [`Image`] into the `MOEQI2` binary container format.
 pub fn encode(img: &Image, cfg: CodecConfig) -> Result<Vec<u8>> {
     moeqi_core::format::binary::encode(img, cfg)
 }
 
-/// Decode an `MOEQI1` binary container into an [`Image`] and the parsed [`CodecConfig`].
+/// Decode a `MOEQI2` (or legacy `MOEQI1`) binary container into an [`Image`] and the parsed [`CodecConfig`].
 pub fn decode(bytes: &[u8]) -> Result<(Image, CodecConfig)> {
     moeqi_core::format::binary::decode(bytes)
 }
+
+pub use moeqi_core::train::rate::{RateReport, TargetRate};
+
+/// Row-by-row coding over `std::io`, for images too large to hold in memory;
+/// see [`moeqi_core::format::stream`].
+pub use moeqi_core::format::stream::{Decoder, Encoder};
+
+/// Encode at the residual quantizer that meets `target`; see
+/// [`moeqi_core::train::rate::encode_with_target`].
+pub fn encode_with_target(
+    img: &Image,
+    cfg: CodecConfig,
+    target: TargetRate,
+) -> Result<(Vec<u8>, RateReport)> {
+    moeqi_core::train::rate::encode_with_target(img, cfg, target)
+}
+
+/// Decode only the `w`×`h` region at `(x, y)`. With a tiled config
+/// ([`CodecConfig::tile_size`]) only the overlapping tiles are decoded.
+pub fn decode_region(bytes: &[u8], x: u32, y: u32, w: u32, h: u32) -> Result<Image> {
+    moeqi_core::format::binary::decode_region(bytes, x, y, w, h)
+}
+
+/// Worker threads for coding tiled images (0 = one per core, 1 = serial).
+/// Process-wide; only takes effect with the `parallel` feature, and only on
+/// configs with a nonzero `tile_size`: untiled images (the default) are
+/// coded on one thread.
+pub fn set_threads(n: usize) {
+    moeqi_core::parallel::set_threads(n)
+}